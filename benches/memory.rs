@@ -0,0 +1,70 @@
+//! Measures how long a [`Memory`] takes to write and then read back a megabyte-sized working set
+//! with each of its storage backends, the way a program's data would be accessed over a long
+//! simulation.
+//!
+//! Run with `cargo bench --bench memory`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use custom_cpu::device::memory::storage::StorageKind;
+use custom_cpu::device::memory::Memory;
+use custom_cpu::device::{Device, PortValue};
+
+/// Width of the memory's addresses, enough for a few megabytes of 32-bit words.
+const ADDRESS_WIDTH: u32 = 20;
+
+/// Number of words written, and then read back.
+const WORDS: u32 = 1 << 18;
+
+/// Gives the address of each access, by its index.
+type Pattern = fn(u32) -> PortValue;
+
+/// Get the address of the `index`th access when running through memory a word at a time, as an
+/// instruction fetch or array copy would.
+fn sequential(index: u32) -> PortValue {
+    PortValue::from(index)
+}
+
+/// Get the address of the `index`th access when striding through the whole address space, so
+/// that accesses jump around rather than going to the next word.
+fn scattered(index: u32) -> PortValue {
+    PortValue::from(index.wrapping_mul(0x9e37) & ((1 << ADDRESS_WIDTH) - 1))
+}
+
+/// Time writing `WORDS` words into a new memory with the given backend, and then reading them all
+/// back, at the addresses given by `address`. Returns the mean time per write and per read.
+fn time_accesses(kind: StorageKind, address: Pattern) -> (Duration, Duration) {
+    let mut memory = Memory::with_storage(ADDRESS_WIDTH, 32, kind).unwrap();
+
+    let start = Instant::now();
+    for index in 0..WORDS {
+        memory.provide_port_value(Memory::WE, PortValue::from(1u32)).unwrap();
+        memory.provide_port_value(Memory::WA, address(index)).unwrap();
+        memory.provide_port_value(Memory::WV, PortValue::from(index)).unwrap();
+        memory.tick().unwrap();
+    }
+    let writes = start.elapsed() / WORDS;
+
+    let start = Instant::now();
+    for index in 0..WORDS {
+        memory.provide_port_value(Memory::RA, address(index)).unwrap();
+        memory.provide_port_value(Memory::WE, PortValue::ZERO).unwrap();
+        black_box(memory.get_port_value(Memory::RV)).unwrap();
+        memory.tick().unwrap();
+    }
+    let reads = start.elapsed() / WORDS;
+
+    (writes, reads)
+}
+
+fn main() {
+    println!("{WORDS} words in a memory with {ADDRESS_WIDTH}-bit addresses, mean per access:");
+    let patterns: [(&str, Pattern); 2] = [("sequential", sequential), ("scattered", scattered)];
+    for (pattern, address) in patterns {
+        println!("  {pattern} addresses:");
+        for kind in [StorageKind::Sparse, StorageKind::Dense, StorageKind::Paged] {
+            let (writes, reads) = time_accesses(kind, address);
+            println!("    {:<8} write: {writes:>8?}  read: {reads:>8?}", format!("{kind:?}"));
+        }
+    }
+}
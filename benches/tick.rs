@@ -0,0 +1,87 @@
+//! Measures how long [`Controller::tick`] takes on a large generated circuit, comparing ticks that
+//! reuse the compiled evaluation schedule against ticks that have to recompile it first because
+//! the circuit was changed.
+//!
+//! Run with `cargo bench --bench tick`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use custom_cpu::controller::Controller;
+use custom_cpu::device::debug::constant::Constant;
+use custom_cpu::device::memory::Memory;
+use custom_cpu::device::PortValue;
+
+/// Number of memories chained together in the generated circuit.
+const CHAIN_LENGTH: usize = 1000;
+
+/// Number of ticks to time for each measurement.
+const TICKS: u32 = 100;
+
+/// Build a circuit of `CHAIN_LENGTH` memories, where each memory's read address is driven by the
+/// read value of the previous one, and all the write ports are driven by shared constants.
+fn build_circuit() -> Controller {
+    let mut controller = Controller::new();
+    for (id, width, value) in [("ra", 32, 0u32), ("we", 1, 1), ("wa", 32, 0), ("wv", 32, 0)] {
+        let constant = Constant::with_width("qq".to_owned(), width, PortValue::from(value));
+        let constant = constant.unwrap();
+        controller.add_device(id.to_owned(), Box::new(constant)).unwrap();
+    }
+
+    for idx in 0..CHAIN_LENGTH {
+        let id = format!("Memory {idx}");
+        controller.add_device(id.clone(), Box::new(Memory::new())).unwrap();
+        let (ra_device, ra_port) = match idx {
+            0 => ("ra".to_owned(), "qq".to_owned()),
+            _ => (format!("Memory {}", idx - 1), "rv".to_owned()),
+        };
+        controller.add_connection(&ra_device, &ra_port, &id, &"ra".to_owned()).unwrap();
+        for port in ["we", "wa", "wv"] {
+            controller.add_connection(
+                &port.to_owned(), &"qq".to_owned(),
+                &id, &port.to_owned(),
+            ).unwrap();
+        }
+    }
+
+    controller
+}
+
+/// Time `TICKS` ticks, calling `between_ticks` (untimed) before each one.
+fn time_ticks(controller: &mut Controller, mut between_ticks: impl FnMut(&mut Controller))
+    -> Duration
+{
+    let mut total = Duration::ZERO;
+    for _ in 0..TICKS {
+        between_ticks(controller);
+        let start = Instant::now();
+        black_box(controller.tick()).unwrap();
+        total += start.elapsed();
+    }
+    total / TICKS
+}
+
+fn main() {
+    let mut controller = build_circuit();
+
+    // Changing the circuit throws away the compiled schedule, so every tick has to recompile it
+    let last = format!("Memory {}", CHAIN_LENGTH - 1);
+    let recompiling = time_ticks(&mut controller, |controller| {
+        controller.remove_connection(
+            &"wv".to_owned(), &"qq".to_owned(),
+            &last, &"wv".to_owned(),
+        ).unwrap();
+        controller.add_connection(
+            &"wv".to_owned(), &"qq".to_owned(),
+            &last, &"wv".to_owned(),
+        ).unwrap();
+    });
+
+    // Leaving the circuit alone lets every tick reuse the compiled schedule
+    let cached = time_ticks(&mut controller, |_| {});
+
+    println!("circuit of {CHAIN_LENGTH} chained memories, mean of {TICKS} ticks:");
+    println!("  recompiling schedule every tick: {recompiling:?}");
+    println!("  reusing compiled schedule:       {cached:?}");
+    println!("  speedup:                         {:.2}x",
+        recompiling.as_secs_f64() / cached.as_secs_f64());
+}
@@ -1,29 +1,246 @@
 use std::collections::HashMap;
-use crate::device::{Device, PortIdentifier, PortValue};
-use petgraph::graph::{DiGraph, NodeIndex};
-use petgraph::acyclic::{Acyclic};
+use std::ops::Range;
+use std::error::Error;
+use std::fmt;
+use crate::device::{
+    Device, DeviceError, PortDescriptor, PortDirection, PortId, PortIdentifier, PortValue,
+};
+use petgraph::algo::{astar, has_path_connecting};
+use petgraph::graph::NodeIndex;
+use petgraph::stable_graph::{EdgeReference, StableDiGraph};
+use petgraph::visit::EdgeFiltered;
 use petgraph::Direction;
+use schedule::{Order, Schedule, Step, StepKind};
+
+mod schedule;
+mod state;
+
+pub use state::{CircuitState, ConnectionState, PortState};
 
 pub type DeviceIdentifier = String;
 
+/// A user-defined connection from an output port of one device to an input port of another.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Connection {
+    pub from: (DeviceIdentifier, PortIdentifier),
+    pub to: (DeviceIdentifier, PortIdentifier),
+}
+
 #[derive(Clone)]
 enum EdgeType {
     Internal,
     External,
+
+    /// An internal dependency that only applies for some values of the device's inputs (see
+    /// [`Device::has_dynamic_dependencies`]).
+    Conditional,
+}
+
+/// Whether an edge always applies, rather than only for some values of a device's inputs.
+fn is_unconditional(edge: EdgeReference<EdgeType>) -> bool {
+    !matches!(edge.weight(), EdgeType::Conditional)
+}
+
+/// Represents an error that can be thrown by methods on [`Controller`].
+///
+/// Wherever possible, the offending device and port are named, so that a failure can be traced
+/// back to the part of the circuit that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControllerError {
+    /// The given device is not known by this controller.
+    UnknownDevice { device: DeviceIdentifier },
+
+    /// A device was added with the same identifier as a device already known by this controller.
+    DuplicateDevice { device: DeviceIdentifier },
+
+    /// The given device is known by this controller, but has no port with the given identifier.
+    UnknownPort { device: DeviceIdentifier, port: PortIdentifier },
+
+    /// Adding a connection was rejected because it would introduce a cycle into the dependency
+    /// graph. Cycles through dependencies that only apply for some input values (see
+    /// [`Device::has_dynamic_dependencies`]) are allowed, and are checked for during each tick
+    /// instead ([`ControllerError::CombinationalLoop`]). Never returned once feedback is allowed
+    /// (see [`Controller::allow_feedback`]).
+    ///
+    /// `path` lists every port on the loop, starting and ending with the "from" port of the
+    /// rejected connection; the final step of the path is the rejected connection itself.
+    Cycle { path: Vec<(DeviceIdentifier, PortIdentifier)> },
+
+    /// A connection was attempted from a port that is not an output port.
+    NotAnOutputPort { device: DeviceIdentifier, port: PortIdentifier },
+
+    /// A connection was attempted to a port that is not an input port.
+    NotAnInputPort { device: DeviceIdentifier, port: PortIdentifier },
+
+    /// A connection was attempted to an input port that is already driven by another output port.
+    /// An input port can only have one driver, unless the controller uses four-state logic (see
+    /// [`Controller::new_four_state`]), in which case this is only returned for a connection
+    /// that already exists.
+    AlreadyDriven {
+        device: DeviceIdentifier,
+        port: PortIdentifier,
+        driver: (DeviceIdentifier, PortIdentifier),
+    },
+
+    /// A connection was attempted between two ports of different widths.
+    WidthMismatch {
+        from: (DeviceIdentifier, PortIdentifier),
+        from_width: u32,
+        to: (DeviceIdentifier, PortIdentifier),
+        to_width: u32,
+    },
+
+    /// A connection was asked to be removed, but no such connection exists.
+    NoSuchConnection { connection: Connection },
+
+    /// A tick could not be performed because a device needed the value of an input port that has
+    /// nothing connected to it. This is never returned by a controller using four-state logic,
+    /// where such a port reads as Z.
+    UnconnectedInput { device: DeviceIdentifier, port: PortIdentifier },
+
+    /// A tick could not be performed because, given the values of this tick, the value of a port
+    /// depends on itself.
+    ///
+    /// `path` lists every port on the loop in the direction values flow, starting and ending with
+    /// the same port.
+    CombinationalLoop { path: Vec<(DeviceIdentifier, PortIdentifier)> },
+
+    /// A tick could not be performed because the values around a feedback loop did not settle
+    /// within the allowed number of iterations (see [`Controller::allow_feedback`]), as with a
+    /// ring oscillator.
+    ///
+    /// `ports` lists every port on the loop.
+    Oscillation { ports: Vec<(DeviceIdentifier, PortIdentifier)>, iterations: usize },
+
+    /// A device managed by this controller returned an error.
+    Device { device: DeviceIdentifier, source: DeviceError },
+}
+
+impl fmt::Display for ControllerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControllerError::UnknownDevice { device } => write!(f, "unknown device `{device}`"),
+            ControllerError::DuplicateDevice { device } =>
+                write!(f, "there is already a device called `{device}`"),
+            ControllerError::UnknownPort { device, port } =>
+                write!(f, "device `{device}` has no port `{port}`"),
+            ControllerError::Cycle { path } =>
+                write!(f, "connection would create a cycle: {}", describe_path(path)),
+            ControllerError::CombinationalLoop { path } =>
+                write!(f, "combinational loop: {}", describe_path(path)),
+            ControllerError::Oscillation { ports, iterations } => {
+                let ports: Vec<String> = ports.iter()
+                    .map(|(device, port)| format!("{device}.{port}"))
+                    .collect();
+                write!(f, "feedback loop did not settle within {iterations} iterations: {}",
+                    ports.join(", "))
+            }
+            ControllerError::NotAnOutputPort { device, port } =>
+                write!(f, "port `{port}` on device `{device}` is not an output port"),
+            ControllerError::NotAnInputPort { device, port } =>
+                write!(f, "port `{port}` on device `{device}` is not an input port"),
+            ControllerError::AlreadyDriven { device, port, driver: (driver_device, driver_port) } =>
+                write!(f, "input port `{port}` on device `{device}` is already driven by \
+                    `{driver_device}.{driver_port}`"),
+            ControllerError::WidthMismatch { from, from_width, to, to_width } =>
+                write!(f, "cannot connect {from_width}-bit port `{}.{}` to {to_width}-bit port \
+                    `{}.{}`", from.0, from.1, to.0, to.1),
+            ControllerError::NoSuchConnection { connection } =>
+                write!(f, "there is no connection from `{}.{}` to `{}.{}`",
+                    connection.from.0, connection.from.1, connection.to.0, connection.to.1),
+            ControllerError::UnconnectedInput { device, port } =>
+                write!(f, "input port `{port}` on device `{device}` is not connected"),
+            ControllerError::Device { device, source } =>
+                write!(f, "device `{device}` failed: {source}"),
+        }
+    }
+}
+
+/// Describe a path through the circuit as the ports along it, joined by arrows.
+fn describe_path(path: &[(DeviceIdentifier, PortIdentifier)]) -> String {
+    let steps: Vec<String> = path.iter()
+        .map(|(device, port)| format!("{device}.{port}"))
+        .collect();
+    steps.join(" -> ")
+}
+
+impl Error for ControllerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ControllerError::Device { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A handle to a device managed by a [`Controller`], returned by [`Controller::add_device`].
+///
+/// Handles are never reused, so a handle to a removed device stays invalid rather than quietly
+/// referring to whichever device is added next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceId(usize);
+
+/// A device managed by a [`Controller`], along with what the controller knows about it.
+struct DeviceEntry {
+    name: DeviceIdentifier,
+    device: Box<dyn Device>,
+
+    /// The node in the dependency graph of each of the device's ports, indexed by [`PortId`].
+    nodes: Vec<NodeIndex>,
+}
+
+/// Working space for resolving ports during a tick, kept by a [`Controller`] between ticks so that
+/// ticking does not need to allocate. It is cleared at the start of every tick.
+#[derive(Default)]
+struct Scratch {
+    /// Whether each port has been resolved yet, indexed by the port's node.
+    resolved: Vec<bool>,
+
+    /// Whether each port is waiting on the value of another while resolving on demand, indexed
+    /// by the port's node.
+    in_progress: Vec<bool>,
+
+    /// Ports being resolved on demand, each waiting on the value of the one above it.
+    stack: Vec<NodeIndex>,
+
+    /// The inputs an output of a device with dynamic dependencies currently depends on.
+    deps: Vec<PortId>,
+
+    /// Whether each port is on a feedback loop that has been or is being resolved, indexed by the
+    /// port's node.
+    on_loop: Vec<bool>,
+
+    /// The devices with ports on the feedback loop being resolved.
+    loop_devices: Vec<DeviceId>,
 }
 
-#[derive(Debug)]
-pub struct ControllerError;
+impl Scratch {
+    /// Clear everything, ready to resolve the ports of a circuit whose nodes are all below
+    /// `node_bound`.
+    fn reset(&mut self, node_bound: usize) {
+        for flags in [&mut self.resolved, &mut self.in_progress, &mut self.on_loop] {
+            flags.clear();
+            flags.resize(node_bound, false);
+        }
+        self.stack.clear();
+        self.deps.clear();
+        self.loop_devices.clear();
+    }
+}
 
 /// Holds devices and the connections between them, and facilitates whole-circuit ticks and
 /// information flow.
+///
+/// Devices and ports are named with [`DeviceIdentifier`]s and [`PortIdentifier`]s when building a
+/// circuit, but internally (and in the hot path of [`Controller::tick`]) they are referred to by
+/// [`DeviceId`] and [`PortId`] handles, so that a tick does not need to hash or allocate strings.
 pub struct Controller {
-    /// Holds all the devices managed by this `Controller`, mapped by their identifier.
-    devices: HashMap<DeviceIdentifier, Box<dyn Device>>,
-    
-    /// Holds references to all the ports known by this `Controller`. The stored value is the index
-    /// of the port's node in the dependency graph.
-    ports: HashMap<(DeviceIdentifier, PortIdentifier), NodeIndex>,
+    /// Holds all the devices managed by this `Controller`, indexed by their [`DeviceId`]. Removed
+    /// devices leave a `None` behind, so that the handles of other devices stay valid.
+    devices: Vec<Option<DeviceEntry>>,
+
+    /// Maps the identifier of every device managed by this `Controller` to its handle.
+    device_ids: HashMap<DeviceIdentifier, DeviceId>,
     
     /// Holds the dependency graph of all the ports on all the devices managed by this `Controller`.
     /// 
@@ -32,186 +249,828 @@ pub struct Controller {
     ///   an input port of another; or
     /// * _internal_, representing an intra-device dependency where the output port of a device
     ///   depends on a particular input port _on the same device_.
-    dependencies: DiGraph<(DeviceIdentifier, PortIdentifier), EdgeType>,
+    dependencies: StableDiGraph<(DeviceId, PortId), EdgeType>,
+
+    /// Holds the order in which to resolve ports during a tick, compiled from `dependencies`.
+    ///
+    /// This is `None` if the topology has changed since it was last compiled, in which case it
+    /// is recompiled on the next tick.
+    schedule: Option<Schedule>,
+
+    /// Holds the value of every port as of the most recent successful tick, indexed by the port's
+    /// node in the dependency graph.
+    values: Vec<Option<PortValue>>,
+
+    /// Scratch space the same shape as `values`, which a tick resolves into before swapping it
+    /// with `values` on success. Kept around so that ticking does not need to allocate.
+    next_values: Vec<Option<PortValue>>,
+
+    /// Working space for resolving ports during a tick.
+    scratch: Scratch,
+
+    /// Whether this controller uses four-state logic. See [`Controller::new_four_state`].
+    four_state: bool,
+
+    /// The most times to go round a feedback loop in a tick, if feedback is allowed. See
+    /// [`Controller::allow_feedback`].
+    max_feedback_iterations: Option<usize>,
+
+    /// How many ticks have succeeded so far.
+    ticks: u64,
 }
 
 impl Controller {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Controller {
         Controller {
-            devices: HashMap::new(),
-            ports: HashMap::new(),
-            dependencies: DiGraph::new(),
+            devices: Vec::new(),
+            device_ids: HashMap::new(),
+            dependencies: StableDiGraph::new(),
+            schedule: None,
+            values: Vec::new(),
+            next_values: Vec::new(),
+            scratch: Scratch::default(),
+            four_state: false,
+            max_feedback_iterations: None,
+            ticks: 0,
+        }
+    }
+
+    /// Create a controller that uses four-state logic, as an HDL simulator would.
+    ///
+    /// Rather than failing to tick, an input port with nothing connected to it reads as all
+    /// [`LogicBit::Z`](crate::device::value::LogicBit::Z). An input port can also be driven by
+    /// more than one output port, like a tri-state bus: the values driven are combined with
+    /// [`PortValue::resolve`], so any bits driven to conflicting values read as
+    /// [`LogicBit::X`](crate::device::value::LogicBit::X).
+    pub fn new_four_state() -> Controller {
+        Controller { four_state: true, ..Controller::new() }
+    }
+
+    /// Allow connections that form combinational feedback loops, such as the cross-coupled gates
+    /// of an SR latch.
+    ///
+    /// During a tick, the ports on each loop start with the values they had after the previous
+    /// tick (so that latches hold their state), and the loop is gone round until its values stop
+    /// changing. If they are still changing after `max_iterations` goes, the tick fails with
+    /// [`ControllerError::Oscillation`].
+    ///
+    /// Once allowed, feedback can't be disallowed again, as the circuit may already contain loops.
+    /// Calling this again just changes `max_iterations`.
+    pub fn allow_feedback(&mut self, max_iterations: usize) {
+        self.max_feedback_iterations = Some(max_iterations);
+        self.schedule = None;
+    }
+
+    /// Add a device to this [`Controller`] under the given identifier, returning its handle.
+    ///
+    /// Fails if there is already a device with the same identifier
+    /// ([`ControllerError::DuplicateDevice`]).
+    pub fn add_device(&mut self, id: DeviceIdentifier, device: Box<dyn Device>)
+        -> Result<DeviceId, ControllerError>
+    {
+        if self.device_ids.contains_key(&id) {
+            return Err(ControllerError::DuplicateDevice { device: id });
         }
+
+        let handle = DeviceId(self.devices.len());
+        let nodes = self.add_port_nodes(handle, device.as_ref());
+
+        // Add the device
+        self.device_ids.insert(id.clone(), handle);
+        self.devices.push(Some(DeviceEntry { name: id, device, nodes }));
+        self.schedule = None;
+
+        Ok(handle)
     }
-    pub fn add_device(&mut self, id: DeviceIdentifier, device: Box<dyn Device>) {
+
+    /// Add a node to the dependency graph for every port on the given device, along with edges for
+    /// its internal dependencies, returning the nodes indexed by [`PortId`].
+    fn add_port_nodes(&mut self, handle: DeviceId, device: &dyn Device) -> Vec<NodeIndex> {
         // Add the ports in this device to the graph
-        for input_port in device.get_input_ports().iter() {
-            let index = self.dependencies.add_node(
-                (id.clone(), input_port.clone())
-            );
-            self.ports.insert((id.clone(), input_port.clone()), index);
+        let nodes: Vec<NodeIndex> = (0..device.get_ports().len())
+            .map(|port| self.dependencies.add_node((handle, PortId(port))))
+            .collect();
 
+        // Nodes of removed ports get reused, so make sure new ports don't inherit their values
+        for node in nodes.iter() {
+            if let Some(value) = self.values.get_mut(node.index()) {
+                *value = None;
+            }
         }
-        for output_port in device.get_output_ports().iter() {
-            let index = self.dependencies.add_node(
-                (id.clone(), output_port.clone())
-            );
-            self.ports.insert((id.clone(), output_port.clone()), index);
-
-            // Add the internal dependencies as edges
-            // Don't need to worry about cycles being introduced, as the nodes have no other
-            // connections at this stage
-            let port_deps = device.get_output_dependencies(output_port)
-                .expect("Port identifiers returned by device's `get_output_ports()` method \
-                should always be valid inputs to same device's `get_output_dependencies()` method");
+
+        // Add the internal dependencies as edges
+        // Don't need to worry about cycles being introduced, as the nodes have no other
+        // connections at this stage
+        for (port, descriptor) in device.get_ports().iter().enumerate() {
+            if descriptor.direction != PortDirection::Output {
+                continue;
+            }
+            let port_deps = device.get_output_dependencies(PortId(port))
+                .expect("Output ports returned by device's `get_ports()` method should always \
+                be valid inputs to same device's `get_output_dependencies()` method");
+
+            // With dynamic dependencies, the inputs needed before anything has been provided are
+            // needed whatever the values, and only the rest are conditional
+            let mut unconditional_deps = Vec::new();
+            match device.has_dynamic_dependencies() {
+                true => device
+                    .get_current_output_dependencies(PortId(port), &mut unconditional_deps)
+                    .expect("Output ports returned by device's `get_ports()` method should \
+                    always be valid inputs to same device's \
+                    `get_current_output_dependencies()` method"),
+                false => unconditional_deps.extend(port_deps.iter().copied()),
+            }
             for dep in port_deps.iter() {
-                let dep_idx = self.ports[&(id.clone(), dep.clone())];
-                self.dependencies.add_edge(dep_idx, index, EdgeType::Internal);
+                let edge_type = match unconditional_deps.contains(dep) {
+                    true => EdgeType::Internal,
+                    false => EdgeType::Conditional,
+                };
+                self.dependencies.add_edge(nodes[dep.0], nodes[port], edge_type);
             }
         }
 
-        // Add the device
-        // Do this after the ports because it moves the device into the HashMap
-        self.devices.insert(id, device);
+        nodes
     }
 
     /// Attempt to add a connection between two ports known by this [`Controller`].
     /// Fails (returns `Err`) if:
     /// * Any of the devices or ports are not known by the controller
-    /// * TODO: The "from" port is not an output port
-    /// * TODO: The "to" port is not an input port
+    ///   ([`ControllerError::UnknownDevice`] or [`ControllerError::UnknownPort`])
+    /// * The "from" port is not an output port ([`ControllerError::NotAnOutputPort`])
+    /// * The "to" port is not an input port ([`ControllerError::NotAnInputPort`])
+    /// * The "to" port already has a connection driving it ([`ControllerError::AlreadyDriven`]). A
+    ///   controller using four-state logic only rejects the same connection being added twice.
+    /// * The ports are different widths ([`ControllerError::WidthMismatch`])
     /// * Adding the connection would result in the dependency graph containing a cycle
+    ///   ([`ControllerError::Cycle`]), unless feedback is allowed (see
+    ///   [`Controller::allow_feedback`])
     pub fn add_connection(
         &mut self,
         from_device: &DeviceIdentifier, from_port: &PortIdentifier,
         to_device: &DeviceIdentifier, to_port: &PortIdentifier,
     ) -> Result<(), ControllerError> {
-        // Get indices of ports
-        let from_idx = match self.ports.get(&(from_device.clone(), from_port.clone())) {
-            None => return Err(ControllerError),
-            Some(idx) => *idx,
-        };
-        let to_idx = match self.ports.get(&(to_device.clone(), to_port.clone())) {
-            None => return Err(ControllerError),
-            Some(idx) => *idx,
-        };
+        // Get handles of ports
+        let from = self.port_handle(from_device, from_port)?;
+        let to = self.port_handle(to_device, to_port)?;
+
+        // Check the connection goes from an output to an input
+        let from_descriptor = self.port_descriptor(from);
+        let to_descriptor = self.port_descriptor(to);
+        if from_descriptor.direction != PortDirection::Output {
+            return Err(ControllerError::NotAnOutputPort {
+                device: from_device.clone(),
+                port: from_port.clone(),
+            });
+        }
+        if to_descriptor.direction != PortDirection::Input {
+            return Err(ControllerError::NotAnInputPort {
+                device: to_device.clone(),
+                port: to_port.clone(),
+            });
+        }
+
+        // Check the ports are the same width, so no bits go missing or appear from nowhere
+        if from_descriptor.width != to_descriptor.width {
+            return Err(ControllerError::WidthMismatch {
+                from: (from_device.clone(), from_port.clone()),
+                from_width: from_descriptor.width,
+                to: (to_device.clone(), to_port.clone()),
+                to_width: to_descriptor.width,
+            });
+        }
 
-        // Wrap graph to enforce acyclic constraint
-        // TODO: improve the memory usage of this, I don't like the clone
-        let mut acyclic = Acyclic::try_from_graph(self.dependencies.clone())
-            .expect("`self.dependencies` should never contain cycles, so should always be \
-            wrappable in the `Acyclic` wrapper");
-        match acyclic.try_add_edge(from_idx, to_idx, EdgeType::External) {
-            Err(_) => return Err(ControllerError),
-            _ => {},
+        // Input ports can only have one driver (or one of each driver, with four-state logic).
+        // Only external edges ever arrive at an input port, so any incoming neighbour is an
+        // existing driver.
+        let from_idx = self.node(from);
+        let to_idx = self.node(to);
+        let driver_idx = match self.four_state {
+            true => self.dependencies.contains_edge(from_idx, to_idx).then_some(from_idx),
+            false => self.dependencies.neighbors_directed(to_idx, Direction::Incoming).next(),
         };
-        self.dependencies = acyclic.into_inner();
+        if let Some(driver_idx) = driver_idx {
+            return Err(ControllerError::AlreadyDriven {
+                device: to_device.clone(),
+                port: to_port.clone(),
+                driver: self.port_names(self.dependencies[driver_idx]),
+            });
+        }
+
+        // Enforce acyclic constraint: the new edge closes a loop exactly when there is already a
+        // path from the "to" port back to the "from" port. Loops through conditional dependencies
+        // might never actually happen, so are left for ticks to detect.
+        let unconditional = EdgeFiltered::from_fn(&self.dependencies, is_unconditional);
+        if self.max_feedback_iterations.is_none()
+            && has_path_connecting(&unconditional, to_idx, from_idx, None)
+        {
+            return Err(self.cycle_error(from_idx, to_idx));
+        }
+        self.dependencies.add_edge(from_idx, to_idx, EdgeType::External);
+        self.schedule = None;
 
         Ok(())
     }
 
+    /// Remove a connection previously added with [`Controller::add_connection`].
+    ///
+    /// Fails if any of the devices or ports are not known by the controller, or if there is no
+    /// connection between the two ports ([`ControllerError::NoSuchConnection`]).
+    pub fn remove_connection(
+        &mut self,
+        from_device: &DeviceIdentifier, from_port: &PortIdentifier,
+        to_device: &DeviceIdentifier, to_port: &PortIdentifier,
+    ) -> Result<(), ControllerError> {
+        let from_idx = self.node(self.port_handle(from_device, from_port)?);
+        let to_idx = self.node(self.port_handle(to_device, to_port)?);
+
+        // Internal edges aren't connections, so mustn't be removable here
+        let edge = self.dependencies.find_edge(from_idx, to_idx)
+            .filter(|edge| matches!(self.dependencies[*edge], EdgeType::External));
+        match edge {
+            None => Err(ControllerError::NoSuchConnection {
+                connection: Connection {
+                    from: (from_device.clone(), from_port.clone()),
+                    to: (to_device.clone(), to_port.clone()),
+                },
+            }),
+            Some(edge) => {
+                self.dependencies.remove_edge(edge);
+                self.schedule = None;
+                Ok(())
+            }
+        }
+    }
+
+    /// Remove a device, along with all of its ports and every connection to or from it.
+    ///
+    /// Returns the removed device, or fails if the device is not known by the controller.
+    pub fn remove_device(&mut self, id: &DeviceIdentifier)
+        -> Result<Box<dyn Device>, ControllerError>
+    {
+        let handle = self.device_ids.remove(id)
+            .ok_or_else(|| ControllerError::UnknownDevice { device: id.clone() })?;
+        let entry = self.devices[handle.0].take()
+            .expect("Handle retrieved from `self.device_ids` should always refer to a device");
+
+        // Removing a node from the graph also removes every edge attached to it
+        for node in entry.nodes {
+            self.dependencies.remove_node(node);
+        }
+        self.schedule = None;
+
+        Ok(entry.device)
+    }
+
+    /// Replace a device with another, keeping every connection to or from the old device that is
+    /// still valid for the new one. The device keeps the same [`DeviceId`].
+    ///
+    /// A connection is kept if the new device has a port with the same identifier and direction,
+    /// and re-adding it would not break any of the rules enforced by
+    /// [`Controller::add_connection`] (for example, by introducing a cycle through the new
+    /// device's internal dependencies).
+    ///
+    /// Returns the old device and the connections that were dropped, or fails if the device is
+    /// not known by the controller.
+    pub fn replace_device(&mut self, id: &DeviceIdentifier, device: Box<dyn Device>)
+        -> Result<(Box<dyn Device>, Vec<Connection>), ControllerError>
+    {
+        let handle = self.device_id(id)
+            .ok_or_else(|| ControllerError::UnknownDevice { device: id.clone() })?;
+
+        // Remember every connection involving the old device before its ports are removed
+        let connections: Vec<Connection> = self.connections().into_iter()
+            .filter(|connection| connection.from.0 == *id || connection.to.0 == *id)
+            .collect();
+
+        // Swap out the device and its ports, keeping its place in `self.devices`
+        let old_nodes = std::mem::take(&mut self.devices[handle.0].as_mut()
+            .expect("Handle retrieved from `self.device_ids` should always refer to a device")
+            .nodes);
+        for node in old_nodes {
+            self.dependencies.remove_node(node);
+        }
+        let nodes = self.add_port_nodes(handle, device.as_ref());
+        let entry = self.devices[handle.0].as_mut()
+            .expect("Handle retrieved from `self.device_ids` should always refer to a device");
+        entry.nodes = nodes;
+        let old_device = std::mem::replace(&mut entry.device, device);
+        self.schedule = None;
+
+        let mut dropped = Vec::new();
+        for connection in connections {
+            let result = self.add_connection(
+                &connection.from.0, &connection.from.1,
+                &connection.to.0, &connection.to.1,
+            );
+            if result.is_err() {
+                dropped.push(connection);
+            }
+        }
+
+        Ok((old_device, dropped))
+    }
+
+    /// Get every user-defined connection known by this [`Controller`].
+    pub fn connections(&self) -> Vec<Connection> {
+        self.dependencies.edge_indices()
+            .filter(|edge| matches!(self.dependencies[*edge], EdgeType::External))
+            .map(|edge| {
+                let (from_idx, to_idx) = self.dependencies.edge_endpoints(edge)
+                    .expect("Edge index retrieved from `edge_indices()` should have endpoints");
+                Connection {
+                    from: self.port_names(self.dependencies[from_idx]),
+                    to: self.port_names(self.dependencies[to_idx]),
+                }
+            })
+            .collect()
+    }
+
+    /// Get the handle of the device with the given identifier, if there is one.
+    pub fn device_id(&self, id: &str) -> Option<DeviceId> {
+        self.device_ids.get(id).copied()
+    }
+
+    /// Get the identifier of the device with the given handle, if there is one.
+    pub fn device_name(&self, device: DeviceId) -> Option<&DeviceIdentifier> {
+        self.entry(device).map(|entry| &entry.name)
+    }
+
+    /// Get the handle of the port with the given identifier on the given device, if there is one.
+    pub fn port_id(&self, device: DeviceId, port: &str) -> Option<PortId> {
+        self.entry(device)?.device.find_port(port)
+    }
+
+    /// Get a view of the values in the circuit as of the most recent successful tick.
+    pub fn state(&self) -> CircuitState<'_> {
+        CircuitState::new(self)
+    }
+
+    /// Get the value of the given port as of the most recent successful tick.
+    ///
+    /// Returns `None` if the device or port is unknown, or if there has not been a successful tick
+    /// since the port was added.
+    pub fn port_value(&self, device: DeviceId, port: PortId) -> Option<PortValue> {
+        self.state().value(device, port).cloned()
+    }
+
+    /// Get the value of the given port as of the most recent successful tick, looking the device
+    /// and port up by their identifiers.
+    pub fn port_value_by_name(&self, device: &str, port: &str) -> Option<PortValue> {
+        let device = self.device_id(device)?;
+        self.port_value(device, self.port_id(device, port)?)
+    }
+
+    fn entry(&self, device: DeviceId) -> Option<&DeviceEntry> {
+        self.devices.get(device.0).and_then(Option::as_ref)
+    }
+
+    /// Get the handles of the given port.
+    ///
+    /// Fails if the device or port is not known by this controller.
+    fn port_handle(&self, device: &DeviceIdentifier, port: &PortIdentifier)
+        -> Result<(DeviceId, PortId), ControllerError>
+    {
+        let handle = self.device_id(device)
+            .ok_or_else(|| ControllerError::UnknownDevice { device: device.clone() })?;
+        match self.port_id(handle, port) {
+            None => Err(ControllerError::UnknownPort {
+                device: device.clone(),
+                port: port.clone(),
+            }),
+            Some(port) => Ok((handle, port)),
+        }
+    }
+
+    /// Get the node in the dependency graph of a port whose handles are known to be valid.
+    fn node(&self, (device, port): (DeviceId, PortId)) -> NodeIndex {
+        self.entry(device).expect("Device handle should be valid").nodes[port.0]
+    }
+
+    /// Get the description of a port whose handles are known to be valid.
+    fn port_descriptor(&self, (device, port): (DeviceId, PortId)) -> &PortDescriptor {
+        &self.entry(device).expect("Device handle should be valid")
+            .device.get_ports()[port.0]
+    }
+
+    /// Get the identifiers of a port whose handles are known to be valid, for use in errors and
+    /// display.
+    fn port_names(&self, (device, port): (DeviceId, PortId)) -> (DeviceIdentifier, PortIdentifier) {
+        let entry = self.entry(device).expect("Device handle should be valid");
+        (entry.name.clone(), entry.device.port_name(port))
+    }
+
+    /// Build the error explaining why a connection from `from_idx` to `to_idx` was rejected for
+    /// introducing a cycle.
+    ///
+    /// The connection closes a loop because there is already a path from `to_idx` back to
+    /// `from_idx`, so we find that path and report it along with the connection itself.
+    fn cycle_error(&self, from_idx: NodeIndex, to_idx: NodeIndex) -> ControllerError {
+        let (_, existing_path) = astar(
+            &EdgeFiltered::from_fn(&self.dependencies, is_unconditional),
+            to_idx,
+            |idx| idx == from_idx,
+            |_| 1,
+            |_| 0,
+        ).expect("A connection is only rejected for creating a cycle if there is already a path \
+            from its \"to\" port back to its \"from\" port");
+        let mut path = vec![self.port_names(self.dependencies[from_idx])];
+        path.extend(existing_path.into_iter().map(|idx| self.port_names(self.dependencies[idx])));
+        ControllerError::Cycle { path }
+    }
+
     /// Perform a tick.
     ///
     /// This uses the dependency graph to figure out the value of every single port and connection
-    /// in the circuit (returning the values of all ports as a [`HashSet`]).
-    pub fn tick(&mut self)
-        -> Result<HashMap<(DeviceIdentifier, PortIdentifier), PortValue>, ControllerError>
-    {
-        // Here is where the acyclic data structure comes into its own - we can perform a
-        // "topological sort" of the nodes, which will tell us what order to traverse them in
-        // TODO: improve the memory usage of this, I don't like the clone
-        let acyclic = Acyclic::try_from_graph(self.dependencies.clone())
-            .expect("`self.dependencies` should never contain cycles, so should always be \
-            wrappable in the `Acyclic` wrapper");
-        let mut result: HashMap<(DeviceIdentifier, PortIdentifier), PortValue> = HashMap::new();
-        for port_idx in acyclic.nodes_iter() {
-            let (device_id, port_id) = acyclic.node_weight(port_idx)
-                .expect("Node index retrieved from `nodes_iter()` should return `Some` \
-                from `node_weight()`");
-
-            let device = self.devices.get(device_id)
-                .expect("Device id retrieved from `node_weight()` should always be a key \
-                present in `self.devices`");
-            if device.get_output_ports().contains(port_id) {
-                // This is an output port, so we should have provided its dependencies in a
-                // previous iteration, or it has no dependencies
-                result.insert(
-                    (device_id.clone(), port_id.clone()),
-                    device.get_port_value(port_id)
-                        .expect("Port value retrieved from `get_output_ports()` should always \
-                            be a valid input to `get_port_value()`")
-                        .expect("Output port should have a known value at this point in the \
-                            topological sort")
-                );
-            } else if device.get_input_ports().contains(port_id) {
-                // This is an input port, so its value must be coming from a connected output port.
-                // For now (and this will be changed), all input ports must have a value connected
-                // to them, so if we don't find a connection between this and an output port, that
-                // is an error.
-                // Thanks to the `neighbors_directed()` function, we can just directly find the
-                // connected output port, as it will be at the other end of the only incoming
-                // edge to this input port.
-                let mut incoming_neighbours = acyclic.neighbors_directed(
-                    port_idx,
-                    Direction::Incoming
-                );
-                let n_incoming = incoming_neighbours.clone().count();
-                let output_port_idx = match n_incoming {
-                    0 => return Err(ControllerError),  // No incoming value, cannot resolve
-                    1 => incoming_neighbours.next().expect("Length is 1, so `next()` should \
-                        return a value"),
-                    _ => panic!("Should not end up in a state where an input port can have more \
-                        than one incoming connection"),
+    /// in the circuit, which can then be queried with [`Controller::port_value`].
+    ///
+    /// Not every port needs a value for the tick to go ahead. An input port with nothing connected
+    /// to it, or driven by an output port that couldn't be resolved, is simply not given a value,
+    /// and its device decides whether it can manage without it. Such ports have no value
+    /// afterwards, and neither do any output ports their devices couldn't resolve as a result.
+    ///
+    /// If the circuit has loops through dynamic dependencies, devices are asked which inputs they
+    /// need as values are worked out, and the tick fails with
+    /// [`ControllerError::CombinationalLoop`] if a port turns out to depend on itself.
+    ///
+    /// A tick is transactional: either every device receives its inputs and ticks, or (if the tick
+    /// fails at any point) every device is restored to exactly the state it was in beforehand, so
+    /// the tick can be attempted again once the problem has been fixed.
+    ///
+    /// Returns a view of the values in the circuit after the tick (see [`CircuitState`]).
+    pub fn tick(&mut self) -> Result<CircuitState<'_>, ControllerError> {
+        // First phase: propagate values around the circuit, and check that every device is happy
+        // to tick with what it has been given
+        let result = self.resolve_port_values()
+            .and_then(|_| self.check_devices_can_tick());
+        if let Err(err) = result {
+            // Nothing has ticked yet, so forgetting the provided values rolls everything back
+            for entry in self.devices.iter_mut().flatten() {
+                entry.device.clear_port_values();
+            }
+            return Err(err);
+        }
+
+        // Second phase: every device has said it can tick, so now actually perform the ticks
+        for entry in self.devices.iter_mut().flatten() {
+            entry.device.tick().expect("Device should always be able to tick once `check_tick()` \
+                has succeeded");
+        }
+        std::mem::swap(&mut self.values, &mut self.next_values);
+        self.ticks += 1;
+
+        Ok(self.state())
+    }
+
+    /// Check that every device is able to tick with the port values it has been provided.
+    fn check_devices_can_tick(&self) -> Result<(), ControllerError> {
+        for entry in self.devices.iter().flatten() {
+            entry.device.check_tick().map_err(|source| self.device_error(entry, source))?;
+        }
+        Ok(())
+    }
+
+    /// Wrap an error returned by a device, reporting a missing input that has nothing connected
+    /// to it as [`ControllerError::UnconnectedInput`].
+    fn device_error(&self, entry: &DeviceEntry, source: DeviceError) -> ControllerError {
+        if let DeviceError::MissingInput { port, .. } = &source {
+            let unconnected = entry.device.find_port(port).is_some_and(|port| {
+                self.dependencies
+                    .neighbors_directed(entry.nodes[port.0], Direction::Incoming)
+                    .next()
+                    .is_none()
+            });
+            if unconnected {
+                return ControllerError::UnconnectedInput {
+                    device: entry.name.clone(),
+                    port: port.clone(),
                 };
+            }
+        }
+        ControllerError::Device { device: entry.name.clone(), source }
+    }
+
+    /// Work out the value of every port in the circuit into `self.next_values`, providing input
+    /// port values to devices along the way.
+    ///
+    /// If this fails, some devices may be left holding provided values, which must be cleared.
+    fn resolve_port_values(&mut self) -> Result<(), ControllerError> {
+        // The schedule is thrown away whenever the circuit changes, so recompile it if needed
+        let schedule = match self.schedule.take() {
+            Some(schedule) => schedule,
+            None => Schedule::compile(
+                &self.dependencies,
+                &self.devices,
+                self.max_feedback_iterations.is_some(),
+            ),
+        };
+
+        // Values of ports resolved so far, indexed by the port's node in the dependency graph
+        let mut values = std::mem::take(&mut self.next_values);
+        values.clear();
+        values.resize(schedule.node_bound, None);
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.reset(schedule.node_bound);
+        let result = match &schedule.order {
+            Order::Topological => self.resolve_in_order(&schedule, &mut values),
+            Order::OnDemand { step_of_node } =>
+                self.resolve_on_demand(&schedule, step_of_node, &mut values, &mut scratch),
+            Order::FixedPoint { loops } =>
+                self.resolve_with_feedback(&schedule, loops, &mut values, &mut scratch),
+        };
+        self.schedule = Some(schedule);
+        self.next_values = values;
+        self.scratch = scratch;
+        result
+    }
+
+    /// Resolve every port in the order given by the schedule, which is topological.
+    fn resolve_in_order(&mut self, schedule: &Schedule, values: &mut [Option<PortValue>])
+        -> Result<(), ControllerError>
+    {
+        for step in schedule.steps.iter() {
+            values[step.node.index()] = self.resolve_step(step, values)?;
+        }
+        Ok(())
+    }
+
+    /// Resolve every port as it is needed, asking devices which inputs they currently depend on.
+    ///
+    /// This is used when the dependency graph has loops through conditional dependencies, so
+    /// there's no fixed order to resolve ports in. It fails if, given this tick's values, a port
+    /// turns out to depend on itself.
+    fn resolve_on_demand(
+        &mut self,
+        schedule: &Schedule,
+        step_of_node: &[usize],
+        values: &mut [Option<PortValue>],
+        scratch: &mut Scratch,
+    ) -> Result<(), ControllerError> {
+        let Scratch { resolved, in_progress, stack, deps, .. } = scratch;
+        for root in schedule.steps.iter() {
+            if resolved[root.node.index()] {
+                continue;
+            }
+            stack.push(root.node);
+            in_progress[root.node.index()] = true;
+            while let Some(&node) = stack.last() {
+                let step = &schedule.steps[step_of_node[node.index()]];
+                match self.next_unresolved_dependency(step, resolved, deps)? {
+                    Some(dep) if in_progress[dep.index()] => {
+                        return Err(self.loop_error(stack, dep));
+                    }
+                    Some(dep) => {
+                        stack.push(dep);
+                        in_progress[dep.index()] = true;
+                    }
+                    None => {
+                        values[node.index()] = self.resolve_step(step, values)?;
+                        resolved[node.index()] = true;
+                        in_progress[node.index()] = false;
+                        stack.pop();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Find a port that the given step's port currently depends on, but which hasn't been
+    /// resolved yet. `deps` is somewhere to put the inputs a device currently needs.
+    fn next_unresolved_dependency(&self, step: &Step, resolved: &[bool], deps: &mut Vec<PortId>)
+        -> Result<Option<NodeIndex>, ControllerError>
+    {
+        match step.kind {
+            StepKind::Input { ref drivers, .. } =>
+                Ok(drivers.iter().copied().find(|driver| !resolved[driver.index()])),
+            StepKind::Output { .. } => {
+                let entry = self.entry(step.device)
+                    .expect("Device handle in the schedule should always refer to a device");
+                // Take the lowest, so that ports are always resolved in the same order
+                if !entry.device.has_dynamic_dependencies() {
+                    // The dependencies never change, so are just the edges into this port
+                    return Ok(self.dependencies
+                        .neighbors_directed(step.node, Direction::Incoming)
+                        .filter(|dep| !resolved[dep.index()])
+                        .min());
+                }
+                entry.device.get_current_output_dependencies(step.port, deps)
+                    .map_err(|source| ControllerError::Device {
+                        device: entry.name.clone(),
+                        source,
+                    })?;
+                Ok(deps.iter()
+                    .map(|dep| entry.nodes[dep.0])
+                    .filter(|dep| !resolved[dep.index()])
+                    .min())
+            }
+        }
+    }
 
-                // Get the value of that other port
-                let (output_device_id, output_port_id) = acyclic
-                    .node_weight(output_port_idx)
-                    .expect("Node index retrieved from `neighbors_directed()` should return \
-                    `Some` from `node_weight()`");
-                let output_device = self.devices.get(output_device_id)
-                    .expect("Device id retrieved from `node_weight()` should always be a key \
-                    present in `self.devices`");
-                let value = output_device.get_port_value(output_port_id)
-                    .expect("Port connected to an input port should be an output port, and \
-                        should be present on the device arrived at at this point")
-                    .expect("Output port should have a known value at this point in the \
-                            topological sort");
-
-                // Pass it to this device
-                // Need to re-borrow it as mutable
-                let device = self.devices.get_mut(device_id)
-                    .expect("Using same device id as before should retrieve value");
-                device.provide_port_value(port_id.clone(), value).expect("Port id and value \
-                    obtained at this point should be valid inputs to `provide_port_value()`");
-
-                // Store this value
-                result.insert((device_id.clone(), port_id.clone()), value);
+    /// Build the error for a combinational loop found while resolving ports on demand: `dep` is
+    /// a dependency of the port at the top of `stack`, but is further down `stack` itself.
+    fn loop_error(&self, stack: &[NodeIndex], dep: NodeIndex) -> ControllerError {
+        let start = stack.iter().position(|node| *node == dep)
+            .expect("Port should only be reported as looping if it is being resolved");
+
+        // Each port on the stack depends on the one above it, so values flow down the stack
+        let mut path = vec![self.port_names(self.dependencies[dep])];
+        path.extend(stack[start + 1..].iter().rev()
+            .map(|node| self.port_names(self.dependencies[*node])));
+        path.push(self.port_names(self.dependencies[dep]));
+        ControllerError::CombinationalLoop { path }
+    }
+
+    /// Resolve every port in the order given by the schedule, going round each feedback loop
+    /// until its values settle.
+    fn resolve_with_feedback(
+        &mut self,
+        schedule: &Schedule,
+        loops: &[Range<usize>],
+        values: &mut [Option<PortValue>],
+        scratch: &mut Scratch,
+    ) -> Result<(), ControllerError> {
+        let mut loops = loops.iter().peekable();
+        let mut index = 0;
+        while index < schedule.steps.len() {
+            let steps = match loops.next_if(|range| range.start == index) {
+                Some(range) => {
+                    let steps = &schedule.steps[range.clone()];
+                    self.resolve_loop(steps, values, scratch)?;
+                    steps
+                }
+                None => {
+                    let step = &schedule.steps[index];
+                    values[step.node.index()] = self.resolve_step(step, values)?;
+                    std::slice::from_ref(step)
+                }
+            };
+            for step in steps {
+                scratch.resolved[step.node.index()] = true;
             }
+            index += steps.len();
+        }
+        Ok(())
+    }
+
+    /// Resolve the ports on a feedback loop by going round it until its values stop changing.
+    /// Every port the loop depends on must already have been resolved.
+    fn resolve_loop(
+        &mut self,
+        steps: &[Step],
+        values: &mut [Option<PortValue>],
+        scratch: &mut Scratch,
+    ) -> Result<(), ControllerError> {
+        let max_iterations = self.max_feedback_iterations
+            .expect("Feedback loops should only be scheduled if feedback is allowed");
+        let Scratch { resolved, on_loop, loop_devices: devices, .. } = scratch;
+        for step in steps {
+            on_loop[step.node.index()] = true;
+        }
+        devices.clear();
+        devices.extend(steps.iter().map(|step| step.device));
+        devices.sort_unstable();
+        devices.dedup();
+
+        // Start from the values these ports had after the last tick, so that latches and the
+        // like hold their state
+        for step in steps {
+            values[step.node.index()] = self.values.get(step.node.index()).cloned().flatten();
         }
 
-        // TODO: Ensure that if a failure occurs in one of the loops, we rollback the provision of
-        //  values to the other devices so a tick can be attempted again
+        for _ in 0..max_iterations {
+            // Give the devices on the loop their inputs afresh: the current guess for those on
+            // the loop, and the values already resolved for the rest
+            for &device in devices.iter() {
+                let entry = self.devices[device.0].as_mut()
+                    .expect("Device handle in the schedule should always refer to a device");
+                entry.device.clear_port_values();
+                for (port, node) in entry.nodes.iter().enumerate() {
+                    let is_input = entry.device.get_ports()[port].direction
+                        == PortDirection::Input;
+                    let known = resolved[node.index()] || on_loop[node.index()];
+                    if let (true, true, Some(value)) = (is_input, known, &values[node.index()]) {
+                        entry.device.provide_port_value(PortId(port), value.clone())
+                            .map_err(|source| ControllerError::Device {
+                                device: entry.name.clone(),
+                                source,
+                            })?;
+                    }
+                }
+            }
+
+            // Work out every output on the loop from those inputs, then every input on the loop
+            // from those outputs
+            let mut changed = false;
+            for step in steps.iter() {
+                if let StepKind::Output { width } = step.kind {
+                    let value = self.output_value(step, width)?;
+                    changed |= values[step.node.index()] != value;
+                    values[step.node.index()] = value;
+                }
+            }
+            for step in steps.iter() {
+                if let StepKind::Input { width, ref drivers } = step.kind {
+                    let value = self.driven_value(width, drivers, values);
+                    changed |= values[step.node.index()] != value;
+                    values[step.node.index()] = value;
+                }
+            }
 
-        // Now that we have retrieved all the values, we should perform a tick on every device
-        for device in self.devices.values_mut() {
-            if device.tick().is_err() {
-                // TODO: Rollback ticks so this can be done again
-                return Err(ControllerError);
+            // If nothing changed, the devices already hold these inputs
+            if !changed {
+                return Ok(());
             }
         }
 
-        Ok(result)
+        Err(ControllerError::Oscillation {
+            ports: steps.iter()
+                .map(|step| self.port_names((step.device, step.port)))
+                .collect(),
+            iterations: max_iterations,
+        })
+    }
+
+    /// Work out the value of a single port, providing it to its device if it is an input port.
+    /// Every port it depends on must already have been resolved into `values`.
+    fn resolve_step(&mut self, step: &Step, values: &[Option<PortValue>])
+        -> Result<Option<PortValue>, ControllerError>
+    {
+        let value = match step.kind {
+            // Every input this output depends on has been resolved, and provided if its value
+            // is known
+            StepKind::Output { width } => self.output_value(step, width)?,
+            StepKind::Input { width, ref drivers } => {
+                // The driving output ports have been resolved, so we already know their values,
+                // or that they can't be resolved this tick
+                let value = self.driven_value(width, drivers, values);
+
+                // Pass it to this device, if we know it
+                if let Some(value) = &value {
+                    let entry = self.devices[step.device.0].as_mut()
+                        .expect("Device handle in the schedule should always refer to a device");
+                    entry.device.provide_port_value(step.port, value.clone())
+                        .map_err(|source| ControllerError::Device {
+                            device: entry.name.clone(),
+                            source,
+                        })?;
+                }
+                value
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Read the value of the output port for the given step from its device, given the inputs
+    /// provided so far. The device may be unable to resolve it if an input it needs is unknown.
+    ///
+    /// Fails if the device reports an error for the inputs it has been given.
+    fn output_value(&self, step: &Step, width: u32)
+        -> Result<Option<PortValue>, ControllerError>
+    {
+        let entry = self.entry(step.device)
+            .expect("Device handle in the schedule should always refer to a device");
+        let value = entry.device.get_port_value(step.port)
+            .map_err(|source| ControllerError::Device { device: entry.name.clone(), source })?;
+        // Don't let a device put more bits onto a connection than the port has
+        Ok(value.map(|value| value.mask(width)))
+    }
+
+    /// Work out the value of an input port from the values of the output ports driving it.
+    fn driven_value(&self, width: u32, drivers: &[NodeIndex], values: &[Option<PortValue>])
+        -> Option<PortValue>
+    {
+        match drivers.split_first() {
+            // With four-state logic an undriven input floats. Otherwise it is left without a
+            // value, and it is up to the device whether it needs one.
+            None => self.four_state.then(|| PortValue::z(width)),
+            Some((first, rest)) => {
+                let mut value = values[first.index()].clone();
+                for driver in rest {
+                    value = match (value, &values[driver.index()]) {
+                        (Some(value), Some(driven)) => Some(value.resolve(driven)),
+                        _ => None,
+                    };
+                }
+                value
+            }
+        }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::let_unit_value, clippy::useless_vec)]
 mod tests {
-    use crate::controller::{Controller, DeviceIdentifier};
+    use crate::controller::{Connection, Controller, ControllerError, DeviceIdentifier};
     use crate::device::debug::constant::Constant;
     use crate::device::debug::sequencer::Sequencer;
     use crate::device::memory::Memory;
-    use crate::device::{PortIdentifier, PortValue};
+    use crate::device::register::Register;
+    use crate::device::{
+        Device, DeviceError, PortDescriptor, PortId, PortIdentifier, PortValue,
+    };
+    use std::collections::{HashMap, HashSet};
 
     #[test]
     fn controller_can_be_instantiated() {
@@ -222,20 +1081,44 @@ mod tests {
     fn controller_can_have_devices_added_to_it() {
         let mut controller = Controller::new();
         let memory = Memory::new();
-        let sequencer = Sequencer::new("qq".to_owned(), &vec![0]).unwrap();
+        let sequencer = Sequencer::new("qq".to_owned(), &vec![PortValue::from(0u32)]).unwrap();
         
-        let _ = controller.add_device("Memory".to_owned(), Box::new(memory));
-        let _ = controller.add_device("Sequencer".to_owned(), Box::new(sequencer));
+        let _ = controller.add_device("Memory".to_owned(), Box::new(memory)).unwrap();
+        let _ = controller.add_device("Sequencer".to_owned(), Box::new(sequencer)).unwrap();
     }
     
+    #[test]
+    fn controller_cannot_have_two_devices_with_the_same_identifier() {
+        let mut controller = Controller::new();
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+
+        let result = controller.add_device("Memory".to_owned(), Box::new(Memory::new()));
+        assert!(matches!(
+            result,
+            Err(ControllerError::DuplicateDevice { device }) if device == "Memory"
+        ));
+    }
+
+    #[test]
+    fn controller_can_look_up_handles_by_identifier() {
+        let mut controller = Controller::new();
+        let handle = controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+
+        assert_eq!(controller.device_id("Memory"), Some(handle));
+        assert_eq!(controller.device_id("Nonexistent"), None);
+        assert_eq!(controller.device_name(handle), Some(&"Memory".to_owned()));
+        assert_eq!(controller.port_id(handle, "rv"), Some(Memory::RV));
+        assert_eq!(controller.port_id(handle, "qq"), None);
+    }
+
     #[test]
     fn controller_can_have_connections_added_to_it() {
         let mut controller = Controller::new();
         let memory = Memory::new();
-        let sequencer = Sequencer::new("qq".to_owned(), &vec![0]).unwrap();
+        let sequencer = Sequencer::new("qq".to_owned(), &vec![PortValue::from(0u32)]).unwrap();
 
-        let _ = controller.add_device("Memory".to_owned(), Box::new(memory));
-        let _ = controller.add_device("Sequencer".to_owned(), Box::new(sequencer));
+        let _ = controller.add_device("Memory".to_owned(), Box::new(memory)).unwrap();
+        let _ = controller.add_device("Sequencer".to_owned(), Box::new(sequencer)).unwrap();
         
         let result = controller.add_connection(
             &"Sequencer".to_owned(), &"qq".to_owned(),
@@ -250,29 +1133,263 @@ mod tests {
         let mut controller = Controller::new();
         let memory = Memory::new();
 
-        _ = controller.add_device("Memory".to_owned(), Box::new(memory));
+        _ = controller.add_device("Memory".to_owned(), Box::new(memory)).unwrap();
 
         let result = controller.add_connection(
             &"Memory".to_owned(), &"rv".to_owned(),
             &"Memory".to_owned(), &"ra".to_owned(),
         );
 
-        assert!(result.is_err());
+        assert_eq!(result, Err(ControllerError::Cycle {
+            path: vec![
+                ("Memory".to_owned(), "rv".to_owned()),
+                ("Memory".to_owned(), "ra".to_owned()),
+                ("Memory".to_owned(), "rv".to_owned()),
+            ],
+        }));
     }
-    
+
     #[test]
-    fn controller_can_perform_tick_with_no_devices() {
+    fn controller_reports_full_path_of_rejected_cycle() {
         let mut controller = Controller::new();
-        let result = controller.tick();
-        assert!(result.is_ok());
+        controller.add_device("First".to_owned(), Box::new(Memory::new())).unwrap();
+        controller.add_device("Second".to_owned(), Box::new(Memory::new())).unwrap();
+
+        controller.add_connection(
+            &"First".to_owned(), &"rv".to_owned(),
+            &"Second".to_owned(), &"ra".to_owned(),
+        ).unwrap();
+        let result = controller.add_connection(
+            &"Second".to_owned(), &"rv".to_owned(),
+            &"First".to_owned(), &"ra".to_owned(),
+        );
+
+        assert_eq!(result, Err(ControllerError::Cycle {
+            path: vec![
+                ("Second".to_owned(), "rv".to_owned()),
+                ("First".to_owned(), "ra".to_owned()),
+                ("First".to_owned(), "rv".to_owned()),
+                ("Second".to_owned(), "ra".to_owned()),
+                ("Second".to_owned(), "rv".to_owned()),
+            ],
+        }));
     }
-    
+
     #[test]
-    fn controller_can_perform_tick_with_device_with_only_outputs() {
+    fn controller_cannot_connect_unknown_devices_or_ports() {
         let mut controller = Controller::new();
-        let constant = Constant::new("qq".to_owned(), 1);
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
 
-        _ = controller.add_device("Constant".to_owned(), Box::new(constant));
+        let result = controller.add_connection(
+            &"Nonexistent".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        );
+        assert_eq!(result, Err(ControllerError::UnknownDevice {
+            device: "Nonexistent".to_owned(),
+        }));
+
+        let result = controller.add_connection(
+            &"Memory".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        );
+        assert_eq!(result, Err(ControllerError::UnknownPort {
+            device: "Memory".to_owned(),
+            port: "qq".to_owned(),
+        }));
+    }
+    
+    #[test]
+    fn controller_cannot_connect_ports_in_the_wrong_direction() {
+        let mut controller = Controller::new();
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(0u32));
+        controller.add_device("Constant".to_owned(), Box::new(constant)).unwrap();
+
+        // From an input port
+        let result = controller.add_connection(
+            &"Memory".to_owned(), &"wa".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        );
+        assert_eq!(result, Err(ControllerError::NotAnOutputPort {
+            device: "Memory".to_owned(),
+            port: "wa".to_owned(),
+        }));
+
+        // To an output port
+        let result = controller.add_connection(
+            &"Constant".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"rv".to_owned(),
+        );
+        assert_eq!(result, Err(ControllerError::NotAnInputPort {
+            device: "Memory".to_owned(),
+            port: "rv".to_owned(),
+        }));
+    }
+
+    #[test]
+    fn controller_cannot_connect_ports_of_different_widths() {
+        let mut controller = Controller::new();
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(1u32));
+        controller.add_device("Constant".to_owned(), Box::new(constant)).unwrap();
+
+        // Write enable is a single bit, but the constant is a whole word
+        let result = controller.add_connection(
+            &"Constant".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"we".to_owned(),
+        );
+        assert_eq!(result, Err(ControllerError::WidthMismatch {
+            from: ("Constant".to_owned(), "qq".to_owned()),
+            from_width: 32,
+            to: ("Memory".to_owned(), "we".to_owned()),
+            to_width: 1,
+        }));
+    }
+
+    #[test]
+    fn controller_cannot_have_two_drivers_connected_to_one_input() {
+        let mut controller = Controller::new();
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(0u32));
+        controller.add_device("First".to_owned(), Box::new(constant)).unwrap();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(1u32));
+        controller.add_device("Second".to_owned(), Box::new(constant)).unwrap();
+
+        controller.add_connection(
+            &"First".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        ).unwrap();
+        let result = controller.add_connection(
+            &"Second".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        );
+
+        assert_eq!(result, Err(ControllerError::AlreadyDriven {
+            device: "Memory".to_owned(),
+            port: "ra".to_owned(),
+            driver: ("First".to_owned(), "qq".to_owned()),
+        }));
+    }
+
+    #[test]
+    fn controller_can_have_connections_removed() {
+        let mut controller = Controller::new();
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(0u32));
+        controller.add_device("Constant".to_owned(), Box::new(constant)).unwrap();
+        controller.add_connection(
+            &"Constant".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        ).unwrap();
+
+        let result = controller.remove_connection(
+            &"Constant".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        );
+        assert!(result.is_ok());
+        assert!(controller.connections().is_empty());
+
+        // The input is no longer driven, so can be connected again
+        let result = controller.add_connection(
+            &"Constant".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn controller_cannot_remove_nonexistent_connections() {
+        let mut controller = Controller::new();
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(0u32));
+        controller.add_device("Constant".to_owned(), Box::new(constant)).unwrap();
+
+        let result = controller.remove_connection(
+            &"Constant".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        );
+        assert!(matches!(result, Err(ControllerError::NoSuchConnection { .. })));
+
+        // Internal dependencies are not connections
+        let result = controller.remove_connection(
+            &"Memory".to_owned(), &"ra".to_owned(),
+            &"Memory".to_owned(), &"rv".to_owned(),
+        );
+        assert!(matches!(result, Err(ControllerError::NoSuchConnection { .. })));
+    }
+
+    #[test]
+    fn controller_can_have_devices_removed() {
+        let mut controller = Controller::new();
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(0u32));
+        controller.add_device("Constant".to_owned(), Box::new(constant)).unwrap();
+        controller.add_connection(
+            &"Constant".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        ).unwrap();
+
+        let result = controller.remove_device(&"Memory".to_owned());
+        assert!(result.is_ok());
+        assert!(controller.connections().is_empty());
+
+        // Only the constant is left, so the circuit can now tick
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name("Constant", "qq"), Some(PortValue::from(0u32)));
+        assert!(controller.port_value_by_name("Memory", "rv").is_none());
+
+        let result = controller.remove_device(&"Memory".to_owned());
+        assert!(matches!(result, Err(ControllerError::UnknownDevice { .. })));
+    }
+
+    #[test]
+    fn controller_keeps_compatible_connections_when_replacing_devices() {
+        let mut controller = Controller::new();
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(0u32));
+        controller.add_device("Source".to_owned(), Box::new(constant)).unwrap();
+        controller.add_connection(
+            &"Source".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        ).unwrap();
+        let connection = Connection {
+            from: ("Source".to_owned(), "qq".to_owned()),
+            to: ("Memory".to_owned(), "ra".to_owned()),
+        };
+
+        // Same output port, so the connection is kept
+        let handle = controller.device_id("Source").unwrap();
+        let values = [PortValue::from(1u32), PortValue::from(2u32)];
+        let sequencer = Sequencer::new("qq".to_owned(), &values).unwrap();
+        let (_, dropped) = controller
+            .replace_device(&"Source".to_owned(), Box::new(sequencer))
+            .unwrap();
+        assert!(dropped.is_empty());
+        assert_eq!(controller.device_id("Source"), Some(handle));
+        assert_eq!(controller.connections(), vec![connection.clone()]);
+
+        // Different output port, so the connection is dropped
+        let constant = Constant::new("zz".to_owned(), PortValue::from(1u32));
+        let (_, dropped) = controller
+            .replace_device(&"Source".to_owned(), Box::new(constant))
+            .unwrap();
+        assert_eq!(dropped, vec![connection]);
+        assert!(controller.connections().is_empty());
+    }
+
+    #[test]
+    fn controller_can_perform_tick_with_no_devices() {
+        let mut controller = Controller::new();
+        let result = controller.tick();
+        assert!(result.is_ok());
+    }
+    
+    #[test]
+    fn controller_can_perform_tick_with_device_with_only_outputs() {
+        let mut controller = Controller::new();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(1u32));
+
+        _ = controller.add_device("Constant".to_owned(), Box::new(constant)).unwrap();
         
         let result = controller.tick();
         assert!(result.is_ok());
@@ -283,43 +1400,177 @@ mod tests {
         let mut controller = Controller::new();
         let device_id: DeviceIdentifier = "Constant".to_owned();
         let port_id: PortIdentifier = "qq".to_owned();
-        let value: PortValue = 1;
-        let constant = Constant::new(port_id.clone(), value);
+        let value = PortValue::from(1u32);
+        let constant = Constant::new(port_id.clone(), value.clone());
 
-        _ = controller.add_device(device_id.clone(), Box::new(constant));
+        _ = controller.add_device(device_id.clone(), Box::new(constant)).unwrap();
 
-        let result = controller.tick().unwrap();
-        assert_eq!(result.len(), 1);
-        assert!(result.contains_key(&(device_id.clone(), port_id.clone())));
-        assert_eq!(result.get(&(device_id.clone(), port_id.clone())), Some(&value));
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name(&device_id, &port_id), Some(value));
+    }
+
+    #[test]
+    fn controller_gets_output_values_by_handle() {
+        let mut controller = Controller::new();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(1u32));
+        let handle = controller.add_device("Constant".to_owned(), Box::new(constant)).unwrap();
+
+        // Nothing is known until there has been a tick
+        assert_eq!(controller.port_value(handle, Constant::OUTPUT), None);
+
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value(handle, Constant::OUTPUT), Some(PortValue::from(1u32)));
     }
     
     #[test]
-    fn controller_cannot_tick_with_unconnected_inputs() {
+    fn controller_picks_up_circuit_changes_between_ticks() {
+        let mut controller = Controller::new();
+        let first = Box::new(Constant::new("qq".to_owned(), PortValue::from(1u32)));
+        controller.add_device("First".to_owned(), first).unwrap();
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name("First", "qq"), Some(PortValue::from(1u32)));
+
+        let second = Box::new(Constant::new("qq".to_owned(), PortValue::from(2u32)));
+        controller.add_device("Second".to_owned(), second).unwrap();
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name("First", "qq"), Some(PortValue::from(1u32)));
+        assert_eq!(controller.port_value_by_name("Second", "qq"), Some(PortValue::from(2u32)));
+
+        controller.remove_device(&"First".to_owned()).unwrap();
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name("First", "qq"), None);
+        assert_eq!(controller.port_value_by_name("Second", "qq"), Some(PortValue::from(2u32)));
+    }
+
+    #[test]
+    fn controller_cannot_tick_when_a_device_needs_an_unconnected_input() {
         let mut controller = Controller::new();
         let memory = Memory::new();
 
-        _ = controller.add_device("Memory".to_owned(), Box::new(memory));
+        _ = controller.add_device("Memory".to_owned(), Box::new(memory)).unwrap();
 
         let result = controller.tick();
-        assert!(result.is_err());
+        assert_eq!(result.err(), Some(ControllerError::UnconnectedInput {
+            device: "Memory".to_owned(),
+            port: "we".to_owned(),
+        }));
+    }
+
+    #[test]
+    fn controller_can_tick_with_unconnected_inputs_that_are_not_needed() {
+        let mut controller = Controller::new();
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+        let we_const = Constant::with_width("qq".to_owned(), 1, PortValue::ZERO).unwrap();
+        controller.add_device("WE".to_owned(), Box::new(we_const)).unwrap();
+        controller.add_connection(
+            &"WE".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"we".to_owned(),
+        ).unwrap();
+
+        // Nothing is written, so the write address and value aren't needed, and with no read
+        // address there is simply no read value
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name("Memory", "wa"), None);
+        assert_eq!(controller.port_value_by_name("Memory", "rv"), None);
+    }
+
+    #[test]
+    fn controller_passes_on_unresolved_outputs_as_missing_inputs() {
+        let mut controller = Controller::new();
+        for id in ["First", "Second"] {
+            controller.add_device(id.to_owned(), Box::new(Memory::with_widths(1, 1).unwrap()))
+                .unwrap();
+        }
+        let we_const = Constant::with_width("qq".to_owned(), 1, PortValue::ZERO).unwrap();
+        controller.add_device("WE".to_owned(), Box::new(we_const)).unwrap();
+        controller.add_connection(
+            &"WE".to_owned(), &"qq".to_owned(),
+            &"First".to_owned(), &"we".to_owned(),
+        ).unwrap();
+        controller.add_connection(
+            &"First".to_owned(), &"rv".to_owned(),
+            &"Second".to_owned(), &"we".to_owned(),
+        ).unwrap();
+
+        // "First" has no read address, so can't drive the write enable of "Second"; that input is
+        // connected, so it's reported as missing by the device rather than as unconnected
+        let result = controller.tick();
+        assert!(matches!(
+            result,
+            Err(ControllerError::Device { device, source: DeviceError::MissingInput { port, .. } })
+                if device == "Second" && port == "we"
+        ));
+    }
+
+    #[test]
+    fn four_state_controller_reads_unconnected_inputs_as_z() {
+        let mut controller = Controller::new_four_state();
+        let mut memory = Memory::with_widths(8, 8).unwrap();
+        memory.set_four_state(true);
+        controller.add_device("Memory".to_owned(), Box::new(memory)).unwrap();
+        let we_const = Constant::with_width("qq".to_owned(), 1, PortValue::ZERO).unwrap();
+        controller.add_device("WE".to_owned(), Box::new(we_const)).unwrap();
+        controller.add_connection(
+            &"WE".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"we".to_owned(),
+        ).unwrap();
+
+        // The floating read address means the memory can't know what to read
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name("Memory", "ra"), Some(PortValue::z(8)));
+        assert_eq!(controller.port_value_by_name("Memory", "rv"), Some(PortValue::x(8)));
+    }
+
+    #[test]
+    fn four_state_controller_resolves_inputs_with_several_drivers() {
+        let mut controller = Controller::new_four_state();
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+        let drivers = [("Low", "zzzz_0101"), ("High", "0101_zzzz"), ("Clash", "zzzz_zz10")];
+        for (id, value) in drivers {
+            let constant = Constant::new("qq".to_owned(), value.parse().unwrap());
+            controller.add_device(id.to_owned(), Box::new(constant)).unwrap();
+            controller.add_connection(
+                &id.to_owned(), &"qq".to_owned(),
+                &"Memory".to_owned(), &"ra".to_owned(),
+            ).unwrap();
+        }
+
+        // The same connection still can't be added twice
+        let result = controller.add_connection(
+            &"Low".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        );
+        assert!(matches!(result, Err(ControllerError::AlreadyDriven { .. })));
+
+        // Leave the write ports floating as well, which is fine as long as we aren't writing
+        let we_const = Constant::with_width("qq".to_owned(), 1, PortValue::ZERO).unwrap();
+        controller.add_device("WE".to_owned(), Box::new(we_const)).unwrap();
+        controller.add_connection(
+            &"WE".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"we".to_owned(),
+        ).unwrap();
+
+        // Where exactly one constant drives a bit it wins, but the lowest two bits clash
+        controller.tick().unwrap();
+        let expected: PortValue = "0101_01xx".parse().unwrap();
+        assert_eq!(controller.port_value_by_name("Memory", "ra"), Some(expected));
     }
 
     #[test]
     fn controller_can_tick_with_connected_inputs() {
         let mut controller = Controller::new();
         let memory = Memory::new();
-        let ra_const = Constant::new("qq".to_owned(), 1);
-        let we_const = Constant::new("qq".to_owned(), 1);
-        let wa_const = Constant::new("qq".to_owned(), 2);
-        let wv_const = Constant::new("qq".to_owned(), 3);
+        let ra_const = Constant::new("qq".to_owned(), PortValue::from(1u32));
+        let we_const = Constant::with_width("qq".to_owned(), 1, PortValue::from(1u32)).unwrap();
+        let wa_const = Constant::new("qq".to_owned(), PortValue::from(2u32));
+        let wv_const = Constant::new("qq".to_owned(), PortValue::from(3u32));
 
         // Add devices
-        _ = controller.add_device("Memory".to_owned(), Box::new(memory));
-        _ = controller.add_device("RA constant".to_owned(), Box::new(ra_const));
-        _ = controller.add_device("WE constant".to_owned(), Box::new(we_const));
-        _ = controller.add_device("WA constant".to_owned(), Box::new(wa_const));
-        _ = controller.add_device("WV constant".to_owned(), Box::new(wv_const));
+        _ = controller.add_device("Memory".to_owned(), Box::new(memory)).unwrap();
+        _ = controller.add_device("RA constant".to_owned(), Box::new(ra_const)).unwrap();
+        _ = controller.add_device("WE constant".to_owned(), Box::new(we_const)).unwrap();
+        _ = controller.add_device("WA constant".to_owned(), Box::new(wa_const)).unwrap();
+        _ = controller.add_device("WV constant".to_owned(), Box::new(wv_const)).unwrap();
         
         // Add connections
         _ = controller.add_connection(
@@ -343,22 +1594,49 @@ mod tests {
         assert!(result.is_ok());
     }
     
+    #[test]
+    fn controller_passes_values_wider_than_64_bits() {
+        let mut controller = Controller::new();
+        let value = PortValue::from_limbs(&[0x1234, 0x5678]);
+        let constant = Constant::with_width("qq".to_owned(), 128, value.clone()).unwrap();
+        controller.add_device("Constant".to_owned(), Box::new(constant)).unwrap();
+        let sequencer = Sequencer::with_width("qq".to_owned(), 1, &[PortValue::from(1u32)]);
+        controller.add_device("WE".to_owned(), Box::new(sequencer.unwrap())).unwrap();
+        let memory = Memory::with_widths(128, 128).unwrap();
+        controller.add_device("Memory".to_owned(), Box::new(memory)).unwrap();
+        for port in ["ra", "wa", "wv"] {
+            controller.add_connection(
+                &"Constant".to_owned(), &"qq".to_owned(),
+                &"Memory".to_owned(), &port.to_owned(),
+            ).unwrap();
+        }
+        controller.add_connection(
+            &"WE".to_owned(), &"qq".to_owned(),
+            &"Memory".to_owned(), &"we".to_owned(),
+        ).unwrap();
+
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name("Memory", "rv"), Some(PortValue::ZERO));
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name("Memory", "rv"), Some(value));
+    }
+
     #[test]
     fn controller_passes_values_and_performs_ticks_on_devices() {
         let mut controller = Controller::new();
         let memory = Memory::new();
-        let ra_const = Constant::new("qq".to_owned(), 1);
-        let we_const = Constant::new("qq".to_owned(), 1);
-        let wa_const = Constant::new("qq".to_owned(), 1);
-        let written_value: PortValue = 5;
-        let wv_const = Constant::new("qq".to_owned(), written_value);
+        let ra_const = Constant::new("qq".to_owned(), PortValue::from(1u32));
+        let we_const = Constant::with_width("qq".to_owned(), 1, PortValue::from(1u32)).unwrap();
+        let wa_const = Constant::new("qq".to_owned(), PortValue::from(1u32));
+        let written_value = PortValue::from(5u32);
+        let wv_const = Constant::new("qq".to_owned(), written_value.clone());
 
         // Add devices
-        _ = controller.add_device("Memory".to_owned(), Box::new(memory));
-        _ = controller.add_device("RA constant".to_owned(), Box::new(ra_const));
-        _ = controller.add_device("WE constant".to_owned(), Box::new(we_const));
-        _ = controller.add_device("WA constant".to_owned(), Box::new(wa_const));
-        _ = controller.add_device("WV constant".to_owned(), Box::new(wv_const));
+        _ = controller.add_device("Memory".to_owned(), Box::new(memory)).unwrap();
+        _ = controller.add_device("RA constant".to_owned(), Box::new(ra_const)).unwrap();
+        _ = controller.add_device("WE constant".to_owned(), Box::new(we_const)).unwrap();
+        _ = controller.add_device("WA constant".to_owned(), Box::new(wa_const)).unwrap();
+        _ = controller.add_device("WV constant".to_owned(), Box::new(wv_const)).unwrap();
 
         // Add connections
         _ = controller.add_connection(
@@ -379,13 +1657,469 @@ mod tests {
         ).unwrap();
         
         // After the first tick, memory reads 0 because it hasn't been written to yet
-        let result = controller.tick().unwrap();
-        assert!(result.contains_key(&("Memory".to_owned(), "rv".to_owned())));
-        assert_eq!(result.get(&("Memory".to_owned(), "rv".to_owned())), Some(&0));
+        controller.tick().unwrap();
+        assert!(controller.port_value_by_name("Memory", "rv").is_some());
+        assert_eq!(controller.port_value_by_name("Memory", "rv"), Some(PortValue::from(0u32)));
         
         // After the second tick, it should read 1 because we have written that value to it
-        let result = controller.tick().unwrap();
-        assert!(result.contains_key(&("Memory".to_owned(), "rv".to_owned())));
-        assert_eq!(result.get(&("Memory".to_owned(), "rv".to_owned())), Some(&written_value));
+        controller.tick().unwrap();
+        assert!(controller.port_value_by_name("Memory", "rv").is_some());
+        assert_eq!(controller.port_value_by_name("Memory", "rv"), Some(written_value));
+    }
+
+    /// Drive each input of the [`Memory`] with the given id from a new [`Constant`] device.
+    fn connect_constant_memory_inputs(
+        controller: &mut Controller,
+        id: &str,
+        ra: u64, we: u64, wa: u64, wv: u64,
+    ) {
+        // Write enable is the only input narrower than the default width
+        let inputs = [("ra", 32, ra), ("we", 1, we), ("wa", 32, wa), ("wv", 32, wv)];
+        for (port, width, value) in inputs {
+            let const_id = format!("{id} {port} constant");
+            let value = PortValue::from(value);
+            let constant = Constant::with_width("qq".to_owned(), width, value).unwrap();
+            controller.add_device(const_id.clone(), Box::new(constant)).unwrap();
+            controller.add_connection(
+                &const_id, &"qq".to_owned(),
+                &id.to_owned(), &port.to_owned(),
+            ).unwrap();
+        }
+    }
+
+    #[test]
+    fn controller_rolls_back_failed_tick_so_it_can_be_retried() {
+        let mut controller = Controller::new();
+        let written_value = 5;
+        controller.add_device("Written".to_owned(), Box::new(Memory::new())).unwrap();
+        connect_constant_memory_inputs(&mut controller, "Written", 1, 1, 1, written_value);
+
+        // This memory has nothing connected to it, so the tick fails partway through, after
+        // "Written" has been provided its inputs
+        controller.add_device("Unconnected".to_owned(), Box::new(Memory::new())).unwrap();
+        let result = controller.tick();
+        assert!(matches!(
+            result,
+            Err(ControllerError::UnconnectedInput { device, .. }) if device == "Unconnected"
+        ));
+
+        // Wire up the unconnected memory, after which the tick can be retried
+        connect_constant_memory_inputs(&mut controller, "Unconnected", 0, 0, 0, 0);
+
+        // The failed tick must not have written to memory, so it still reads 0 the first time
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name("Written", "rv"), Some(PortValue::from(0u32)));
+        controller.tick().unwrap();
+        let written_value = PortValue::from(written_value);
+        assert_eq!(controller.port_value_by_name("Written", "rv"), Some(written_value));
+    }
+
+    /// A device that passes its input straight through, but fails to resolve its output if the
+    /// input is non-zero, for testing devices that reject values given to them.
+    struct TestPicky {
+        ports: [PortDescriptor; 2],
+        provided: Option<PortValue>,
+    }
+
+    impl TestPicky {
+        fn new() -> TestPicky {
+            TestPicky {
+                ports: [PortDescriptor::input("in", 32), PortDescriptor::output("out", 32)],
+                provided: None,
+            }
+        }
+    }
+
+    impl Device for TestPicky {
+        fn get_ports(&self) -> &[PortDescriptor] {
+            &self.ports
+        }
+
+        fn get_output_dependencies(&self, _: PortId) -> Result<HashSet<PortId>, DeviceError> {
+            Ok(HashSet::from([PortId(0)]))
+        }
+
+        fn provide_port_value(&mut self, _: PortId, value: PortValue) -> Result<(), DeviceError> {
+            self.provided = Some(value);
+            Ok(())
+        }
+
+        fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+            -> Result<(), DeviceError>
+        {
+            values.into_iter().try_for_each(|(port, value)| self.provide_port_value(port, value))
+        }
+
+        fn get_port_value(&self, _: PortId) -> Result<Option<PortValue>, DeviceError> {
+            match &self.provided {
+                Some(value) if !value.is_zero() => Err(DeviceError::DeviceSpecific {
+                    message: "input must be zero".to_owned(),
+                }),
+                value => Ok(value.clone()),
+            }
+        }
+
+        fn clear_port_values(&mut self) {
+            self.provided = None;
+        }
+
+        fn check_tick(&self) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn tick(&mut self) -> Result<(), DeviceError> {
+            self.clear_port_values();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn controller_rolls_back_tick_when_a_device_rejects_its_inputs() {
+        let mut controller = Controller::new();
+        controller.add_device("Written".to_owned(), Box::new(Memory::new())).unwrap();
+        connect_constant_memory_inputs(&mut controller, "Written", 1, 1, 1, 5);
+        controller.add_device("Picky".to_owned(), Box::new(TestPicky::new())).unwrap();
+        let one = Constant::new("qq".to_owned(), PortValue::from(1u32));
+        controller.add_device("One".to_owned(), Box::new(one)).unwrap();
+        controller.add_connection(
+            &"One".to_owned(), &"qq".to_owned(),
+            &"Picky".to_owned(), &"in".to_owned(),
+        ).unwrap();
+
+        let result = controller.tick();
+        assert!(matches!(
+            result,
+            Err(ControllerError::Device { device, source: DeviceError::DeviceSpecific { .. } })
+                if device == "Picky"
+        ));
+
+        // Once the offending input is removed the tick can be retried, and the failed tick
+        // didn't write to memory
+        controller.remove_connection(
+            &"One".to_owned(), &"qq".to_owned(),
+            &"Picky".to_owned(), &"in".to_owned(),
+        ).unwrap();
+        controller.tick().unwrap();
+        assert_eq!(controller.port_value_by_name("Written", "rv"), Some(PortValue::from(0u32)));
+    }
+
+    /// A two-input multiplexer whose output only depends on the input currently selected, for
+    /// testing dynamic dependencies.
+    struct TestMux {
+        ports: [PortDescriptor; 4],
+        provided: [Option<PortValue>; 3],
+    }
+
+    impl TestMux {
+        const A: PortId = PortId(0);
+        const B: PortId = PortId(1);
+        const SEL: PortId = PortId(2);
+        const OUT: PortId = PortId(3);
+
+        fn new() -> TestMux {
+            TestMux {
+                ports: [
+                    PortDescriptor::input("a", 32),
+                    PortDescriptor::input("b", 32),
+                    PortDescriptor::input("sel", 32),
+                    PortDescriptor::output("out", 32),
+                ],
+                provided: Default::default(),
+            }
+        }
+
+        /// The input currently selected, if the select input has been provided.
+        fn selected(&self) -> Option<PortId> {
+            self.provided[Self::SEL.0].as_ref().map(|sel| match sel.bit(0) {
+                true => Self::B,
+                false => Self::A,
+            })
+        }
+    }
+
+    impl Device for TestMux {
+        fn get_ports(&self) -> &[PortDescriptor] {
+            &self.ports
+        }
+
+        fn get_output_dependencies(&self, _: PortId) -> Result<HashSet<PortId>, DeviceError> {
+            Ok(HashSet::from([Self::A, Self::B, Self::SEL]))
+        }
+
+        fn has_dynamic_dependencies(&self) -> bool {
+            true
+        }
+
+        fn get_current_output_dependencies(&self, _: PortId, deps: &mut Vec<PortId>)
+            -> Result<(), DeviceError>
+        {
+            deps.clear();
+            deps.extend([Some(Self::SEL), self.selected()].into_iter().flatten());
+            Ok(())
+        }
+
+        fn provide_port_value(&mut self, port: PortId, value: PortValue)
+            -> Result<(), DeviceError>
+        {
+            self.provided[port.0] = Some(value);
+            Ok(())
+        }
+
+        fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+            -> Result<(), DeviceError>
+        {
+            values.into_iter().try_for_each(|(port, value)| self.provide_port_value(port, value))
+        }
+
+        fn get_port_value(&self, _: PortId) -> Result<Option<PortValue>, DeviceError> {
+            Ok(self.selected().and_then(|selected| self.provided[selected.0].clone()))
+        }
+
+        fn clear_port_values(&mut self) {
+            self.provided = Default::default();
+        }
+
+        fn check_tick(&self) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn tick(&mut self) -> Result<(), DeviceError> {
+            self.clear_port_values();
+            Ok(())
+        }
+    }
+
+    /// Add a [`TestMux`] whose output is fed back into its `b` input, with `a` driven by a
+    /// constant 5 and `sel` by a constant `sel`.
+    fn add_mux_with_feedback(controller: &mut Controller, sel: u64) {
+        controller.add_device("Mux".to_owned(), Box::new(TestMux::new())).unwrap();
+        let a = Constant::new("qq".to_owned(), PortValue::from(5u32));
+        controller.add_device("A".to_owned(), Box::new(a)).unwrap();
+        let sel = Constant::new("qq".to_owned(), PortValue::from(sel));
+        controller.add_device("Sel".to_owned(), Box::new(sel)).unwrap();
+
+        let connections = [("A", "qq", "Mux", "a"), ("Sel", "qq", "Mux", "sel"),
+            ("Mux", "out", "Mux", "b")];
+        for (from, from_port, to, to_port) in connections {
+            controller.add_connection(
+                &from.to_owned(), &from_port.to_owned(),
+                &to.to_owned(), &to_port.to_owned(),
+            ).unwrap();
+        }
+    }
+
+    #[test]
+    fn controller_allows_feedback_through_unselected_dynamic_dependencies() {
+        let mut controller = Controller::new();
+        add_mux_with_feedback(&mut controller, 0);
+
+        controller.tick().unwrap();
+        let mux = controller.device_id("Mux").unwrap();
+        assert_eq!(controller.port_value(mux, TestMux::OUT), Some(PortValue::from(5u32)));
+        assert_eq!(controller.port_value(mux, TestMux::B), Some(PortValue::from(5u32)));
+    }
+
+    #[test]
+    fn controller_detects_combinational_loops_through_selected_dynamic_dependencies() {
+        let mut controller = Controller::new();
+        add_mux_with_feedback(&mut controller, 1);
+
+        let result = controller.tick();
+        assert_eq!(result.err(), Some(ControllerError::CombinationalLoop {
+            path: vec![
+                ("Mux".to_owned(), "b".to_owned()),
+                ("Mux".to_owned(), "out".to_owned()),
+                ("Mux".to_owned(), "b".to_owned()),
+            ],
+        }));
+    }
+
+    #[test]
+    fn controller_still_rejects_static_cycles_alongside_dynamic_dependencies() {
+        let mut controller = Controller::new();
+        add_mux_with_feedback(&mut controller, 0);
+        controller.add_device("Memory".to_owned(), Box::new(Memory::new())).unwrap();
+        controller.add_connection(
+            &"Mux".to_owned(), &"out".to_owned(),
+            &"Memory".to_owned(), &"ra".to_owned(),
+        ).unwrap();
+
+        // The loop back into the mux goes through its select input, which is always needed
+        controller.remove_connection(
+            &"Sel".to_owned(), &"qq".to_owned(),
+            &"Mux".to_owned(), &"sel".to_owned(),
+        ).unwrap();
+        let result = controller.add_connection(
+            &"Memory".to_owned(), &"rv".to_owned(),
+            &"Mux".to_owned(), &"sel".to_owned(),
+        );
+        assert!(matches!(result, Err(ControllerError::Cycle { .. })));
+    }
+
+    /// A two-input, one-bit NOR gate, for testing feedback loops. Its output is known if either
+    /// input is known to be 1, even if the other isn't.
+    struct TestNor {
+        ports: [PortDescriptor; 3],
+        provided: [Option<PortValue>; 2],
+    }
+
+    impl TestNor {
+        fn new() -> TestNor {
+            TestNor {
+                ports: [
+                    PortDescriptor::input("a", 1),
+                    PortDescriptor::input("b", 1),
+                    PortDescriptor::output("out", 1),
+                ],
+                provided: Default::default(),
+            }
+        }
+    }
+
+    impl Device for TestNor {
+        fn get_ports(&self) -> &[PortDescriptor] {
+            &self.ports
+        }
+
+        fn get_output_dependencies(&self, _: PortId) -> Result<HashSet<PortId>, DeviceError> {
+            Ok(HashSet::from([PortId(0), PortId(1)]))
+        }
+
+        fn provide_port_value(&mut self, port: PortId, value: PortValue)
+            -> Result<(), DeviceError>
+        {
+            self.provided[port.0] = Some(value);
+            Ok(())
+        }
+
+        fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+            -> Result<(), DeviceError>
+        {
+            values.into_iter().try_for_each(|(port, value)| self.provide_port_value(port, value))
+        }
+
+        fn get_port_value(&self, _: PortId) -> Result<Option<PortValue>, DeviceError> {
+            let [a, b] = &self.provided;
+            let is_one = |input: &Option<PortValue>| input.as_ref().map(|value| value.bit(0));
+            Ok(match (is_one(a), is_one(b)) {
+                (Some(true), _) | (_, Some(true)) => Some(PortValue::from(0u32)),
+                (Some(false), Some(false)) => Some(PortValue::from(1u32)),
+                _ => None,
+            })
+        }
+
+        fn clear_port_values(&mut self) {
+            self.provided = Default::default();
+        }
+
+        fn check_tick(&self) -> Result<(), DeviceError> {
+            Ok(())
+        }
+
+        fn tick(&mut self) -> Result<(), DeviceError> {
+            self.clear_port_values();
+            Ok(())
+        }
+    }
+
+    /// Connect the output of one device to the input of another, by name.
+    fn connect(controller: &mut Controller, from: (&str, &str), to: (&str, &str))
+        -> Result<(), ControllerError>
+    {
+        controller.add_connection(
+            &from.0.to_owned(), &from.1.to_owned(),
+            &to.0.to_owned(), &to.1.to_owned(),
+        )
+    }
+
+    /// Add a one-bit [`Sequencer`] outputting the given values.
+    fn add_bit_sequencer(controller: &mut Controller, id: &str, bits: &[u32]) {
+        let values: Vec<PortValue> = bits.iter().map(|bit| PortValue::from(*bit)).collect();
+        let sequencer = Sequencer::with_width("qq".to_owned(), 1, &values).unwrap();
+        controller.add_device(id.to_owned(), Box::new(sequencer)).unwrap();
+    }
+
+    /// Build an SR latch from two cross-coupled [`TestNor`]s, with the set and reset inputs
+    /// driven by sequencers outputting the given values.
+    fn add_sr_latch(controller: &mut Controller, set: &[u32], reset: &[u32])
+        -> Result<(), ControllerError>
+    {
+        add_bit_sequencer(controller, "S", set);
+        add_bit_sequencer(controller, "R", reset);
+        controller.add_device("Q".to_owned(), Box::new(TestNor::new())).unwrap();
+        controller.add_device("Qbar".to_owned(), Box::new(TestNor::new())).unwrap();
+        connect(controller, ("R", "qq"), ("Q", "a"))?;
+        connect(controller, ("S", "qq"), ("Qbar", "a"))?;
+        connect(controller, ("Q", "out"), ("Qbar", "b"))?;
+        connect(controller, ("Qbar", "out"), ("Q", "b"))
+    }
+
+    #[test]
+    fn controller_rejects_feedback_loops_unless_allowed() {
+        let mut controller = Controller::new();
+        let result = add_sr_latch(&mut controller, &[0], &[0]);
+        assert!(matches!(result, Err(ControllerError::Cycle { .. })));
+
+        let mut controller = Controller::new();
+        controller.allow_feedback(10);
+        add_sr_latch(&mut controller, &[0], &[0]).unwrap();
+    }
+
+    #[test]
+    fn controller_iterates_feedback_loops_to_a_fixed_point() {
+        let mut controller = Controller::new();
+        controller.allow_feedback(10);
+        add_sr_latch(&mut controller, &[1, 0, 0, 0], &[0, 0, 1, 0]).unwrap();
+
+        // Set, hold, reset, hold
+        for expected in [1u32, 1, 0, 0] {
+            controller.tick().unwrap();
+            assert_eq!(controller.port_value_by_name("Q", "out"), Some(PortValue::from(expected)));
+            let expected = PortValue::from(expected ^ 1);
+            assert_eq!(controller.port_value_by_name("Qbar", "out"), Some(expected));
+        }
+    }
+
+    #[test]
+    fn controller_reports_feedback_loops_that_never_settle() {
+        let mut controller = Controller::new();
+        controller.allow_feedback(10);
+
+        // A NOR gate feeding back into itself is an inverter, once its other input is 0
+        add_bit_sequencer(&mut controller, "Enable", &[1, 0]);
+        controller.add_device("Nor".to_owned(), Box::new(TestNor::new())).unwrap();
+        connect(&mut controller, ("Enable", "qq"), ("Nor", "a")).unwrap();
+        connect(&mut controller, ("Nor", "out"), ("Nor", "b")).unwrap();
+
+        controller.tick().unwrap();
+        let result = controller.tick();
+        assert_eq!(result.err(), Some(ControllerError::Oscillation {
+            ports: vec![
+                ("Nor".to_owned(), "b".to_owned()),
+                ("Nor".to_owned(), "out".to_owned()),
+            ],
+            iterations: 10,
+        }));
+
+        // The failed tick is rolled back, so the oscillator keeps its last settled value
+        assert_eq!(controller.port_value_by_name("Nor", "out"), Some(PortValue::from(0u32)));
+    }
+
+    #[test]
+    fn controller_accepts_loops_through_registers() {
+        let mut controller = Controller::new();
+        controller.add_device("Register".to_owned(), Box::new(Register::new())).unwrap();
+        add_bit_sequencer(&mut controller, "Enable", &[0]);
+        add_bit_sequencer(&mut controller, "Reset", &[1, 0]);
+        connect(&mut controller, ("Enable", "qq"), ("Register", "en")).unwrap();
+        connect(&mut controller, ("Reset", "qq"), ("Register", "rst")).unwrap();
+
+        // The register's output doesn't depend on its input, so this isn't a cycle
+        connect(&mut controller, ("Register", "q"), ("Register", "d")).unwrap();
+
+        for _ in 0..2 {
+            let state = controller.tick().unwrap();
+            assert_eq!(state.value_by_name("Register", "d"), Some(&PortValue::ZERO));
+        }
     }
 }
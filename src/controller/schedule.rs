@@ -0,0 +1,130 @@
+use std::ops::Range;
+use petgraph::algo::{tarjan_scc, toposort};
+use petgraph::graph::NodeIndex;
+use petgraph::stable_graph::StableDiGraph;
+use petgraph::visit::NodeIndexable;
+use petgraph::Direction;
+use crate::controller::{DeviceEntry, DeviceId, EdgeType};
+use crate::device::{PortDirection, PortId};
+
+/// How to resolve the value of a single port during a tick.
+pub(super) enum StepKind {
+    /// Read the value of an output port from its device, masking it to the port's width.
+    Output { width: u32 },
+
+    /// Pass the value of the output ports driving this input port to the input port's device.
+    /// `drivers` is empty if nothing is connected to the input port, and only ever has more than
+    /// one entry when using four-state logic.
+    Input { width: u32, drivers: Vec<NodeIndex> },
+}
+
+/// The work needed to resolve the value of a single port during a tick, with everything needed to
+/// do it already looked up.
+pub(super) struct Step {
+    /// The port's node in the dependency graph.
+    pub(super) node: NodeIndex,
+    pub(super) device: DeviceId,
+    pub(super) port: PortId,
+    pub(super) kind: StepKind,
+}
+
+/// How the steps of a [`Schedule`] are ordered, and so how to go through them during a tick.
+pub(super) enum Order {
+    /// The steps are in topological order, so each can be resolved once, in turn.
+    Topological,
+
+    /// The dependency graph has loops through conditional dependencies, so there's no fixed order
+    /// to resolve ports in, and they are resolved as they are needed during the tick instead.
+    /// `step_of_node` holds the index of each node's step.
+    OnDemand { step_of_node: Vec<usize> },
+
+    /// The dependency graph has feedback loops, which are resolved by iterating to a fixed point.
+    /// The steps of each strongly connected component are kept together, with the components in
+    /// topological order, and `loops` holds the range of steps of every component with more than
+    /// one port.
+    FixedPoint { loops: Vec<Range<usize>> },
+}
+
+/// A precompiled order in which to resolve every port in a circuit during a tick.
+///
+/// Working this out means sorting the whole dependency graph and asking every device about its
+/// ports, none of which changes from one tick to the next unless the circuit itself is changed.
+/// So a [`Controller`](super::Controller) compiles a schedule once, and keeps using it until its
+/// topology changes.
+pub(super) struct Schedule {
+    /// Every port in the dependency graph, in the order described by `order`.
+    pub(super) steps: Vec<Step>,
+
+    pub(super) order: Order,
+
+    /// One more than the largest node index in the dependency graph, for sizing storage indexed
+    /// by node.
+    pub(super) node_bound: usize,
+}
+
+impl Schedule {
+    /// Compile a schedule for the given circuit. `feedback` says whether the circuit is allowed
+    /// to have feedback loops (see [`super::Controller::allow_feedback`]); otherwise loops are
+    /// only allowed through conditional dependencies.
+    pub(super) fn compile(
+        dependencies: &StableDiGraph<(DeviceId, PortId), EdgeType>,
+        devices: &[Option<DeviceEntry>],
+        feedback: bool,
+    ) -> Schedule {
+        let (nodes, mut order) = match toposort(dependencies, None) {
+            Ok(nodes) => (nodes, Order::Topological),
+            Err(_) if feedback => {
+                // Components come out of this in reverse topological order
+                let mut nodes = Vec::new();
+                let mut loops = Vec::new();
+                for component in tarjan_scc(dependencies).into_iter().rev() {
+                    if component.len() > 1 {
+                        loops.push(nodes.len()..nodes.len() + component.len());
+                    }
+                    nodes.extend(component);
+                }
+                (nodes, Order::FixedPoint { loops })
+            }
+            Err(_) => (
+                dependencies.node_indices().collect(),
+                Order::OnDemand { step_of_node: Vec::new() },
+            ),
+        };
+
+        let steps: Vec<Step> = nodes.into_iter()
+            .map(|node| {
+                let (device, port) = dependencies[node];
+                let entry = devices[device.0].as_ref()
+                    .expect("Device handle retrieved from the dependency graph should always \
+                    refer to a device");
+                let descriptor = &entry.device.get_ports()[port.0];
+                let kind = match descriptor.direction {
+                    PortDirection::Output => StepKind::Output { width: descriptor.width },
+                    // Only connections arrive at an input port, so every incoming neighbour is
+                    // a driver
+                    PortDirection::Input => StepKind::Input {
+                        width: descriptor.width,
+                        drivers: dependencies
+                            .neighbors_directed(node, Direction::Incoming)
+                            .collect(),
+                    },
+                };
+                Step { node, device, port, kind }
+            })
+            .collect();
+
+        let node_bound = dependencies.node_bound();
+        if let Order::OnDemand { step_of_node } = &mut order {
+            step_of_node.resize(node_bound, 0);
+            for (index, step) in steps.iter().enumerate() {
+                step_of_node[step.node.index()] = index;
+            }
+        }
+
+        Schedule {
+            steps,
+            order,
+            node_bound,
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::fmt;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use crate::controller::{Controller, DeviceId, DeviceIdentifier, EdgeType};
+use crate::device::{PortDirection, PortId, PortIdentifier, PortValue};
+
+/// A read-only view of the values in a circuit as of the most recent successful tick, returned by
+/// [`Controller::tick`] and [`Controller::state`].
+///
+/// Nothing is copied when taking a view, and values are borrowed straight from the controller, so
+/// it is cheap to take one whenever the circuit's values are needed.
+#[derive(Clone, Copy)]
+pub struct CircuitState<'a> {
+    controller: &'a Controller,
+}
+
+/// A single port, along with its value as of the most recent successful tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortState<'a> {
+    pub device: &'a DeviceIdentifier,
+    pub port: &'a PortIdentifier,
+    pub direction: PortDirection,
+
+    /// `None` if the port had no value, or there has not been a successful tick since it was
+    /// added.
+    pub value: Option<&'a PortValue>,
+}
+
+/// A user-defined connection, along with the value it carried in the most recent successful tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionState<'a> {
+    pub from: (&'a DeviceIdentifier, &'a PortIdentifier),
+    pub to: (&'a DeviceIdentifier, &'a PortIdentifier),
+
+    /// The value of the "from" port. With four-state logic, the "to" port may have a different
+    /// value if it has other drivers too.
+    pub value: Option<&'a PortValue>,
+}
+
+impl<'a> CircuitState<'a> {
+    pub(super) fn new(controller: &'a Controller) -> CircuitState<'a> {
+        CircuitState { controller }
+    }
+
+    /// How many ticks have succeeded so far.
+    pub fn tick_count(&self) -> u64 {
+        self.controller.ticks
+    }
+
+    /// Get the value of the given port.
+    ///
+    /// Returns `None` if the device or port is unknown, if the port had no value, or if there has
+    /// not been a successful tick since the port was added.
+    pub fn value(&self, device: DeviceId, port: PortId) -> Option<&'a PortValue> {
+        let node = *self.controller.entry(device)?.nodes.get(port.0)?;
+        self.controller.values.get(node.index())?.as_ref()
+    }
+
+    /// Get the value of the given port, looking the device and port up by their identifiers.
+    pub fn value_by_name(&self, device: &str, port: &str) -> Option<&'a PortValue> {
+        let device = self.controller.device_id(device)?;
+        self.value(device, self.controller.port_id(device, port)?)
+    }
+
+    /// Iterate over every port of every device, in the order the devices were added.
+    pub fn ports(&self) -> impl Iterator<Item = PortState<'a>> + 'a {
+        let state = *self;
+        self.controller.devices.iter()
+            .flatten()
+            .flat_map(move |entry| {
+                entry.device.get_ports().iter().zip(entry.nodes.iter())
+                    .map(move |(descriptor, node)| PortState {
+                        device: &entry.name,
+                        port: &descriptor.name,
+                        direction: descriptor.direction,
+                        value: state.node_value(node.index()),
+                    })
+            })
+    }
+
+    /// Iterate over every user-defined connection, along with the value it carried.
+    pub fn connections(&self) -> impl Iterator<Item = ConnectionState<'a>> + 'a {
+        let state = *self;
+        let dependencies = &self.controller.dependencies;
+        dependencies.edge_indices()
+            .filter(move |edge| matches!(dependencies[*edge], EdgeType::External))
+            .map(move |edge| {
+                let (from_idx, to_idx) = dependencies.edge_endpoints(edge)
+                    .expect("Edge index retrieved from `edge_indices()` should have endpoints");
+                ConnectionState {
+                    from: state.port_names(dependencies[from_idx]),
+                    to: state.port_names(dependencies[to_idx]),
+                    value: state.node_value(from_idx.index()),
+                }
+            })
+    }
+
+    /// Iterate over the connections driven by the given output port, along with the value they
+    /// carried. This is empty if the device or port is unknown.
+    pub fn fan_out(&self, device: DeviceId, port: PortId)
+        -> impl Iterator<Item = ConnectionState<'a>> + 'a
+    {
+        let state = *self;
+        let dependencies = &self.controller.dependencies;
+        let from = self.controller.entry(device)
+            .and_then(|entry| entry.nodes.get(port.0).copied())
+            .filter(|node| dependencies.contains_node(*node));
+        from.into_iter()
+            .flat_map(move |from_idx| {
+                dependencies.edges_directed(from_idx, Direction::Outgoing)
+                    .filter(|edge| matches!(edge.weight(), EdgeType::External))
+                    .map(move |edge| ConnectionState {
+                        from: state.port_names(dependencies[from_idx]),
+                        to: state.port_names(dependencies[edge.target()]),
+                        value: state.node_value(from_idx.index()),
+                    })
+            })
+    }
+
+    fn node_value(&self, index: usize) -> Option<&'a PortValue> {
+        self.controller.values.get(index)?.as_ref()
+    }
+
+    /// Get borrowed identifiers of the given port.
+    fn port_names(&self, (device, port): (DeviceId, PortId))
+        -> (&'a DeviceIdentifier, &'a PortIdentifier)
+    {
+        let entry = self.controller.entry(device)
+            .expect("Device handle retrieved from the dependency graph should always refer to a \
+            device");
+        (&entry.name, &entry.device.get_ports()[port.0].name)
+    }
+}
+
+impl fmt::Debug for CircuitState<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ports = self.ports()
+            .map(|port| (format!("{}.{}", port.device, port.port), port.value));
+        f.debug_map().entries(ports).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::{ConnectionState, Controller, PortState};
+    use crate::device::debug::constant::Constant;
+    use crate::device::memory::Memory;
+    use crate::device::{PortDirection, PortValue};
+
+    /// Build a circuit where a constant 3 drives the read address of two memories, neither of
+    /// which is ever written to.
+    fn fan_out_circuit() -> Controller {
+        let mut controller = Controller::new();
+        let constant = Constant::new("qq".to_owned(), PortValue::from(3u32));
+        controller.add_device("Constant".to_owned(), Box::new(constant)).unwrap();
+        let disabled = Constant::with_width("qq".to_owned(), 1, PortValue::ZERO).unwrap();
+        controller.add_device("Disabled".to_owned(), Box::new(disabled)).unwrap();
+        for memory in ["First", "Second"] {
+            controller.add_device(memory.to_owned(), Box::new(Memory::new())).unwrap();
+            for (from, to) in [("Constant", "ra"), ("Disabled", "we")] {
+                controller.add_connection(
+                    &from.to_owned(), &"qq".to_owned(),
+                    &memory.to_owned(), &to.to_owned(),
+                ).unwrap();
+            }
+        }
+        controller
+    }
+
+    #[test]
+    fn state_reports_values_as_of_the_last_tick() {
+        let mut controller = fan_out_circuit();
+        assert_eq!(controller.state().tick_count(), 0);
+        assert_eq!(controller.state().value_by_name("First", "ra"), None);
+
+        let state = controller.tick().unwrap();
+        assert_eq!(state.tick_count(), 1);
+        assert_eq!(state.value_by_name("First", "ra"), Some(&PortValue::from(3u32)));
+
+        // The values can be looked at again later without ticking
+        let state = controller.state();
+        assert_eq!(state.tick_count(), 1);
+        assert_eq!(state.value_by_name("Second", "ra"), Some(&PortValue::from(3u32)));
+        assert_eq!(state.value_by_name("Second", "qq"), None);
+        assert_eq!(state.value_by_name("Nonexistent", "ra"), None);
+    }
+
+    #[test]
+    fn state_iterates_over_ports_with_their_values() {
+        let mut controller = fan_out_circuit();
+        let state = controller.tick().unwrap();
+        let ports: Vec<PortState> = state.ports().collect();
+
+        // One port on each constant, and five on each memory
+        assert_eq!(ports.len(), 12);
+        assert_eq!(ports[0], PortState {
+            device: &"Constant".to_owned(),
+            port: &"qq".to_owned(),
+            direction: PortDirection::Output,
+            value: Some(&PortValue::from(3u32)),
+        });
+    }
+
+    #[test]
+    fn state_iterates_over_connections_with_their_values() {
+        let mut controller = fan_out_circuit();
+        let three = PortValue::from(3u32);
+        let state = controller.tick().unwrap();
+
+        let mut connections: Vec<ConnectionState> = state.connections()
+            .filter(|connection| *connection.from.0 == "Constant")
+            .collect();
+        connections.sort_by_key(|connection| connection.to);
+        let constant = (&"Constant".to_owned(), &"qq".to_owned());
+        assert_eq!(connections, vec![
+            ConnectionState {
+                from: constant,
+                to: (&"First".to_owned(), &"ra".to_owned()),
+                value: Some(&three),
+            },
+            ConnectionState {
+                from: constant,
+                to: (&"Second".to_owned(), &"ra".to_owned()),
+                value: Some(&three),
+            },
+        ]);
+    }
+
+    #[test]
+    fn state_lists_the_fan_out_of_an_output_port() {
+        let mut controller = fan_out_circuit();
+        controller.tick().unwrap();
+        let state = controller.state();
+
+        let constant = controller.device_id("Constant").unwrap();
+        let mut targets: Vec<_> = state.fan_out(constant, Constant::OUTPUT)
+            .map(|connection| connection.to)
+            .collect();
+        targets.sort();
+        assert_eq!(targets, vec![
+            (&"First".to_owned(), &"ra".to_owned()),
+            (&"Second".to_owned(), &"ra".to_owned()),
+        ]);
+
+        // Memories' read values aren't connected to anything
+        let first = controller.device_id("First").unwrap();
+        assert_eq!(state.fan_out(first, Memory::RV).count(), 0);
+    }
+
+    #[test]
+    fn state_does_not_give_new_ports_the_values_of_removed_ones() {
+        let mut controller = fan_out_circuit();
+        controller.tick().unwrap();
+        controller.remove_device(&"Second".to_owned()).unwrap();
+
+        // The new memory's ports reuse the removed memory's places in the dependency graph
+        controller.add_device("Third".to_owned(), Box::new(Memory::new())).unwrap();
+        assert_eq!(controller.state().value_by_name("Third", "ra"), None);
+    }
+}
@@ -1,87 +1,352 @@
 use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 
 pub mod memory;
+pub mod multi_port_memory;
+pub mod rom;
+pub mod byte_memory;
+pub mod image;
+pub mod dump;
+pub mod register;
+pub mod register_file;
+pub mod alu;
+pub mod logic;
+pub mod mux;
+pub mod bits;
 pub mod debug;
+pub mod value;
+
+pub use value::PortValue;
 
 pub type PortIdentifier = String;
-pub type PortValue = u32;
+
+/// The width, in bits, of ports on devices that don't have their width configured.
+pub const DEFAULT_PORT_WIDTH: u32 = 32;
+
+/// Check that `width` is a valid port width, i.e. at least 1 bit.
+pub fn check_width(width: u32) -> Result<(), DeviceError> {
+    match width >= 1 {
+        true => Ok(()),
+        false => Err(DeviceError::InvalidWidth { width }),
+    }
+}
+
+/// Get the name of the given port out of `ports`, or describe the [`PortId`] itself if there is
+/// no such port.
+fn name_of(ports: &[PortDescriptor], port: PortId) -> PortIdentifier {
+    match ports.get(port.0) {
+        None => port.to_string(),
+        Some(descriptor) => descriptor.name.clone(),
+    }
+}
+
+/// Check that a value can be provided for the given port, out of `ports`, of a device that stores
+/// the values provided to its input ports in `specified`, indexed by [`PortId`].
+fn check_can_provide(ports: &[PortDescriptor], specified: &[Option<PortValue>], port: PortId)
+    -> Result<(), DeviceError>
+{
+    match ports.get(port.0) {
+        None => return Err(DeviceError::UnknownPort { port: name_of(ports, port) }),
+        Some(descriptor) if descriptor.direction == PortDirection::Output =>
+            return Err(DeviceError::NotAnInputPort { port: name_of(ports, port) }),
+        _ => {}
+    }
+    if specified[port.0].is_some() {
+        return Err(DeviceError::AlreadyProvided { port: name_of(ports, port) });
+    }
+    Ok(())
+}
+
+/// Store `value` in `specified` as the value provided for the given input port, out of `ports`,
+/// masked to the port's width. Fails as [`Device::provide_port_value`] should.
+pub(crate) fn provide_into(
+    ports: &[PortDescriptor],
+    specified: &mut [Option<PortValue>],
+    port: PortId,
+    value: PortValue,
+) -> Result<(), DeviceError> {
+    check_can_provide(ports, specified, port)?;
+    specified[port.0] = Some(ports[port.0].mask(&value));
+    Ok(())
+}
+
+/// Store each of `values` in `specified` as [`provide_into`] does. Fails as
+/// [`Device::provide_port_values`] should, without storing any of them.
+pub(crate) fn provide_all_into(
+    ports: &[PortDescriptor],
+    specified: &mut [Option<PortValue>],
+    values: HashMap<PortId, PortValue>,
+) -> Result<(), DeviceError> {
+    for port in values.keys() {
+        check_can_provide(ports, specified, *port)?;
+    }
+    for (port, value) in values {
+        specified[port.0] = Some(ports[port.0].mask(&value));
+    }
+    Ok(())
+}
+
+/// Work out the error to return when the given port, out of `ports`, is used somewhere only an
+/// output port is valid.
+pub(crate) fn output_port_error(ports: &[PortDescriptor], port: PortId) -> DeviceError {
+    match ports.get(port.0) {
+        Some(descriptor) if descriptor.direction == PortDirection::Input =>
+            DeviceError::NotAnOutputPort { port: name_of(ports, port) },
+        _ => DeviceError::UnknownPort { port: name_of(ports, port) },
+    }
+}
+
+/// A handle to a port on a particular device: the port's index in the slice returned by that
+/// device's [`Device::get_ports`].
+///
+/// Devices are driven through these rather than through [`PortIdentifier`]s, so that passing
+/// values around during a tick is just indexing. Port names are only used for lookup (see
+/// [`Device::find_port`]) and display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PortId(pub usize);
+
+impl fmt::Display for PortId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Whether a port has values provided to it from outside the device, or provides values to the
+/// outside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PortDirection {
+    Input,
+    Output,
+}
+
+/// Describes a single port on a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortDescriptor {
+    pub name: PortIdentifier,
+    pub direction: PortDirection,
+
+    /// How many bits wide the port is. Only the lowest `width` bits of a value on this port are
+    /// meaningful; any others are masked off.
+    pub width: u32,
+}
+
+impl PortDescriptor {
+    pub fn input(name: &str, width: u32) -> PortDescriptor {
+        PortDescriptor { name: name.to_owned(), direction: PortDirection::Input, width }
+    }
+
+    pub fn output(name: &str, width: u32) -> PortDescriptor {
+        PortDescriptor { name: name.to_owned(), direction: PortDirection::Output, width }
+    }
+
+    /// Mask `value` down to this port's width.
+    pub fn mask(&self, value: &PortValue) -> PortValue {
+        value.mask(self.width)
+    }
+}
 
 /// Represents an error that can be thrown by methods in the [`Device`] trait.
 ///
-/// Not yet fleshed out. In future these should be much more comprehensive.
-#[derive(Debug)]
-pub struct DeviceError;
+/// Each variant carries enough context (the offending port, and a message where the reason isn't
+/// obvious from the variant alone) to work out which part of a circuit went wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceError {
+    /// The given port is not known by this device.
+    UnknownPort { port: PortIdentifier },
+
+    /// A value was provided for a port that is not an input port.
+    NotAnInputPort { port: PortIdentifier },
+
+    /// An output-only operation (such as reading a value or asking for dependencies) was
+    /// attempted on a port that is not an output port.
+    NotAnOutputPort { port: PortIdentifier },
+
+    /// A value was provided for an input port that has already had its value provided this tick.
+    AlreadyProvided { port: PortIdentifier },
+
+    /// The device cannot resolve because the value of an input port it needs was not provided.
+    MissingInput { port: PortIdentifier, message: String },
+
+    /// A device was configured with a port width of zero.
+    InvalidWidth { width: u32 },
+
+    /// Any other failure particular to a specific kind of device.
+    DeviceSpecific { message: String },
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::UnknownPort { port } => write!(f, "unknown port `{port}`"),
+            DeviceError::NotAnInputPort { port } => write!(f, "port `{port}` is not an input port"),
+            DeviceError::NotAnOutputPort { port } =>
+                write!(f, "port `{port}` is not an output port"),
+            DeviceError::AlreadyProvided { port } =>
+                write!(f, "a value has already been provided for port `{port}` this tick"),
+            DeviceError::MissingInput { port, message } =>
+                write!(f, "missing value for input port `{port}`: {message}"),
+            DeviceError::InvalidWidth { width } =>
+                write!(f, "invalid port width {width}: must be at least 1 bit"),
+            DeviceError::DeviceSpecific { message } => write!(f, "{message}"),
+        }
+    }
+}
+
+impl Error for DeviceError {}
 
 /// Represents a device in a circuit.
-/// 
-/// More of a note to myself than anything (so if you're reading this, you're either me, or I forgot
-/// to delete it): I'm going to write this under a couple of assumptions that I know are not
-/// correct. I'm doing this because I've been prematurely optimising this for ages now, and I just
-/// want to get _something_ down, but I can't bear to write code that I consider suboptimal... so
-/// the only option is to simplify the problem.
-/// 
-/// **Incorrect simplifying assumption 1**: A device must have all of its input ports specified in
-/// order to resolve every tick. This makes logic easier, but is a "sufficient but not necessary"
-/// constraint; sometimes certain inputs don't need to be known for the device to know what to do,
-/// and by enforcing this constraint we disallow circuit designs that make use of that.
-/// 
-/// **Incorrect simplifying assumption 2**: Output dependencies don't change. Again, this allows for
-/// simplifying logic out on the controller level because we don't need to rebuild the dependency
-/// graph, but it disallows more complicated circuit designs that would nonetheless be valid and
-/// resolvable.
+///
+/// A device doesn't need every one of its input ports to be provided in order to resolve or tick:
+/// inputs may be left unconnected, or be driven by outputs that can't be resolved this tick. It is
+/// up to each device to work out what it can from the inputs it has been given (see
+/// [`Device::get_port_value`]), and to fail [`Device::check_tick`] only if it is missing an input
+/// it actually needs.
+///
+/// Which inputs an output depends on can also change from tick to tick. A multiplexer's output, for
+/// example, only depends on its select input and whichever input is selected. Such a device says so
+/// with [`Device::has_dynamic_dependencies`], and a
+/// [`Controller`](crate::controller::Controller) then asks it which inputs it currently needs
+/// (see [`Device::get_current_output_dependencies`]) as the tick goes on. This allows feedback
+/// through inputs that aren't currently in use, which would otherwise be rejected as a loop.
 pub trait Device {
-    /// Get a [`HashSet`] containing the identifiers of all input ports on this device.
-    fn get_input_ports(&self) -> HashSet<PortIdentifier>;
+    /// Get descriptions of every port on this device. The [`PortId`] of each port is its index in
+    /// this slice.
+    ///
+    /// This must not change over the lifetime of the device.
+    fn get_ports(&self) -> &[PortDescriptor];
 
-    /// Get a [`HashSet`] containing the identifiers of all input ports on this device.
-    fn get_output_ports(&self) -> HashSet<PortIdentifier>;
+    /// Find the port with the given name, if this device has one.
+    fn find_port(&self, name: &str) -> Option<PortId> {
+        self.get_ports().iter().position(|port| port.name == name).map(PortId)
+    }
+
+    /// Get the name of the given port, for use in errors and display.
+    ///
+    /// If this device has no such port, the [`PortId`] itself is described instead.
+    fn port_name(&self, port: PortId) -> PortIdentifier {
+        name_of(self.get_ports(), port)
+    }
     
-    /// Get a [`HashSet`] containing the identifiers of all input ports required to resolve the
-    /// given output port.
+    /// Get a [`HashSet`] containing the handles of all input ports that might be needed to resolve
+    /// the given output port.
     /// 
     /// Fails if:
-    /// * The provided port is unknown
-    /// * The provided port is not an output port
+    /// * The provided port is unknown ([`DeviceError::UnknownPort`])
+    /// * The provided port is not an output port ([`DeviceError::NotAnOutputPort`])
     /// 
-    /// Following on from the (incorrect) assumptions in the description of [`Device`], this should
-    /// return a complete set of the dependencies of the provided output; in other words, if all
-    /// the input ports returned by this function are provided to the device, the output should be
-    /// guaranteed to be known.
-    fn get_output_dependencies(&self, output: &PortIdentifier)
-        -> Result<HashSet<PortIdentifier>, DeviceError>;
+    /// This should return a complete set of the dependencies of the provided output, whatever the
+    /// values of the device's inputs; in other words, if all the input ports returned by this
+    /// function are provided to the device, the output should be guaranteed to be known. The
+    /// output may well be known with fewer of them (a multiplexer only needs its selected input,
+    /// for example). The result must not change over the lifetime of the device.
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError>;
+
+    /// Whether the inputs that this device's outputs depend on can change depending on the values
+    /// of other inputs. If so, [`Device::get_current_output_dependencies`] should be implemented
+    /// to say which inputs are needed at any given point in a tick.
+    fn has_dynamic_dependencies(&self) -> bool {
+        false
+    }
+
+    /// Replace the contents of `deps` with the handles of the input ports needed to resolve the
+    /// given output port, given the input port values provided so far this tick. This fills a
+    /// buffer rather than returning a new set, so that a
+    /// [`Controller`](crate::controller::Controller) can reuse it without allocating each tick.
+    ///
+    /// The ports filled in must be a subset of [`Device::get_output_dependencies`], and this fails
+    /// in the same circumstances. Before anything has been provided, it should contain whichever
+    /// inputs decide what else is needed (the select input of a multiplexer, say); as those are
+    /// provided, it should grow to include the inputs they pick out. Once every port in the set
+    /// has been provided (or is known to have no value this tick) and the set stays the same, the
+    /// output is resolved, and providing any other inputs afterwards must not change its value.
+    ///
+    /// The set filled in before anything has been provided is taken to be needed whatever the
+    /// input values, so connections that would loop back into it are still rejected.
+    ///
+    /// Only devices with [`Device::has_dynamic_dependencies`] need to implement this; by default
+    /// it is the same as [`Device::get_output_dependencies`].
+    fn get_current_output_dependencies(&self, output: PortId, deps: &mut Vec<PortId>)
+        -> Result<(), DeviceError>
+    {
+        let all_deps = self.get_output_dependencies(output)?;
+        deps.clear();
+        deps.extend(all_deps);
+        Ok(())
+    }
 
     /// Provide a single value for an input port to this device.
     ///
     /// Should fail in the following circumstances:
     /// * A value is provided for a port whose value has already been provided
+    ///   ([`DeviceError::AlreadyProvided`])
     /// * A value is provided for an output port (only input ports have values provided from
-    ///   outside)
-    /// * A value is provided for an unknown port
-    fn provide_port_value(&mut self, port: PortIdentifier, value: PortValue)
-        -> Result<(), DeviceError>;
+    ///   outside) ([`DeviceError::NotAnInputPort`])
+    /// * A value is provided for an unknown port ([`DeviceError::UnknownPort`])
+    ///
+    /// Any bits of the value beyond the port's width are ignored.
+    fn provide_port_value(&mut self, port: PortId, value: PortValue) -> Result<(), DeviceError>;
     
     /// Provide a set of values for input ports to this device.
     /// 
     /// Should fail in the following circumstances:
     /// * A value is provided for a port whose value has already been provided
+    ///   ([`DeviceError::AlreadyProvided`])
     /// * A value is provided for an output port (only input ports have values provided from
-    ///   outside)
-    /// * A value is provided for an unknown port
-    fn provide_port_values(&mut self, values: HashMap<PortIdentifier, PortValue>)
+    ///   outside) ([`DeviceError::NotAnInputPort`])
+    /// * A value is provided for an unknown port ([`DeviceError::UnknownPort`])
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
         -> Result<(), DeviceError>;
     
     /// Get the value of the provided output port.
     /// 
     /// Fails (returns `Err(DeviceError)`) if:
-    /// * The provided port is unknown
-    /// * The provided port is not an output port
+    /// * The provided port is unknown ([`DeviceError::UnknownPort`])
+    /// * The provided port is not an output port ([`DeviceError::NotAnOutputPort`])
     /// 
-    /// Returns `Ok(None)` if the output port's value is not yet known.
-    /// 
-    /// Returns `Ok(Some(port_value))` if the output port's value has resolved.
-    fn get_port_value(&self, port: &PortIdentifier) -> Result<Option<PortValue>, DeviceError>;
+    /// Returns `Ok(None)` if the output port's value can't be worked out from the input port values
+    /// provided so far.
+    ///
+    /// Returns `Ok(Some(port_value))` if the output port's value has resolved. The value should
+    /// fit within the port's width.
+    ///
+    /// A [`Controller`](crate::controller::Controller) asks for an output port's value once every
+    /// input port it might depend on has either been provided or is known to have no value this
+    /// tick, so a device should resolve an output whenever the inputs it has are enough to do so.
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError>;
     
-    /// Perform a tick. Should fail if this device has not had enough ports specified to know what
-    /// to do this tick.
-    fn tick(&mut self) -> Result<(), DeviceError>;
+    /// Discard every input port value provided since the last tick, returning the device to the
+    /// state it was in immediately after that tick.
+    ///
+    /// This is used to roll back a tick that failed partway through, so it must leave the device
+    /// exactly as it would have been had none of the values been provided.
+    fn clear_port_values(&mut self);
+
+    /// Check whether this device would be able to tick with the port values provided so far,
+    /// without changing any state. Should fail in exactly the circumstances that [`Device::tick`]
+    /// would fail.
+    ///
+    /// This is the "prepare" half of a two-phase tick: a
+    /// [`Controller`](crate::controller::Controller) checks every device before ticking any of
+    /// them, so that if one device cannot tick the whole circuit can be rolled back with
+    /// [`Device::clear_port_values`].
+    ///
+    /// By default a device can always tick, as a purely combinational one can.
+    fn check_tick(&self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    /// Perform a tick. Should fail (usually with [`DeviceError::MissingInput`]) if this device has
+    /// not had enough ports specified to know what to do this tick. Inputs that aren't needed,
+    /// given the values of the others, may be left unspecified.
+    ///
+    /// If [`Device::check_tick`] has just succeeded, this must succeed too. After a successful
+    /// tick, all provided port values are cleared ready for the next tick.
+    ///
+    /// By default this only clears the provided port values, as a purely combinational device
+    /// has no state to update.
+    fn tick(&mut self) -> Result<(), DeviceError> {
+        self.clear_port_values();
+        Ok(())
+    }
 }
@@ -0,0 +1,604 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use crate::device::value::LogicBit;
+use crate::device::{
+    check_width, output_port_error, provide_all_into, provide_into, Device, DeviceError,
+    PortDescriptor, PortId, PortValue, DEFAULT_PORT_WIDTH,
+};
+
+/// Number of input ports on an [`Alu`]. These come first in its port list, so an input port's
+/// [`PortId`] is also its index in `specified_this_tick`.
+const INPUT_PORT_COUNT: usize = 3;
+
+/// An operation that an [`Alu`] can perform on its `a` and `b` inputs.
+///
+/// Unless stated otherwise, the carry and overflow flags are 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// `a + b`. Carry is the carry out of the top bit, and overflow is set if the result doesn't
+    /// fit as a two's complement number.
+    Add,
+    /// `a - b`. Carry is set if `a < b` as unsigned numbers (i.e. a borrow was needed), and
+    /// overflow is set if the result doesn't fit as a two's complement number.
+    Sub,
+    And,
+    Or,
+    Xor,
+    /// `!a`. Doesn't need `b`.
+    Not,
+    /// Shift `a` left by `b` bits. Carry is the last bit shifted out.
+    ShiftLeft,
+    /// Shift `a` right by `b` bits, filling with zeroes. Carry is the last bit shifted out.
+    ShiftRight,
+    /// Shift `a` right by `b` bits, filling with copies of its sign bit. Carry is the last bit
+    /// shifted out.
+    ShiftRightArithmetic,
+    /// Rotate `a` left by `b` bits. Carry is the bit that ended up at the bottom.
+    RotateLeft,
+    /// Rotate `a` right by `b` bits. Carry is the bit that ended up at the top.
+    RotateRight,
+    /// `a * b`, keeping the low half of the product. Carry is set if the product doesn't fit as an
+    /// unsigned number, and overflow if it doesn't fit as a two's complement number.
+    Mul,
+    /// 1 if `a == b`, otherwise 0.
+    Equal,
+    /// 1 if `a < b` as two's complement numbers, otherwise 0.
+    LessThan,
+    /// 1 if `a < b` as unsigned numbers, otherwise 0.
+    LessThanUnsigned,
+}
+
+impl Operation {
+    /// Every operation, in the order used by [`OpcodeTable::standard`].
+    pub const ALL: [Operation; 15] = [
+        Operation::Add,
+        Operation::Sub,
+        Operation::And,
+        Operation::Or,
+        Operation::Xor,
+        Operation::Not,
+        Operation::ShiftLeft,
+        Operation::ShiftRight,
+        Operation::ShiftRightArithmetic,
+        Operation::RotateLeft,
+        Operation::RotateRight,
+        Operation::Mul,
+        Operation::Equal,
+        Operation::LessThan,
+        Operation::LessThanUnsigned,
+    ];
+
+    /// Whether this operation needs the `b` input.
+    pub fn uses_b(&self) -> bool {
+        *self != Operation::Not
+    }
+}
+
+/// Maps the values of an [`Alu`]'s `op` input to the [`Operation`]s they select.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeTable {
+    /// How many bits wide the `op` input is.
+    width: u32,
+    operations: HashMap<u64, Operation>,
+}
+
+impl OpcodeTable {
+    /// Create a table for an `op` input `width` bits wide, with the given opcodes.
+    ///
+    /// Fails if the width is invalid ([`DeviceError::InvalidWidth`]), or if an opcode doesn't fit
+    /// in it or is given more than once ([`DeviceError::DeviceSpecific`]). Not every operation
+    /// needs an opcode, and not every opcode needs an operation.
+    pub fn new(width: u32, opcodes: impl IntoIterator<Item = (u64, Operation)>)
+        -> Result<OpcodeTable, DeviceError>
+    {
+        check_width(width)?;
+        let mut operations = HashMap::new();
+        for (opcode, operation) in opcodes {
+            if width < u64::BITS && opcode >> width != 0 {
+                return Err(DeviceError::DeviceSpecific {
+                    message: format!("opcode {opcode:#x} does not fit in {width} bits"),
+                });
+            }
+            if operations.insert(opcode, operation).is_some() {
+                return Err(DeviceError::DeviceSpecific {
+                    message: format!("opcode {opcode:#x} is given more than once"),
+                });
+            }
+        }
+        Ok(OpcodeTable { width, operations })
+    }
+
+    /// A 4-bit table giving each operation the opcode of its position in [`Operation::ALL`].
+    pub fn standard() -> OpcodeTable {
+        OpcodeTable::new(4, Operation::ALL.into_iter().zip(0..).map(|(op, code)| (code, op)))
+            .expect("Every operation should fit in the standard table")
+    }
+
+    /// How many bits wide the `op` input is.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Get the operation selected by the given opcode, if there is one.
+    pub fn operation(&self, opcode: u64) -> Option<Operation> {
+        self.operations.get(&opcode).copied()
+    }
+}
+
+/// What an [`Alu`] outputs for a particular set of inputs.
+struct Outputs {
+    result: PortValue,
+    carry: LogicBit,
+    overflow: LogicBit,
+}
+
+impl Outputs {
+    /// Outputs where nothing is known.
+    fn unknown(width: u32) -> Outputs {
+        Outputs { result: PortValue::x(width), carry: LogicBit::X, overflow: LogicBit::X }
+    }
+
+    /// Outputs with the given result, and neither carry nor overflow.
+    fn plain(result: PortValue) -> Outputs {
+        Outputs { result, carry: LogicBit::Zero, overflow: LogicBit::Zero }
+    }
+}
+
+/// An arithmetic logic unit, which performs the [`Operation`] selected by its `op` input (see
+/// [`OpcodeTable`]) on its `a` and `b` inputs.
+///
+/// As well as `result`, it outputs `zero`, `carry`, `overflow` and `negative` flags. The zero and
+/// negative flags describe `result`; what carry and overflow mean depends on the operation.
+/// Every output depends on all three inputs. An `op` that selects no operation leaves every output
+/// without a value, and unknown input bits make any output bits they might affect X.
+pub struct Alu {
+    table: OpcodeTable,
+    specified_this_tick: [Option<PortValue>; INPUT_PORT_COUNT],
+    ports: [PortDescriptor; INPUT_PORT_COUNT + 5],
+}
+
+impl Alu {
+    /// First operand
+    pub const A: PortId = PortId(0);
+    /// Second operand
+    pub const B: PortId = PortId(1);
+    /// Operation select
+    pub const OP: PortId = PortId(2);
+    /// Result of the operation
+    pub const RESULT: PortId = PortId(3);
+    /// Set if the result is zero
+    pub const ZERO: PortId = PortId(4);
+    /// Carry (or borrow) flag
+    pub const CARRY: PortId = PortId(5);
+    /// Signed overflow flag
+    pub const OVERFLOW: PortId = PortId(6);
+    /// Set if the top bit of the result is set
+    pub const NEGATIVE: PortId = PortId(7);
+
+    /// Create an ALU with [`DEFAULT_PORT_WIDTH`]-bit operands and the standard opcode table.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Alu {
+        Alu::with_table(DEFAULT_PORT_WIDTH, OpcodeTable::standard())
+            .expect("The default port width should always be a valid width")
+    }
+
+    /// Create an ALU whose operands and result are `width` bits wide, and whose `op` input is
+    /// decoded with the given table. The flags are always a single bit.
+    pub fn with_table(width: u32, table: OpcodeTable) -> Result<Alu, DeviceError> {
+        check_width(width)?;
+
+        // The order of these must match the port handles above
+        let ports = [
+            PortDescriptor::input("a", width),
+            PortDescriptor::input("b", width),
+            PortDescriptor::input("op", table.width()),
+            PortDescriptor::output("result", width),
+            PortDescriptor::output("zero", 1),
+            PortDescriptor::output("carry", 1),
+            PortDescriptor::output("overflow", 1),
+            PortDescriptor::output("negative", 1),
+        ];
+
+        Ok(Alu { table, specified_this_tick: Default::default(), ports })
+    }
+
+    /// Get the width of the operands and result.
+    fn width(&self) -> u32 {
+        self.ports[Self::RESULT.0].width
+    }
+
+    /// Get the value provided to the given input port this tick, if there is one.
+    fn provided(&self, port: PortId) -> Option<&PortValue> {
+        self.specified_this_tick[port.0].as_ref()
+    }
+
+    /// Work out the outputs from the inputs provided so far, if there are enough of them.
+    fn outputs(&self) -> Option<Outputs> {
+        let opcode = self.provided(Self::OP)?;
+        let operation = match opcode.to_u64() {
+            // Could be any operation
+            None => return Some(Outputs::unknown(self.width())),
+            Some(opcode) => self.table.operation(opcode)?,
+        };
+        let a = self.provided(Self::A)?;
+        let b = match operation.uses_b() {
+            true => self.provided(Self::B)?,
+            false => &PortValue::ZERO,
+        };
+        Some(evaluate(operation, a, b, self.width()))
+    }
+}
+
+/// Get the state of a flag that is set if `condition` holds, or X if `known` is false.
+fn flag(known: bool, condition: bool) -> LogicBit {
+    match (known, condition) {
+        (false, _) => LogicBit::X,
+        (true, false) => LogicBit::Zero,
+        (true, true) => LogicBit::One,
+    }
+}
+
+/// Get the remainder of dividing a known value by `modulus`.
+fn remainder(value: &PortValue, modulus: u32) -> u32 {
+    let remainder = value.limbs().iter().rev()
+        .fold(0u128, |remainder, limb| ((remainder << 64) | *limb as u128) % modulus as u128);
+    remainder as u32
+}
+
+/// Perform `operation` on the lowest `width` bits of `a` and `b`.
+fn evaluate(operation: Operation, a: &PortValue, b: &PortValue, width: u32) -> Outputs {
+    let (a, b) = (a.mask(width), b.mask(width));
+    let known = a.is_known() && b.is_known();
+    let sign = |value: &PortValue| value.bit(width - 1);
+    match operation {
+        Operation::Add => {
+            let result = a.wrapping_add(&b, width);
+            Outputs {
+                carry: flag(known, (&a + &b).bit(width)),
+                overflow: flag(known, sign(&a) == sign(&b) && sign(&result) != sign(&a)),
+                result,
+            }
+        }
+        Operation::Sub => {
+            let result = a.wrapping_sub(&b, width);
+            Outputs {
+                carry: flag(known, a < b),
+                overflow: flag(known, sign(&a) != sign(&b) && sign(&result) != sign(&a)),
+                result,
+            }
+        }
+        Operation::And => Outputs::plain(&a & &b),
+        Operation::Or => Outputs::plain(&a | &b),
+        Operation::Xor => Outputs::plain(&a ^ &b),
+        Operation::Not => Outputs::plain(a.not(width)),
+        Operation::ShiftLeft | Operation::ShiftRight | Operation::ShiftRightArithmetic => {
+            if !b.is_known() {
+                return Outputs::unknown(width);
+            }
+            // Shifting by the width or more shifts everything out
+            let amount = match b.bit_length() > u32::BITS {
+                true => width,
+                false => (b.low_u64() as u32).min(width),
+            };
+            if amount == 0 {
+                return Outputs::plain(a);
+            }
+            let (result, last_out) = match operation {
+                Operation::ShiftLeft => ((&a << amount).mask(width), a.state(width - amount)),
+                Operation::ShiftRight => (&a >> amount, a.state(amount - 1)),
+                _ => (
+                    (a.sign_extend(width, width + amount) >> amount).mask(width),
+                    a.state(amount - 1),
+                ),
+            };
+            Outputs { result, carry: last_out, overflow: LogicBit::Zero }
+        }
+        Operation::RotateLeft | Operation::RotateRight => {
+            if !b.is_known() {
+                return Outputs::unknown(width);
+            }
+            let amount = remainder(&b, width);
+            if amount == 0 {
+                return Outputs::plain(a);
+            }
+            let (result, carry_bit) = match operation {
+                Operation::RotateLeft => (a.rotate_left(amount, width), 0),
+                _ => (a.rotate_right(amount, width), width - 1),
+            };
+            Outputs { carry: result.state(carry_bit), result, overflow: LogicBit::Zero }
+        }
+        Operation::Mul => {
+            // The product of two's complement numbers fits if sign extending the low half gives
+            // the whole product back
+            let double = width * 2;
+            let signed = a.sign_extend(width, double)
+                .wrapping_mul(&b.sign_extend(width, double), double);
+            Outputs {
+                result: a.wrapping_mul(&b, width),
+                carry: flag(known, (&a * &b).bit_length() > width),
+                overflow: flag(known, signed.mask(width).sign_extend(width, double) != signed),
+            }
+        }
+        Operation::Equal | Operation::LessThan | Operation::LessThanUnsigned => {
+            let condition = match operation {
+                Operation::Equal => a == b,
+                Operation::LessThan => a.signed_cmp(&b, width) == Ordering::Less,
+                _ => a < b,
+            };
+            Outputs::plain(PortValue::from_states(&[flag(known, condition)]))
+        }
+    }
+}
+
+impl Device for Alu {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        if output.0 < INPUT_PORT_COUNT || output.0 >= self.ports.len() {
+            return Err(output_port_error(&self.ports, output));
+        }
+        Ok(HashSet::from([Self::A, Self::B, Self::OP]))
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        if port.0 < INPUT_PORT_COUNT || port.0 >= self.ports.len() {
+            return Err(output_port_error(&self.ports, port));
+        }
+        let Some(outputs) = self.outputs() else {
+            return Ok(None);
+        };
+        let width = self.width();
+        let state = match port {
+            Self::RESULT => return Ok(Some(outputs.result)),
+            Self::ZERO => match outputs.result.is_known() {
+                true => flag(true, outputs.result.is_zero()),
+                // Any known 1 means it definitely isn't zero
+                false => match (0..width).any(|bit| outputs.result.bit(bit)) {
+                    true => LogicBit::Zero,
+                    false => LogicBit::X,
+                },
+            },
+            Self::CARRY => outputs.carry,
+            Self::OVERFLOW => outputs.overflow,
+            _ => outputs.result.state(width - 1),
+        };
+        Ok(Some(PortValue::from_states(&[state])))
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::device::alu::{Alu, OpcodeTable, Operation};
+    use crate::device::{Device, DeviceError, PortId, PortValue};
+
+    /// Parse a four-state value, for brevity.
+    fn v(value: &str) -> PortValue {
+        value.parse().unwrap()
+    }
+
+    /// Give an 8-bit ALU with the standard table the given inputs, and read back its result and
+    /// flags.
+    fn run(operation: Operation, a: &str, b: &str) -> (PortValue, [PortValue; 4]) {
+        let mut alu = Alu::with_table(8, OpcodeTable::standard()).unwrap();
+        let opcode = Operation::ALL.iter().position(|op| *op == operation).unwrap() as u32;
+        alu.provide_port_values(HashMap::from([
+            (Alu::A, v(a)),
+            (Alu::B, v(b)),
+            (Alu::OP, PortValue::from(opcode)),
+        ])).unwrap();
+        let read = |port: PortId| alu.get_port_value(port).unwrap().unwrap();
+        (read(Alu::RESULT), [Alu::ZERO, Alu::CARRY, Alu::OVERFLOW, Alu::NEGATIVE].map(read))
+    }
+
+    /// Check the result and flags of an operation, with the flags given as "zcvn".
+    fn check(operation: Operation, a: &str, b: &str, result: &str, flags: &str) {
+        let (actual, actual_flags) = run(operation, a, b);
+        assert_eq!(actual, v(result), "result of {operation:?} {a} {b}");
+        let flags: Vec<PortValue> = flags.chars().map(|flag| v(&flag.to_string())).collect();
+        assert_eq!(actual_flags.to_vec(), flags, "flags (zcvn) of {operation:?} {a} {b}");
+    }
+
+    #[test]
+    fn alu_can_be_instantiated() {
+        let alu = Alu::new();
+        assert_eq!(alu.get_ports().len(), 8);
+        assert_eq!(alu.get_ports()[Alu::OP.0].width, 4);
+    }
+
+    #[test]
+    fn alu_adds_with_carry_and_overflow() {
+        check(Operation::Add, "0000_0001", "0000_0010", "0000_0011", "0000");
+        check(Operation::Add, "1111_1111", "0000_0001", "0000_0000", "1100");
+        check(Operation::Add, "0111_1111", "0000_0001", "1000_0000", "0011");
+        check(Operation::Add, "1000_0000", "1000_0000", "0000_0000", "1110");
+    }
+
+    #[test]
+    fn alu_subtracts_with_borrow_and_overflow() {
+        check(Operation::Sub, "0000_0011", "0000_0001", "0000_0010", "0000");
+        check(Operation::Sub, "0000_0011", "0000_0011", "0000_0000", "1000");
+        check(Operation::Sub, "0000_0001", "0000_0010", "1111_1111", "0101");
+        check(Operation::Sub, "1000_0000", "0000_0001", "0111_1111", "0010");
+    }
+
+    #[test]
+    fn alu_performs_bitwise_operations() {
+        check(Operation::And, "1100_1100", "1010_1010", "1000_1000", "0001");
+        check(Operation::Or, "1100_1100", "1010_1010", "1110_1110", "0001");
+        check(Operation::Xor, "1100_1100", "1100_1100", "0000_0000", "1000");
+        check(Operation::Not, "1100_1100", "0", "0011_0011", "0000");
+    }
+
+    #[test]
+    fn alu_shifts_with_the_last_bit_out_as_carry() {
+        check(Operation::ShiftLeft, "1100_0001", "10", "0000_0100", "0100");
+        check(Operation::ShiftRight, "1000_0010", "10", "0010_0000", "0100");
+        check(Operation::ShiftRightArithmetic, "1000_0010", "10", "1110_0000", "0101");
+        check(Operation::ShiftRightArithmetic, "0100_0000", "10", "0001_0000", "0000");
+        check(Operation::ShiftLeft, "1010_1010", "0", "1010_1010", "0001");
+    }
+
+    #[test]
+    fn alu_shifts_everything_out_when_shifting_by_the_width_or_more() {
+        check(Operation::ShiftLeft, "1111_1111", "1000", "0000_0000", "1100");
+        check(Operation::ShiftRight, "1111_1111", "1111_1111", "0000_0000", "1100");
+        check(Operation::ShiftRightArithmetic, "1000_0000", "1001", "1111_1111", "0101");
+    }
+
+    #[test]
+    fn alu_rotates_with_the_wrapped_bit_as_carry() {
+        check(Operation::RotateLeft, "1000_0001", "1", "0000_0011", "0100");
+        check(Operation::RotateRight, "1000_0001", "1", "1100_0000", "0101");
+        // Rotating by the width gets back where it started
+        check(Operation::RotateLeft, "1000_0001", "1001", "0000_0011", "0100");
+        check(Operation::RotateRight, "1000_0001", "1000", "1000_0001", "0001");
+    }
+
+    #[test]
+    fn alu_multiplies_with_unsigned_and_signed_overflow() {
+        check(Operation::Mul, "0000_0110", "0000_0111", "0010_1010", "0000");
+        check(Operation::Mul, "0001_0000", "0001_0000", "0000_0000", "1110");
+        // -1 * -1 = 1 doesn't overflow as signed numbers, but does as unsigned ones
+        check(Operation::Mul, "1111_1111", "1111_1111", "0000_0001", "0100");
+        check(Operation::Mul, "0100_0000", "0000_0010", "1000_0000", "0011");
+    }
+
+    #[test]
+    fn alu_compares_signed_and_unsigned() {
+        check(Operation::Equal, "0000_0101", "0000_0101", "1", "0000");
+        check(Operation::Equal, "0000_0101", "0000_0100", "0", "1000");
+        check(Operation::LessThan, "1111_1111", "0000_0001", "1", "0000");
+        check(Operation::LessThanUnsigned, "1111_1111", "0000_0001", "0", "1000");
+        check(Operation::LessThanUnsigned, "0000_0001", "1111_1111", "1", "0000");
+    }
+
+    #[test]
+    fn alu_propagates_unknown_bits() {
+        // Might or might not be zero, unless a bit is known to be set
+        check(Operation::And, "0000_xxxx", "0000_1111", "0000_xxxx", "x000");
+        check(Operation::Or, "1000_xxxx", "0000_0000", "1000_xxxx", "0001");
+        check(Operation::Add, "0000_000x", "0000_0001", "xxxx_xxxx", "xxxx");
+        check(Operation::ShiftLeft, "0000_0001", "x", "xxxx_xxxx", "xxxx");
+        check(Operation::LessThan, "0000_000x", "0000_0001", "x", "x000");
+    }
+
+    #[test]
+    fn alu_gives_unknown_outputs_for_an_unknown_opcode_value() {
+        let mut alu = Alu::with_table(8, OpcodeTable::standard()).unwrap();
+        alu.provide_port_value(Alu::OP, v("00x0")).unwrap();
+        assert_eq!(alu.get_port_value(Alu::RESULT), Ok(Some(PortValue::x(8))));
+        assert_eq!(alu.get_port_value(Alu::CARRY), Ok(Some(PortValue::x(1))));
+    }
+
+    #[test]
+    fn alu_only_resolves_once_it_has_the_inputs_it_needs() {
+        let mut alu = Alu::with_table(8, OpcodeTable::standard()).unwrap();
+        alu.provide_port_value(Alu::A, PortValue::from(0x0fu32)).unwrap();
+        assert_eq!(alu.get_port_value(Alu::RESULT), Ok(None));
+
+        // Not doesn't need `b`
+        alu.provide_port_value(Alu::OP, PortValue::from(5u32)).unwrap();
+        assert_eq!(alu.get_port_value(Alu::RESULT), Ok(Some(PortValue::from(0xf0u32))));
+    }
+
+    #[test]
+    fn alu_leaves_outputs_unresolved_for_opcodes_without_operations() {
+        let table = OpcodeTable::new(2, [(0b10, Operation::Add)]).unwrap();
+        let mut alu = Alu::with_table(8, table).unwrap();
+        alu.provide_port_values(HashMap::from([
+            (Alu::A, PortValue::from(1u32)),
+            (Alu::B, PortValue::from(2u32)),
+            (Alu::OP, PortValue::from(0u32)),
+        ])).unwrap();
+        assert_eq!(alu.get_port_value(Alu::RESULT), Ok(None));
+        assert_eq!(alu.get_port_value(Alu::ZERO), Ok(None));
+
+        alu.tick().unwrap();
+        alu.provide_port_values(HashMap::from([
+            (Alu::A, PortValue::from(1u32)),
+            (Alu::B, PortValue::from(2u32)),
+            (Alu::OP, PortValue::from(0b10u32)),
+        ])).unwrap();
+        assert_eq!(alu.get_port_value(Alu::RESULT), Ok(Some(PortValue::from(3u32))));
+    }
+
+    #[test]
+    fn opcode_table_rejects_opcodes_that_do_not_fit_or_repeat() {
+        assert!(matches!(
+            OpcodeTable::new(2, [(4, Operation::Add)]),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+        assert!(matches!(
+            OpcodeTable::new(2, [(1, Operation::Add), (1, Operation::Sub)]),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+        assert!(matches!(
+            OpcodeTable::new(0, []),
+            Err(DeviceError::InvalidWidth { width: 0 })
+        ));
+        let table = OpcodeTable::new(64, [(u64::MAX, Operation::Xor)]).unwrap();
+        assert_eq!(table.operation(u64::MAX), Some(Operation::Xor));
+    }
+
+    #[test]
+    fn alu_outputs_depend_on_every_input() {
+        let alu = Alu::new();
+        for output in [Alu::RESULT, Alu::ZERO, Alu::CARRY, Alu::OVERFLOW, Alu::NEGATIVE] {
+            let deps = alu.get_output_dependencies(output).unwrap();
+            assert_eq!(deps.len(), 3);
+        }
+        assert_eq!(
+            alu.get_output_dependencies(Alu::A),
+            Err(DeviceError::NotAnOutputPort { port: "a".to_owned() }),
+        );
+        assert_eq!(
+            alu.get_output_dependencies(PortId(8)),
+            Err(DeviceError::UnknownPort { port: "#8".to_owned() }),
+        );
+    }
+
+    #[test]
+    fn alu_cannot_have_outputs_or_repeated_inputs_provided() {
+        let mut alu = Alu::new();
+        assert_eq!(
+            alu.provide_port_value(Alu::RESULT, PortValue::ZERO),
+            Err(DeviceError::NotAnInputPort { port: "result".to_owned() }),
+        );
+        alu.provide_port_value(Alu::A, PortValue::ZERO).unwrap();
+        assert_eq!(
+            alu.provide_port_value(Alu::A, PortValue::ZERO),
+            Err(DeviceError::AlreadyProvided { port: "a".to_owned() }),
+        );
+    }
+
+    #[test]
+    fn alu_works_on_operands_wider_than_64_bits() {
+        let mut alu = Alu::with_table(100, OpcodeTable::standard()).unwrap();
+        alu.provide_port_values(HashMap::from([
+            (Alu::A, PortValue::all_ones(100)),
+            (Alu::B, PortValue::from(1u32)),
+            (Alu::OP, PortValue::from(0u32)),
+        ])).unwrap();
+        assert_eq!(alu.get_port_value(Alu::RESULT), Ok(Some(PortValue::ZERO)));
+        assert_eq!(alu.get_port_value(Alu::CARRY), Ok(Some(PortValue::from(1u32))));
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use crate::device::{
+    check_width, output_port_error, provide_all_into, provide_into, Device, DeviceError,
+    PortDescriptor, PortId, PortValue,
+};
+
+/// Check that `range` is a non-empty range of bits within a value `width` bits wide.
+fn check_range(range: &Range<u32>, width: u32) -> Result<(), DeviceError> {
+    match !range.is_empty() && range.end <= width {
+        true => Ok(()),
+        false => Err(DeviceError::DeviceSpecific {
+            message: format!("bits {}..{} are not a valid range of a {width}-bit value",
+                range.start, range.end),
+        }),
+    }
+}
+
+/// Check that no two ports are given the same name, including `fixed`, the name of the one port
+/// that isn't configured.
+fn check_names<'a>(fixed: &str, names: impl IntoIterator<Item = &'a str>)
+    -> Result<(), DeviceError>
+{
+    let mut seen = HashSet::from([fixed]);
+    for name in names {
+        if !seen.insert(name) {
+            return Err(DeviceError::DeviceSpecific {
+                message: format!("there is more than one port called `{name}`"),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Splits one input into several outputs, each taking a range of the input's bits.
+///
+/// For example, the fields of an instruction word can be split out with ranges such as
+/// `("opcode", 0..7)` and `("rd", 7..12)`. Ranges may overlap, and needn't cover every bit.
+pub struct Splitter {
+    specified_this_tick: [Option<PortValue>; 1],
+    ports: Vec<PortDescriptor>,
+
+    /// The bits of the input taken by each output, in the same order as the output ports.
+    ranges: Vec<Range<u32>>,
+}
+
+impl Splitter {
+    /// The input port, called `in`.
+    pub const INPUT: PortId = PortId(0);
+
+    /// The handle of the `index`th output.
+    pub fn output(index: usize) -> PortId {
+        PortId(index + 1)
+    }
+
+    /// Create a splitter with a `width`-bit input, and an output with each of the given names
+    /// taking the given range of the input's bits (lowest bit first).
+    ///
+    /// Fails if the width is invalid ([`DeviceError::InvalidWidth`]), or if a range is empty or
+    /// goes beyond the input, or two ports have the same name ([`DeviceError::DeviceSpecific`]).
+    pub fn new(width: u32, outputs: &[(&str, Range<u32>)]) -> Result<Splitter, DeviceError> {
+        check_width(width)?;
+        check_names("in", outputs.iter().map(|(name, _)| *name))?;
+        for (_, range) in outputs {
+            check_range(range, width)?;
+        }
+
+        let mut ports = vec![PortDescriptor::input("in", width)];
+        ports.extend(outputs.iter()
+            .map(|(name, range)| PortDescriptor::output(name, range.len() as u32)));
+
+        Ok(Splitter {
+            specified_this_tick: Default::default(),
+            ports,
+            ranges: outputs.iter().map(|(_, range)| range.clone()).collect(),
+        })
+    }
+
+    /// Get the range of input bits taken by the given output port.
+    fn range(&self, port: PortId) -> Result<&Range<u32>, DeviceError> {
+        match port.0.checked_sub(1).and_then(|index| self.ranges.get(index)) {
+            Some(range) => Ok(range),
+            None => Err(output_port_error(&self.ports, port)),
+        }
+    }
+}
+
+impl Device for Splitter {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        self.range(output)?;
+        Ok(HashSet::from([Self::INPUT]))
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        let range = self.range(port)?;
+        Ok(self.specified_this_tick[Self::INPUT.0].as_ref()
+            .map(|input| input.slice(range.start, range.len() as u32)))
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick = Default::default();
+    }
+}
+
+/// Joins several inputs into one output, each input supplying a range of the output's bits.
+///
+/// This is the reverse of a [`Splitter`]: for example, an immediate scattered across an
+/// instruction word can be reassembled by joining its pieces into the right ranges. Ranges may not
+/// overlap, and any bits of the output not covered by a range are 0. The output needs every input.
+pub struct Joiner {
+    specified_this_tick: Vec<Option<PortValue>>,
+    ports: Vec<PortDescriptor>,
+
+    /// The bits of the output supplied by each input, in the same order as the input ports.
+    ranges: Vec<Range<u32>>,
+}
+
+impl Joiner {
+    /// The handle of the `index`th input.
+    pub fn input(index: usize) -> PortId {
+        PortId(index)
+    }
+
+    /// The handle of the output port, called `out`, which comes after every input.
+    pub fn output(&self) -> PortId {
+        PortId(self.ports.len() - 1)
+    }
+
+    /// Create a joiner with a `width`-bit output, and an input with each of the given names
+    /// supplying the given range of the output's bits (lowest bit first).
+    ///
+    /// Fails if the width is invalid ([`DeviceError::InvalidWidth`]), or if a range is empty, goes
+    /// beyond the output or overlaps another, or two ports have the same name
+    /// ([`DeviceError::DeviceSpecific`]).
+    pub fn new(width: u32, inputs: &[(&str, Range<u32>)]) -> Result<Joiner, DeviceError> {
+        check_width(width)?;
+        check_names("out", inputs.iter().map(|(name, _)| *name))?;
+        let mut covered = PortValue::ZERO;
+        for (name, range) in inputs {
+            check_range(range, width)?;
+            let bits = range.len() as u32;
+            if !covered.slice(range.start, bits).is_zero() {
+                return Err(DeviceError::DeviceSpecific {
+                    message: format!("the bits supplied by `{name}` overlap another input"),
+                });
+            }
+            covered = covered.with_slice(range.start, bits, &PortValue::all_ones(bits));
+        }
+
+        let mut ports: Vec<PortDescriptor> = inputs.iter()
+            .map(|(name, range)| PortDescriptor::input(name, range.len() as u32))
+            .collect();
+        ports.push(PortDescriptor::output("out", width));
+
+        Ok(Joiner {
+            specified_this_tick: vec![None; inputs.len()],
+            ports,
+            ranges: inputs.iter().map(|(_, range)| range.clone()).collect(),
+        })
+    }
+}
+
+impl Device for Joiner {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        if output != self.output() {
+            return Err(output_port_error(&self.ports, output));
+        }
+        Ok((0..self.ranges.len()).map(PortId).collect())
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        if port != self.output() {
+            return Err(output_port_error(&self.ports, port));
+        }
+        let mut value = PortValue::ZERO;
+        for (input, range) in self.specified_this_tick.iter().zip(self.ranges.iter()) {
+            let Some(input) = input else {
+                return Ok(None);
+            };
+            value = value.with_slice(range.start, range.len() as u32, input);
+        }
+        Ok(Some(value))
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick.iter_mut().for_each(|input| *input = None);
+    }
+}
+
+/// Takes a range of an input's bits and widens it to fill the output, either with copies of its
+/// top bit ([`SignExtend`]) or with zeroes ([`ZeroExtend`]).
+///
+/// For example, a 12-bit immediate in bits 20 to 31 of an instruction word can be sign extended to
+/// 32 bits with the range `20..32`.
+pub struct Extend<const SIGNED: bool> {
+    specified_this_tick: [Option<PortValue>; 1],
+    ports: [PortDescriptor; 2],
+
+    /// The bits of the input that are extended.
+    range: Range<u32>,
+}
+
+/// A device that sign extends a range of its input's bits. See [`Extend`].
+pub type SignExtend = Extend<true>;
+
+/// A device that zero extends a range of its input's bits. See [`Extend`].
+pub type ZeroExtend = Extend<false>;
+
+impl<const SIGNED: bool> Extend<SIGNED> {
+    /// The input port, called `in`.
+    pub const INPUT: PortId = PortId(0);
+    /// The output port, called `out`.
+    pub const OUTPUT: PortId = PortId(1);
+
+    /// Create a device that extends the given range of its `input_width`-bit input to
+    /// `output_width` bits.
+    ///
+    /// Fails if either width is invalid ([`DeviceError::InvalidWidth`]), or if the range is
+    /// empty, goes beyond the input, or is wider than the output
+    /// ([`DeviceError::DeviceSpecific`]).
+    pub fn new(input_width: u32, range: Range<u32>, output_width: u32)
+        -> Result<Extend<SIGNED>, DeviceError>
+    {
+        check_width(input_width)?;
+        check_width(output_width)?;
+        check_range(&range, input_width)?;
+        if range.len() as u32 > output_width {
+            return Err(DeviceError::DeviceSpecific {
+                message: format!("cannot extend {} bits to {output_width} bits", range.len()),
+            });
+        }
+
+        Ok(Extend {
+            specified_this_tick: Default::default(),
+            ports: [
+                PortDescriptor::input("in", input_width),
+                PortDescriptor::output("out", output_width),
+            ],
+            range,
+        })
+    }
+}
+
+impl<const SIGNED: bool> Device for Extend<SIGNED> {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        if output != Self::OUTPUT {
+            return Err(output_port_error(&self.ports, output));
+        }
+        Ok(HashSet::from([Self::INPUT]))
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        if port != Self::OUTPUT {
+            return Err(output_port_error(&self.ports, port));
+        }
+        let bits = self.range.len() as u32;
+        Ok(self.specified_this_tick[Self::INPUT.0].as_ref().map(|input| {
+            let value = input.slice(self.range.start, bits);
+            match SIGNED {
+                true => value.sign_extend(bits, self.ports[Self::OUTPUT.0].width),
+                false => value,
+            }
+        }))
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::Controller;
+    use crate::device::bits::{Joiner, SignExtend, Splitter, ZeroExtend};
+    use crate::device::debug::constant::Constant;
+    use crate::device::{Device, DeviceError, PortId, PortValue};
+
+    /// `sw x5, -4(x2)`, an S-type RISC-V instruction whose immediate is split into two fields.
+    const STORE: u32 = 0xfe51_2e23;
+
+    /// The fields of an S-type instruction, lowest first.
+    const FIELDS: [(&str, std::ops::Range<u32>); 6] = [
+        ("opcode", 0..7), ("imm_lo", 7..12), ("funct3", 12..15),
+        ("rs1", 15..20), ("rs2", 20..25), ("imm_hi", 25..32),
+    ];
+
+    #[test]
+    fn splitter_slices_fields_out_of_its_input() {
+        let mut splitter = Splitter::new(32, &FIELDS).unwrap();
+        assert_eq!(splitter.get_ports()[Splitter::output(5).0].width, 7);
+        assert_eq!(splitter.get_port_value(Splitter::output(0)), Ok(None));
+
+        splitter.provide_port_value(Splitter::INPUT, PortValue::from(STORE)).unwrap();
+        let fields: Vec<u64> = (0..FIELDS.len())
+            .map(|index| splitter.get_port_value(Splitter::output(index)).unwrap().unwrap())
+            .map(|value| value.to_u64().unwrap())
+            .collect();
+        assert_eq!(fields, [0x23, 0x1c, 0b010, 2, 5, 0x7f]);
+    }
+
+    #[test]
+    fn splitter_outputs_may_overlap() {
+        let mut splitter = Splitter::new(8, &[("all", 0..8), ("top", 4..8), ("x", 0..2)])
+            .unwrap();
+        splitter.provide_port_value(Splitter::INPUT, "1010_01xz".parse().unwrap()).unwrap();
+        assert_eq!(
+            splitter.get_port_value(Splitter::output(0)),
+            Ok(Some("1010_01xz".parse().unwrap())),
+        );
+        assert_eq!(splitter.get_port_value(Splitter::output(1)), Ok(Some(PortValue::from(0xau32))));
+        assert_eq!(splitter.get_port_value(Splitter::output(2)), Ok(Some("xz".parse().unwrap())));
+    }
+
+    #[test]
+    fn joiner_places_each_input_in_its_range() {
+        let mut joiner = Joiner::new(16, &[("hi", 8..12), ("lo", 0..4)]).unwrap();
+        assert_eq!(joiner.output(), PortId(2));
+        joiner.provide_port_value(Joiner::input(0), PortValue::from(0xau32)).unwrap();
+        assert_eq!(joiner.get_port_value(joiner.output()), Ok(None));
+
+        // Inputs are masked to their width, and the gap between them is 0
+        joiner.provide_port_value(Joiner::input(1), PortValue::from(0x35u32)).unwrap();
+        assert_eq!(joiner.get_port_value(joiner.output()), Ok(Some(PortValue::from(0x0a05u32))));
+    }
+
+    #[test]
+    fn extend_widens_a_range_of_the_input() {
+        let cases = [("1011_0000", "1111_1011"), ("0111_0000", "0000_0111")];
+        for (input, sign_extended) in cases {
+            let mut sign = SignExtend::new(8, 4..8, 8).unwrap();
+            sign.provide_port_value(SignExtend::INPUT, input.parse().unwrap()).unwrap();
+            assert_eq!(
+                sign.get_port_value(SignExtend::OUTPUT),
+                Ok(Some(sign_extended.parse().unwrap())),
+            );
+        }
+
+        let mut zero = ZeroExtend::new(8, 4..8, 16).unwrap();
+        zero.provide_port_value(ZeroExtend::INPUT, "1011_0000".parse().unwrap()).unwrap();
+        assert_eq!(zero.get_port_value(ZeroExtend::OUTPUT), Ok(Some(PortValue::from(0xbu32))));
+
+        // An unknown sign bit makes every bit it is copied into unknown
+        let mut sign = SignExtend::new(4, 0..4, 6).unwrap();
+        sign.provide_port_value(SignExtend::INPUT, "x001".parse().unwrap()).unwrap();
+        assert_eq!(sign.get_port_value(SignExtend::OUTPUT), Ok(Some("xxx001".parse().unwrap())));
+    }
+
+    #[test]
+    fn bit_devices_reject_bad_ranges_and_names() {
+        let invalid = [
+            Splitter::new(8, &[("a", 4..9)]).err(),
+            Splitter::new(8, &[("a", 4..4)]).err(),
+            Splitter::new(8, &[("a", 0..4), ("a", 4..8)]).err(),
+            Splitter::new(8, &[("in", 0..4)]).err(),
+            Joiner::new(8, &[("a", 0..5), ("b", 4..8)]).err(),
+            Joiner::new(8, &[("out", 0..8)]).err(),
+            SignExtend::new(8, 0..8, 4).err(),
+            ZeroExtend::new(8, 6..10, 8).err(),
+        ];
+        for error in invalid {
+            assert!(matches!(error, Some(DeviceError::DeviceSpecific { .. })), "{error:?}");
+        }
+        assert!(matches!(Joiner::new(0, &[]), Err(DeviceError::InvalidWidth { width: 0 })));
+    }
+
+    #[test]
+    fn bit_devices_reject_bad_ports() {
+        let mut splitter = Splitter::new(8, &[("a", 0..4)]).unwrap();
+        assert_eq!(
+            splitter.provide_port_value(Splitter::output(0), PortValue::ZERO),
+            Err(DeviceError::NotAnInputPort { port: "a".to_owned() }),
+        );
+        assert_eq!(
+            splitter.get_port_value(Splitter::INPUT),
+            Err(DeviceError::NotAnOutputPort { port: "in".to_owned() }),
+        );
+        splitter.provide_port_value(Splitter::INPUT, PortValue::ZERO).unwrap();
+        assert_eq!(
+            splitter.provide_port_value(Splitter::INPUT, PortValue::ZERO),
+            Err(DeviceError::AlreadyProvided { port: "in".to_owned() }),
+        );
+
+        let joiner = Joiner::new(8, &[("a", 0..4)]).unwrap();
+        assert_eq!(
+            joiner.get_output_dependencies(Joiner::input(0)),
+            Err(DeviceError::NotAnOutputPort { port: "a".to_owned() }),
+        );
+    }
+
+    #[test]
+    fn store_immediate_is_decoded_by_splitting_joining_and_extending() {
+        let mut controller = Controller::new();
+        let word = Constant::with_width("qq".to_owned(), 32, PortValue::from(STORE)).unwrap();
+        controller.add_device("Word".to_owned(), Box::new(word)).unwrap();
+        let fields = Box::new(Splitter::new(32, &FIELDS).unwrap());
+        controller.add_device("Fields".to_owned(), fields).unwrap();
+        let joiner = Joiner::new(12, &[("lo", 0..5), ("hi", 5..12)]).unwrap();
+        controller.add_device("Imm".to_owned(), Box::new(joiner)).unwrap();
+        let extend = SignExtend::new(12, 0..12, 32).unwrap();
+        controller.add_device("Extend".to_owned(), Box::new(extend)).unwrap();
+
+        let connections = [("Word", "qq", "Fields", "in"), ("Fields", "imm_lo", "Imm", "lo"),
+            ("Fields", "imm_hi", "Imm", "hi"), ("Imm", "out", "Extend", "in")];
+        for (from, from_port, to, to_port) in connections {
+            controller.add_connection(
+                &from.to_owned(), &from_port.to_owned(),
+                &to.to_owned(), &to_port.to_owned(),
+            ).unwrap();
+        }
+
+        let state = controller.tick().unwrap();
+        assert_eq!(state.value_by_name("Imm", "out"), Some(&PortValue::from(0xffcu32)));
+        assert_eq!(state.value_by_name("Extend", "out"), Some(&PortValue::from(-4i32 as u32)));
+    }
+}
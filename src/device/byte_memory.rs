@@ -0,0 +1,686 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use crate::device::dump::Dump;
+use crate::device::image::{Endianness, Image};
+use crate::device::value::LogicBit;
+use crate::device::{
+    check_width, output_port_error, provide_all_into, provide_into, Device, DeviceError,
+    PortDescriptor, PortId, PortValue, DEFAULT_PORT_WIDTH,
+};
+
+/// The most bytes a [`ByteMemory`] can hold. All of them are allocated up front.
+pub const MAX_SIZE: u64 = 1 << 26;
+
+/// Number of input ports on a [`ByteMemory`]. These come first in its port list, so an input
+/// port's [`PortId`] is also its index in `specified_this_tick`.
+const INPUT_PORT_COUNT: usize = 5;
+
+/// The error message for a write that is enabled without everything it needs.
+const WRITE_INPUTS_MESSAGE: &str =
+    "write address, value and mask must be provided when write enable is set";
+
+/// Whether a [`ByteMemory`] allows words to be accessed at any byte address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Alignment {
+    /// Word accesses must be at a multiple of the word size; any other address is a fault.
+    #[default]
+    Required,
+    /// Word accesses may be at any byte address.
+    Unaligned,
+}
+
+/// What a [`ByteMemory`] does about an access that is out of range or misaligned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FaultPolicy {
+    /// Fail the tick with a [`DeviceError`]. Until then, a faulting read gives all X.
+    #[default]
+    Error,
+    /// Set the fault output, read all X and ignore the write, leaving the circuit to deal with it.
+    Output,
+}
+
+/// The outcome of decoding the address of an access.
+enum Access {
+    /// The access is to the word at this byte address.
+    Valid(u64),
+    /// The address has unknown bits.
+    Unknown,
+    /// The access touches a byte beyond the end of the memory.
+    OutOfRange,
+    /// The access isn't aligned to a word, and alignment is required.
+    Misaligned,
+}
+
+/// A memory of a fixed number of bytes, read and written a word at a time, with byte-lane masks
+/// for writing only part of a word.
+///
+/// Addresses (`ra` and `wa`) count bytes. The word at an address is made of the bytes starting
+/// there, in the order given by [`ByteMemory::set_endianness`]: with little endian (the default),
+/// byte lane `i` (bits `8 * i` to `8 * i + 7`) of the word is the byte at `address + i`. Each bit
+/// of `wmask` enables writing one byte lane of `wv`, so a CPU can store a single byte or
+/// halfword. Write enable must be provided every tick, and the write address, value and mask
+/// whenever it is set.
+///
+/// An access that touches a byte beyond the end of the memory, or that isn't aligned to a word
+/// when alignment is required (see [`ByteMemory::set_alignment`]), is a fault. Faults are always
+/// reported on `rfault` and `wfault`, and also fail the tick unless the fault policy says
+/// otherwise (see [`ByteMemory::set_fault_policy`]).
+pub struct ByteMemory {
+    /// The stored bytes, as the value of each bit and a mask of the bits that are unknown, or
+    /// `None` for bytes that have never been written.
+    bytes: Vec<Option<(u8, u8)>>,
+    specified_this_tick: [Option<PortValue>; INPUT_PORT_COUNT],
+    ports: [PortDescriptor; INPUT_PORT_COUNT + 3],
+
+    endianness: Endianness,
+    alignment: Alignment,
+    fault_policy: FaultPolicy,
+
+    /// Whether bytes that have never been written read as all X rather than 0.
+    four_state: bool,
+}
+
+impl ByteMemory {
+    /// Read address
+    pub const RA: PortId = PortId(0);
+    /// Write enable
+    pub const WE: PortId = PortId(1);
+    /// Write address
+    pub const WA: PortId = PortId(2);
+    /// Write value
+    pub const WV: PortId = PortId(3);
+    /// Write mask, with one bit per byte lane
+    pub const WMASK: PortId = PortId(4);
+    /// Read value
+    pub const RV: PortId = PortId(5);
+    /// Read fault
+    pub const RFAULT: PortId = PortId(6);
+    /// Write fault
+    pub const WFAULT: PortId = PortId(7);
+
+    /// Create a memory of `size` bytes with [`DEFAULT_PORT_WIDTH`]-bit addresses and words.
+    pub fn new(size: u64) -> Result<ByteMemory, DeviceError> {
+        ByteMemory::with_widths(size, DEFAULT_PORT_WIDTH, DEFAULT_PORT_WIDTH / 8)
+    }
+
+    /// Create a memory of `size` bytes, whose address ports (`ra` and `wa`) are `address_width`
+    /// bits wide, and whose data ports (`wv` and `rv`) are `word_bytes` bytes wide.
+    ///
+    /// Fails if a width is invalid ([`DeviceError::InvalidWidth`]), or if the size is 0, more
+    /// than [`MAX_SIZE`] or more than the address ports can reach, or if a word is bigger than
+    /// the whole memory ([`DeviceError::DeviceSpecific`]).
+    pub fn with_widths(size: u64, address_width: u32, word_bytes: u32)
+        -> Result<ByteMemory, DeviceError>
+    {
+        check_width(address_width)?;
+        check_width(word_bytes)?;
+        let reachable = address_width >= u64::BITS || size <= 1 << address_width;
+        if !(1..=MAX_SIZE).contains(&size) || !reachable {
+            return Err(DeviceError::DeviceSpecific {
+                message: format!("cannot have a {size}-byte memory with {address_width}-bit \
+                    addresses: the size must be between 1 and {MAX_SIZE} bytes, and reachable"),
+            });
+        }
+        // No access to a word bigger than the memory could ever be in range. This also keeps the
+        // width of the data ports in bounds.
+        if word_bytes as u64 > size {
+            return Err(DeviceError::DeviceSpecific {
+                message: format!("cannot have {word_bytes}-byte words in a {size}-byte memory"),
+            });
+        }
+
+        // The order of these must match the port handles above
+        let word_width = word_bytes * 8;
+        let ports = [
+            PortDescriptor::input("ra", address_width),
+            PortDescriptor::input("we", 1),
+            PortDescriptor::input("wa", address_width),
+            PortDescriptor::input("wv", word_width),
+            PortDescriptor::input("wmask", word_bytes),
+            PortDescriptor::output("rv", word_width),
+            PortDescriptor::output("rfault", 1),
+            PortDescriptor::output("wfault", 1),
+        ];
+
+        Ok(ByteMemory {
+            bytes: vec![None; size as usize],
+            specified_this_tick: Default::default(),
+            ports,
+            endianness: Endianness::default(),
+            alignment: Alignment::default(),
+            fault_policy: FaultPolicy::default(),
+            four_state: false,
+        })
+    }
+
+    /// Set the order of the bytes in a word.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// Set whether words may be accessed at addresses that aren't a multiple of the word size.
+    pub fn set_alignment(&mut self, alignment: Alignment) {
+        self.alignment = alignment;
+    }
+
+    /// Set whether faults fail the tick, or are left to the circuit to handle.
+    pub fn set_fault_policy(&mut self, fault_policy: FaultPolicy) {
+        self.fault_policy = fault_policy;
+    }
+
+    /// Set whether this memory uses four-state logic, in which case bytes that have never been
+    /// written read as all [`LogicBit::X`] rather than 0.
+    ///
+    /// Regardless of this setting, reading from an address with unknown bits gives all X, and
+    /// an unknown write enable or mask bit leaves X in any bit of a byte that would end up
+    /// different depending on whether the write happens.
+    pub fn set_four_state(&mut self, four_state: bool) {
+        self.four_state = four_state;
+    }
+
+    /// Get the number of bytes in the memory.
+    pub fn size(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    /// Store the bytes of an image, at the same addresses.
+    ///
+    /// Fails, without storing anything, if any byte is beyond the end of the memory
+    /// ([`DeviceError::DeviceSpecific`]).
+    pub fn load(&mut self, image: &Image) -> Result<(), DeviceError> {
+        if let Some((address, _)) = image.bytes().find(|(address, _)| *address >= self.size()) {
+            return Err(DeviceError::DeviceSpecific {
+                message: format!("byte {address:#x} of the image is beyond the end of a {}-byte \
+                    memory", self.size()),
+            });
+        }
+        for (address, byte) in image.bytes() {
+            self.bytes[address as usize] = Some((byte, 0));
+        }
+        Ok(())
+    }
+
+    /// Take a snapshot of the bytes stored at the given addresses, as a dump of 8-bit words.
+    /// Only bytes that have been written or loaded are included.
+    pub fn dump(&self, addresses: Range<u64>) -> Dump {
+        let mut dump = Dump::new(8);
+        for address in addresses.start..addresses.end.min(self.size()) {
+            if self.bytes[address as usize].is_some() {
+                dump.insert(address, self.byte(address));
+            }
+        }
+        dump
+    }
+
+    /// Get the number of bytes in a word.
+    fn word_bytes(&self) -> u32 {
+        self.ports[Self::WMASK.0].width
+    }
+
+    /// Get the width of the data ports.
+    fn word_width(&self) -> u32 {
+        self.ports[Self::RV.0].width
+    }
+
+    /// Get the value provided to the given input port this tick, if there is one.
+    fn provided(&self, port: PortId) -> Option<&PortValue> {
+        self.specified_this_tick[port.0].as_ref()
+    }
+
+    /// Get the value provided to the given input port this tick, failing with `message` if there
+    /// isn't one.
+    fn required(&self, port: PortId, message: &str) -> Result<&PortValue, DeviceError> {
+        self.provided(port).ok_or_else(|| DeviceError::MissingInput {
+            port: self.port_name(port),
+            message: message.to_owned(),
+        })
+    }
+
+    /// Get the address of the byte in the given lane of the word at `address`, if there is one.
+    fn lane_address(&self, address: u64, lane: u32) -> Option<u64> {
+        let offset = match self.endianness {
+            Endianness::Little => lane,
+            Endianness::Big => self.word_bytes() - 1 - lane,
+        };
+        address.checked_add(offset as u64).filter(|address| *address < self.size())
+    }
+
+    /// Decode an access to the word at `address`, which touches the byte lanes for which `lanes`
+    /// is true.
+    fn decode(&self, address: &PortValue, lanes: impl Fn(u32) -> bool) -> Access {
+        if !address.is_known() {
+            return Access::Unknown;
+        }
+        let Some(address) = address.to_u64() else {
+            return Access::OutOfRange;
+        };
+        if self.alignment == Alignment::Required && address % self.word_bytes() as u64 != 0 {
+            return Access::Misaligned;
+        }
+        let in_range = (0..self.word_bytes())
+            .filter(|lane| lanes(*lane))
+            .all(|lane| self.lane_address(address, lane).is_some());
+        match in_range {
+            true => Access::Valid(address),
+            false => Access::OutOfRange,
+        }
+    }
+
+    /// Decode the read this tick, or `None` if the read address hasn't been provided.
+    fn read_access(&self) -> Option<Access> {
+        self.provided(Self::RA).map(|address| self.decode(address, |_| true))
+    }
+
+    /// Decode the write this tick, with its enable, or `None` if it definitely doesn't happen.
+    ///
+    /// The write value isn't needed to decode the write, so it isn't checked here.
+    fn write_access(&self) -> Result<Option<(Access, &PortValue)>, DeviceError> {
+        let enable = self.required(Self::WE, "write enable must be provided every tick")?;
+        if enable.is_zero() {
+            return Ok(None);
+        }
+        let address = self.required(Self::WA, WRITE_INPUTS_MESSAGE)?;
+        let mask = self.required(Self::WMASK, WRITE_INPUTS_MESSAGE)?;
+        Ok(Some((self.decode(address, |lane| mask.state(lane) != LogicBit::Zero), enable)))
+    }
+
+    /// Build the error for a faulting access, described as `access`.
+    fn fault_error(&self, access: &str, address: &PortValue, fault: &Access) -> DeviceError {
+        let reason = match fault {
+            Access::Misaligned => format!("is not aligned to a {}-byte word", self.word_bytes()),
+            _ => format!("is out of range for a {}-byte memory", self.size()),
+        };
+        DeviceError::DeviceSpecific {
+            message: format!("{access} of address {address:#x} {reason}"),
+        }
+    }
+
+    /// Get the byte at the given address.
+    fn byte(&self, address: u64) -> PortValue {
+        match self.bytes[address as usize] {
+            Some((bits, unknown)) => {
+                let states: Vec<LogicBit> = (0..8)
+                    .map(|index| match (unknown >> index & 1, bits >> index & 1) {
+                        (1, _) => LogicBit::X,
+                        (_, 1) => LogicBit::One,
+                        _ => LogicBit::Zero,
+                    })
+                    .collect();
+                PortValue::from_states(&states)
+            }
+            None if self.four_state => PortValue::x(8),
+            None => PortValue::ZERO,
+        }
+    }
+
+    /// Store a byte at the given address. Z bits are stored as X.
+    fn store(&mut self, address: u64, value: &PortValue) {
+        let (mut bits, mut unknown) = (0, 0);
+        for index in 0..8 {
+            match value.state(index) {
+                LogicBit::Zero => {}
+                LogicBit::One => bits |= 1 << index,
+                LogicBit::X | LogicBit::Z => unknown |= 1 << index,
+            }
+        }
+        self.bytes[address as usize] = Some((bits, unknown));
+    }
+
+    /// Get the word at the given address, which must be a valid access.
+    fn word(&self, address: u64) -> PortValue {
+        (0..self.word_bytes()).fold(PortValue::ZERO, |word, lane| {
+            let byte = self.lane_address(address, lane)
+                .expect("A valid access should only touch bytes in the memory");
+            word.with_slice(lane * 8, 8, &self.byte(byte))
+        })
+    }
+}
+
+impl Device for ByteMemory {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        match output {
+            Self::RV | Self::RFAULT => Ok(HashSet::from([Self::RA])),
+            Self::WFAULT => Ok(HashSet::from([Self::WE, Self::WA, Self::WMASK])),
+            _ => Err(output_port_error(&self.ports, output)),
+        }
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        match port {
+            // A faulting read is reported by `check_tick` if the fault policy says so
+            Self::RV => Ok(self.read_access().map(|access| match access {
+                Access::Valid(address) => self.word(address),
+                _ => PortValue::x(self.word_width()),
+            })),
+            Self::RFAULT => Ok(self.read_access().map(|access| match access {
+                Access::Valid(_) => PortValue::ZERO,
+                Access::Unknown => PortValue::x(1),
+                Access::OutOfRange | Access::Misaligned => PortValue::from(1u32),
+            })),
+            Self::WFAULT => match self.write_access() {
+                Ok(None) => Ok(Some(PortValue::ZERO)),
+                Ok(Some((access, enable))) => Ok(Some(match access {
+                    Access::Valid(_) => PortValue::ZERO,
+                    Access::OutOfRange | Access::Misaligned if enable.is_known() =>
+                        PortValue::from(1u32),
+                    _ => PortValue::x(1),
+                })),
+                // Not everything needed to decode the write has been provided yet
+                Err(_) => Ok(None),
+            },
+            _ => Err(output_port_error(&self.ports, port)),
+        }
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick = Default::default();
+    }
+
+    fn check_tick(&self) -> Result<(), DeviceError> {
+        if self.fault_policy == FaultPolicy::Error {
+            if let Some(fault @ (Access::OutOfRange | Access::Misaligned)) = self.read_access() {
+                return Err(self.fault_error("read", self.provided(Self::RA).unwrap(), &fault));
+            }
+        }
+
+        let Some((access, _)) = self.write_access()? else {
+            return Ok(());
+        };
+        self.required(Self::WV, WRITE_INPUTS_MESSAGE)?;
+        match access {
+            Access::Valid(_) => Ok(()),
+            Access::Unknown => Err(DeviceError::DeviceSpecific {
+                message: "cannot write to an address with unknown bits".to_owned(),
+            }),
+            fault => match self.fault_policy {
+                FaultPolicy::Error =>
+                    Err(self.fault_error("write", self.provided(Self::WA).unwrap(), &fault)),
+                FaultPolicy::Output => Ok(()),
+            },
+        }
+    }
+
+    fn tick(&mut self) -> Result<(), DeviceError> {
+        self.check_tick()?;
+        if let Some((Access::Valid(address), enable)) = self.write_access()? {
+            let enable = enable.clone();
+            let value = self.provided(Self::WV).unwrap().clone();
+            let mask = self.provided(Self::WMASK).unwrap().clone();
+            for lane in 0..self.word_bytes() {
+                let certain = match mask.state(lane) {
+                    LogicBit::Zero => continue,
+                    LogicBit::One => enable.is_known(),
+                    LogicBit::X | LogicBit::Z => false,
+                };
+                let byte = self.lane_address(address, lane)
+                    .expect("A valid access should only touch bytes in the memory");
+                let new = value.slice(lane * 8, 8);
+                let new = match certain {
+                    true => new,
+                    false => new.either(&self.byte(byte)),
+                };
+                self.store(byte, &new);
+            }
+        }
+        self.clear_port_values();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::controller::{Controller, ControllerError};
+    use crate::device::byte_memory::{Alignment, ByteMemory, FaultPolicy};
+    use crate::device::debug::constant::Constant;
+    use crate::device::image::{Endianness, Image};
+    use crate::device::{Device, DeviceError, PortValue};
+
+    /// Write to the memory and tick it.
+    fn write(memory: &mut ByteMemory, address: u32, value: u32, mask: &str)
+        -> Result<(), DeviceError>
+    {
+        memory.provide_port_values(HashMap::from([
+            (ByteMemory::WE, PortValue::from(1u32)),
+            (ByteMemory::WA, PortValue::from(address)),
+            (ByteMemory::WV, PortValue::from(value)),
+            (ByteMemory::WMASK, mask.parse().unwrap()),
+        ]))?;
+        memory.tick()
+    }
+
+    /// Read the word at the given address.
+    fn read(memory: &mut ByteMemory, address: u32) -> Result<Option<PortValue>, DeviceError> {
+        memory.clear_port_values();
+        memory.provide_port_value(ByteMemory::RA, PortValue::from(address)).unwrap();
+        memory.get_port_value(ByteMemory::RV)
+    }
+
+    #[test]
+    fn byte_memory_writes_only_masked_lanes() {
+        let mut memory = ByteMemory::new(16).unwrap();
+        write(&mut memory, 4, 0x1122_3344, "1111").unwrap();
+        write(&mut memory, 4, 0xaabb_ccdd, "0010").unwrap();
+        assert_eq!(read(&mut memory, 4), Ok(Some(PortValue::from(0x1122_cc44u32))));
+
+        let bytes: Vec<u64> = memory.dump(0..16).words()
+            .map(|(_, byte)| byte.to_u64().unwrap())
+            .collect();
+        assert_eq!(bytes, [0x44, 0xcc, 0x22, 0x11]);
+    }
+
+    #[test]
+    fn byte_memory_orders_bytes_by_endianness() {
+        let image = Image::from_binary(&[0x12, 0x34, 0x56, 0x78], 0);
+        let mut memory = ByteMemory::new(8).unwrap();
+        memory.load(&image).unwrap();
+        assert_eq!(read(&mut memory, 0), Ok(Some(PortValue::from(0x7856_3412u32))));
+
+        memory.set_endianness(Endianness::Big);
+        assert_eq!(read(&mut memory, 0), Ok(Some(PortValue::from(0x1234_5678u32))));
+
+        // The lowest lane is the highest address when big endian
+        memory.clear_port_values();
+        write(&mut memory, 4, 0xff, "0001").unwrap();
+        assert_eq!(memory.dump(7..8).word(7), Some(&PortValue::from(0xffu32)));
+    }
+
+    #[test]
+    fn byte_memory_alignment_policy() {
+        let mut memory = ByteMemory::new(16).unwrap();
+        write(&mut memory, 0, 0x4433_2211, "1111").unwrap();
+        write(&mut memory, 4, 0x8877_6655, "1111").unwrap();
+        assert_eq!(read(&mut memory, 2), Ok(Some(PortValue::x(32))));
+        assert!(matches!(memory.check_tick(), Err(DeviceError::DeviceSpecific { .. })));
+        memory.clear_port_values();
+        assert!(matches!(
+            write(&mut memory, 1, 0, "0001"),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+
+        memory.set_alignment(Alignment::Unaligned);
+        assert_eq!(read(&mut memory, 2), Ok(Some(PortValue::from(0x6655_4433u32))));
+        memory.clear_port_values();
+        write(&mut memory, 1, 0xee, "0001").unwrap();
+        assert_eq!(read(&mut memory, 0), Ok(Some(PortValue::from(0x4433_ee11u32))));
+    }
+
+    #[test]
+    fn byte_memory_out_of_range_accesses_are_errors_by_default() {
+        let mut memory = ByteMemory::new(8).unwrap();
+        assert_eq!(read(&mut memory, 8), Ok(Some(PortValue::x(32))));
+        let error = memory.check_tick().unwrap_err();
+        assert_eq!(error.to_string(), "read of address 0x8 is out of range for a 8-byte memory");
+        memory.clear_port_values();
+        assert!(matches!(
+            write(&mut memory, 8, 0, "1111"),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+
+        // A write that only touches bytes in range is fine, even if the rest of the word isn't
+        memory.clear_port_values();
+        memory.set_alignment(Alignment::Unaligned);
+        write(&mut memory, 7, 0xab, "0001").unwrap();
+    }
+
+    #[test]
+    fn byte_memory_can_report_faults_on_outputs() {
+        let mut memory = ByteMemory::new(8).unwrap();
+        memory.set_fault_policy(FaultPolicy::Output);
+        assert_eq!(read(&mut memory, 8), Ok(Some(PortValue::x(32))));
+        assert_eq!(memory.get_port_value(ByteMemory::RFAULT), Ok(Some(PortValue::from(1u32))));
+        assert_eq!(read(&mut memory, 4), Ok(Some(PortValue::ZERO)));
+        assert_eq!(memory.get_port_value(ByteMemory::RFAULT), Ok(Some(PortValue::ZERO)));
+
+        // The faulting write is ignored
+        memory.clear_port_values();
+        memory.provide_port_values(HashMap::from([
+            (ByteMemory::WE, PortValue::from(1u32)),
+            (ByteMemory::WA, PortValue::from(6u32)),
+            (ByteMemory::WV, PortValue::from(u32::MAX)),
+            (ByteMemory::WMASK, PortValue::from(0xfu32)),
+        ])).unwrap();
+        assert_eq!(memory.get_port_value(ByteMemory::WFAULT), Ok(Some(PortValue::from(1u32))));
+        memory.tick().unwrap();
+        assert!(memory.dump(0..8).is_empty());
+    }
+
+    #[test]
+    fn byte_memory_write_fault_needs_the_write_inputs() {
+        let mut memory = ByteMemory::new(8).unwrap();
+        assert_eq!(memory.get_port_value(ByteMemory::WFAULT), Ok(None));
+        memory.provide_port_value(ByteMemory::WE, PortValue::ZERO).unwrap();
+        assert_eq!(memory.get_port_value(ByteMemory::WFAULT), Ok(Some(PortValue::ZERO)));
+        assert_eq!(memory.get_port_value(ByteMemory::RFAULT), Ok(None));
+        assert!(matches!(
+            memory.get_output_dependencies(ByteMemory::WE),
+            Err(DeviceError::NotAnOutputPort { .. })
+        ));
+    }
+
+    #[test]
+    fn byte_memory_read_fault_fails_the_controller_tick() {
+        let mut controller = Controller::new();
+        controller.add_device("Mem".to_owned(), Box::new(ByteMemory::new(16).unwrap())).unwrap();
+        let address = Constant::new("qq".to_owned(), PortValue::from(0x64u32));
+        controller.add_device("Address".to_owned(), Box::new(address)).unwrap();
+        let zero = Constant::with_width("qq".to_owned(), 1, PortValue::ZERO).unwrap();
+        controller.add_device("Zero".to_owned(), Box::new(zero)).unwrap();
+        for (from, to_port) in [("Address", "ra"), ("Zero", "we")] {
+            controller.add_connection(
+                &from.to_owned(), &"qq".to_owned(), &"Mem".to_owned(), &to_port.to_owned(),
+            ).unwrap();
+        }
+
+        let Err(ControllerError::Device { device, source }) = controller.tick() else {
+            panic!("a faulting read should fail the tick");
+        };
+        assert_eq!(device, "Mem");
+        assert_eq!(
+            source.to_string(),
+            "read of address 0x64 is out of range for a 16-byte memory",
+        );
+    }
+
+    #[test]
+    fn byte_memory_write_fault_does_not_wait_for_the_write_value() {
+        let inputs = [("we", 1, 1u32), ("wa", 32, 14), ("wv", 32, 0), ("wmask", 4, 0xf)];
+        // Try every order of adding the inputs, as that decides the order they are resolved in
+        for order in 0..24 {
+            let mut remaining = inputs.to_vec();
+            let mut ordered = Vec::new();
+            let mut choice = order;
+            while !remaining.is_empty() {
+                ordered.push(remaining.remove(choice % remaining.len()));
+                choice /= remaining.len() + 1;
+            }
+
+            let mut controller = Controller::new();
+            for (port, width, value) in &ordered {
+                let constant =
+                    Constant::with_width("qq".to_owned(), *width, PortValue::from(*value)).unwrap();
+                controller.add_device(port.to_string(), Box::new(constant)).unwrap();
+            }
+            let mut memory = ByteMemory::new(16).unwrap();
+            memory.set_fault_policy(FaultPolicy::Output);
+            controller.add_device("Mem".to_owned(), Box::new(memory)).unwrap();
+            for (port, _, _) in &ordered {
+                controller.add_connection(
+                    &port.to_string(), &"qq".to_owned(), &"Mem".to_owned(), &port.to_string(),
+                ).unwrap();
+            }
+
+            controller.tick().unwrap();
+            assert_eq!(
+                controller.port_value_by_name("Mem", "wfault"),
+                Some(PortValue::from(1u32)),
+                "order {order}",
+            );
+        }
+    }
+
+    #[test]
+    fn byte_memory_keeps_only_certain_bits_with_unknown_mask() {
+        let mut memory = ByteMemory::with_widths(4, 8, 2).unwrap();
+        write(&mut memory, 0, 0x0f0f, "11").unwrap();
+        write(&mut memory, 0, 0x3cf0, "x1").unwrap();
+        assert_eq!(read(&mut memory, 0), Ok(Some("00xx_11xx_1111_0000".parse().unwrap())));
+
+        // Floating bits in a lane that may or may not be written don't leave the old byte known
+        let mut memory = ByteMemory::with_widths(4, 8, 2).unwrap();
+        write(&mut memory, 0, 0xffff, "11").unwrap();
+        memory.provide_port_values(HashMap::from([
+            (ByteMemory::WE, PortValue::from(1u32)),
+            (ByteMemory::WA, PortValue::ZERO),
+            (ByteMemory::WV, PortValue::z(16)),
+            (ByteMemory::WMASK, "x0".parse().unwrap()),
+        ])).unwrap();
+        memory.tick().unwrap();
+        assert_eq!(read(&mut memory, 0), Ok(Some("xxxx_xxxx_1111_1111".parse().unwrap())));
+
+        let mut memory = ByteMemory::with_widths(4, 8, 2).unwrap();
+        memory.set_four_state(true);
+        assert_eq!(read(&mut memory, 2), Ok(Some(PortValue::x(16))));
+    }
+
+    #[test]
+    fn byte_memory_size_is_bounded() {
+        assert!(matches!(ByteMemory::new(0), Err(DeviceError::DeviceSpecific { .. })));
+        assert!(matches!(
+            ByteMemory::new(super::MAX_SIZE + 1),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+        assert!(matches!(
+            ByteMemory::with_widths(257, 8, 4),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+        assert!(ByteMemory::with_widths(256, 8, 4).is_ok());
+        assert!(matches!(
+            ByteMemory::with_widths(16, 32, 1 << 29),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+
+        let mut memory = ByteMemory::new(4).unwrap();
+        assert!(matches!(
+            memory.load(&Image::from_binary(&[1, 2], 3)),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+        assert!(memory.dump(0..4).is_empty());
+    }
+}
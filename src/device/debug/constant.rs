@@ -1,84 +1,120 @@
 use std::collections::{HashMap, HashSet};
-use crate::device::{Device, DeviceError, PortIdentifier, PortValue};
+use crate::device::{
+    check_width, Device, DeviceError, PortDescriptor, PortId, PortIdentifier, PortValue,
+    DEFAULT_PORT_WIDTH,
+};
 
 pub struct Constant {
-    output_port: PortIdentifier,
+    ports: [PortDescriptor; 1],
     value: PortValue,
 }
 
 impl Constant {
+    /// The handle of this device's only port.
+    pub const OUTPUT: PortId = PortId(0);
+
+    /// Create a constant with a [`DEFAULT_PORT_WIDTH`]-bit output port. The value is masked to that
+    /// width.
     pub fn new(
         output_port: PortIdentifier,
         value: PortValue,
     ) -> Constant {
-        Constant {
-            output_port,
-            value,
+        Constant::with_width(output_port, DEFAULT_PORT_WIDTH, value)
+            .expect("the default port width is valid")
+    }
+
+    /// Create a constant whose output port is `width` bits wide. The value is masked to that
+    /// width.
+    pub fn with_width(
+        output_port: PortIdentifier,
+        width: u32,
+        value: PortValue,
+    ) -> Result<Constant, DeviceError> {
+        check_width(width)?;
+        let port = PortDescriptor::output(&output_port, width);
+        Ok(Constant {
+            value: port.mask(&value),
+            ports: [port],
+        })
+    }
+
+    /// Work out the error to return when a value is provided for `port`, given that this device
+    /// has no input ports.
+    fn input_port_error(&self, port: PortId) -> DeviceError {
+        match port == Self::OUTPUT {
+            true => DeviceError::NotAnInputPort { port: self.port_name(port) },
+            false => DeviceError::UnknownPort { port: self.port_name(port) },
         }
     }
 }
 
 impl Device for Constant {
-    fn get_input_ports(&self) -> HashSet<PortIdentifier> {
-        // No input ports
-        HashSet::new()
-    }
-
-    fn get_output_ports(&self) -> HashSet<PortIdentifier> {
-        let mut result = HashSet::new();
-        result.insert(self.output_port.to_owned());
-        result
+    fn get_ports(&self) -> &[PortDescriptor] {
+        // Only the one output port, no input ports
+        &self.ports
     }
 
-    fn get_output_dependencies(&self, output: &PortIdentifier) -> Result<HashSet<PortIdentifier>, DeviceError> {
-        if *output != self.output_port {
-            return Err(DeviceError);
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        if output != Self::OUTPUT {
+            return Err(DeviceError::UnknownPort { port: self.port_name(output) });
         }
         Ok(HashSet::new())
     }
 
-    fn provide_port_value(&mut self, _: PortIdentifier, _: PortValue)
+    fn provide_port_value(&mut self, port: PortId, _: PortValue)
         -> Result<(), DeviceError>
     {
         // No input ports, so this operation always fails
-        Err(DeviceError)
+        Err(self.input_port_error(port))
     }
 
-    fn provide_port_values(&mut self, _: HashMap<PortIdentifier, PortValue>)
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
         -> Result<(), DeviceError> {
-        // No input ports, so this operation always fails
-        Err(DeviceError)
+        // No input ports, so this operation always fails (unless there is nothing to provide)
+        match values.into_keys().next() {
+            None => Ok(()),
+            Some(port) => Err(self.input_port_error(port)),
+        }
     }
 
-    fn get_port_value(&self, port: &PortIdentifier) -> Result<Option<PortValue>, DeviceError> {
-        match *port == self.output_port {
-            true => Ok(Some(self.value)),
-            false => Err(DeviceError),
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        match port == Self::OUTPUT {
+            true => Ok(Some(self.value.clone())),
+            false => Err(DeviceError::UnknownPort { port: self.port_name(port) }),
         }
     }
 
+    fn clear_port_values(&mut self) {
+        // No input ports, so there is nothing to clear
+    }
+
+    fn check_tick(&self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
     fn tick(&mut self) -> Result<(), DeviceError> {
         Ok(())
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::let_unit_value)]
 mod tests {
-    use crate::device::{Device, PortIdentifier, PortValue};
+    use crate::device::{Device, DeviceError, PortId, PortIdentifier, PortValue};
     use crate::device::debug::constant::Constant;
     
     #[test]
     fn constant_can_be_instantiated() {
-        _ = Constant::new("qq".to_owned(), 0);
+        _ = Constant::new("qq".to_owned(), PortValue::ZERO);
     }
     
     #[test]
     fn constant_outputs_value_on_given_port() {
         let port: PortIdentifier = "qq".to_owned();
-        let value: PortValue = 1;
-        let debugger = Constant::new(port.clone(), value);
+        let value = PortValue::from(1u32);
+        let debugger = Constant::new(port.clone(), value.clone());
         
-        let result = debugger.get_port_value(&port);
+        let result = debugger.get_port_value(debugger.find_port(&port).unwrap());
         
         assert!(result.is_ok());
         let result = result.unwrap();
@@ -88,13 +124,47 @@ mod tests {
     #[test]
     fn constant_always_outputs_provided_value() {
         let port: PortIdentifier = "qq".to_owned();
-        let value: PortValue = 1;
-        let mut debugger = Constant::new(port.clone(), value);
+        let value = PortValue::from(1u32);
+        let mut debugger = Constant::new(port.clone(), value.clone());
     
         for _ in 0..3 {
-            let val = debugger.get_port_value(&port).unwrap();
-            assert_eq!(val, Some(value));
+            let val = debugger.get_port_value(Constant::OUTPUT).unwrap();
+            assert_eq!(val, Some(value.clone()));
             let _ = debugger.tick().unwrap();
         }
     }
+
+    #[test]
+    fn constant_cannot_have_port_values_provided() {
+        let port: PortIdentifier = "qq".to_owned();
+        let mut debugger = Constant::new(port.clone(), PortValue::from(1u32));
+
+        let result = debugger.provide_port_value(Constant::OUTPUT, PortValue::ZERO);
+        assert_eq!(result, Err(DeviceError::NotAnInputPort { port }));
+
+        let result = debugger.provide_port_value(PortId(1), PortValue::ZERO);
+        assert_eq!(result, Err(DeviceError::UnknownPort { port: "#1".to_owned() }));
+    }
+
+    #[test]
+    fn constant_masks_value_to_port_width() {
+        let value = PortValue::from(0xabu32);
+        let debugger = Constant::with_width("qq".to_owned(), 4, value).unwrap();
+        assert_eq!(debugger.get_ports()[0].width, 4);
+        assert_eq!(debugger.get_port_value(Constant::OUTPUT), Ok(Some(PortValue::from(0xbu32))));
+    }
+
+    #[test]
+    fn constant_cannot_be_instantiated_with_invalid_width() {
+        let result = Constant::with_width("qq".to_owned(), 0, PortValue::ZERO);
+        assert!(matches!(result, Err(DeviceError::InvalidWidth { width: 0 })));
+    }
+
+    #[test]
+    fn constant_can_output_values_wider_than_64_bits() {
+        let value = PortValue::from(u128::MAX);
+        let debugger = Constant::with_width("qq".to_owned(), 100, value).unwrap();
+        let expected = PortValue::all_ones(100);
+        assert_eq!(debugger.get_port_value(Constant::OUTPUT), Ok(Some(expected)));
+    }
 }
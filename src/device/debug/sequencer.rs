@@ -1,67 +1,103 @@
 use std::collections::{HashMap, HashSet};
-use crate::device::{Device, DeviceError, PortIdentifier, PortValue};
+use crate::device::{
+    check_width, Device, DeviceError, PortDescriptor, PortId, PortIdentifier, PortValue,
+    DEFAULT_PORT_WIDTH,
+};
 
 pub struct Sequencer {
-    output_port: PortIdentifier,
+    ports: [PortDescriptor; 1],
     values: Vec<PortValue>,
     current_value_idx: usize,
 }
 
 impl Sequencer {
+    /// The handle of this device's only port.
+    pub const OUTPUT: PortId = PortId(0);
+
+    /// Create a sequencer with a [`DEFAULT_PORT_WIDTH`]-bit output port. The values are masked to
+    /// that width.
     pub fn new(
         output_port: PortIdentifier,
         values: &[PortValue],
-    ) -> Result<Sequencer, ()> {
+    ) -> Result<Sequencer, DeviceError> {
+        Sequencer::with_width(output_port, DEFAULT_PORT_WIDTH, values)
+    }
+
+    /// Create a sequencer whose output port is `width` bits wide. The values are masked to that
+    /// width.
+    pub fn with_width(
+        output_port: PortIdentifier,
+        width: u32,
+        values: &[PortValue],
+    ) -> Result<Sequencer, DeviceError> {
+        check_width(width)?;
+        let port = PortDescriptor::output(&output_port, width);
         match values.is_empty() {
-            true => Err(()),
+            true => Err(DeviceError::DeviceSpecific {
+                message: "a sequencer must be given at least one value to output".to_owned(),
+            }),
             false => Ok(Sequencer {
-                output_port,
-                values: values.to_owned(),
+                values: values.iter().map(|value| port.mask(value)).collect(),
+                ports: [port],
                 current_value_idx: 0,
             })
         }
     }
-}
 
-impl Device for Sequencer {
-    fn get_input_ports(&self) -> HashSet<PortIdentifier> {
-        // No input ports
-        HashSet::new()
+    /// Work out the error to return when a value is provided for `port`, given that this device
+    /// has no input ports.
+    fn input_port_error(&self, port: PortId) -> DeviceError {
+        match port == Self::OUTPUT {
+            true => DeviceError::NotAnInputPort { port: self.port_name(port) },
+            false => DeviceError::UnknownPort { port: self.port_name(port) },
+        }
     }
+}
 
-    fn get_output_ports(&self) -> HashSet<PortIdentifier> {
-        let mut result = HashSet::new();
-        result.insert(self.output_port.to_owned());
-        result
+impl Device for Sequencer {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        // Only the one output port, no input ports
+        &self.ports
     }
 
-    fn get_output_dependencies(&self, output: &PortIdentifier) -> Result<HashSet<PortIdentifier>, DeviceError> {
-        if *output != self.output_port {
-            return Err(DeviceError);
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        if output != Self::OUTPUT {
+            return Err(DeviceError::UnknownPort { port: self.port_name(output) });
         }
         Ok(HashSet::new())
     }
 
-    fn provide_port_value(&mut self, _: PortIdentifier, _: PortValue)
+    fn provide_port_value(&mut self, port: PortId, _: PortValue)
         -> Result<(), DeviceError>
     {
         // No input ports, so this operation always fails
-        Err(DeviceError)
+        Err(self.input_port_error(port))
     }
 
-    fn provide_port_values(&mut self, _: HashMap<PortIdentifier, PortValue>)
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
         -> Result<(), DeviceError> {
-        // No input ports, so this operation always fails
-        Err(DeviceError)
+        // No input ports, so this operation always fails (unless there is nothing to provide)
+        match values.into_keys().next() {
+            None => Ok(()),
+            Some(port) => Err(self.input_port_error(port)),
+        }
     }
 
-    fn get_port_value(&self, port: &PortIdentifier) -> Result<Option<PortValue>, DeviceError> {
-        match *port == self.output_port {
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        match port == Self::OUTPUT {
             true => Ok(self.values.get(self.current_value_idx).cloned()),
-            false => Err(DeviceError),
+            false => Err(DeviceError::UnknownPort { port: self.port_name(port) }),
         }
     }
 
+    fn clear_port_values(&mut self) {
+        // No input ports, so there is nothing to clear
+    }
+
+    fn check_tick(&self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
     fn tick(&mut self) -> Result<(), DeviceError> {
         self.current_value_idx += 1;
         if self.current_value_idx >= self.values.len() {
@@ -72,9 +108,14 @@ impl Device for Sequencer {
 }
 
 #[cfg(test)]
+#[allow(clippy::let_unit_value, clippy::needless_range_loop, clippy::useless_vec)]
 mod tests {
     use crate::device::debug::sequencer::Sequencer;
-    use crate::device::{Device, PortIdentifier, PortValue};
+    use crate::device::{Device, DeviceError, PortId, PortIdentifier, PortValue};
+
+    fn port_values(values: &[u64]) -> Vec<PortValue> {
+        values.iter().map(|value| PortValue::from(*value)).collect()
+    }
 
     #[test]
     fn sequencer_cannot_be_instantiated_if_no_values_given() {
@@ -84,17 +125,17 @@ mod tests {
     
     #[test]
     fn sequencer_can_be_instantiated() {
-        let result = Sequencer::new("qq".to_owned(), &vec![0]);
+        let result = Sequencer::new("qq".to_owned(), &port_values(&vec![0]));
         assert!(result.is_ok());
     }
     
     #[test]
     fn sequencer_outputs_values_on_given_port() {
         let port: PortIdentifier = "qq".to_owned();
-        let value: PortValue = 1;
-        let sequencer = Sequencer::new(port.clone(), &vec![value]).unwrap();
+        let value = PortValue::from(1u32);
+        let sequencer = Sequencer::new(port.clone(), &vec![value.clone()]).unwrap();
         
-        let result = sequencer.get_port_value(&port);
+        let result = sequencer.get_port_value(sequencer.find_port(&port).unwrap());
         
         assert!(result.is_ok());
         let result = result.unwrap();
@@ -104,12 +145,12 @@ mod tests {
     #[test]
     fn sequencer_outputs_provided_values_in_order() {
         let port: PortIdentifier = "qq".to_owned();
-        let values: Vec<PortValue> = vec![1, 2, 3];
+        let values = port_values(&[1, 2, 3]);
         let mut sequencer = Sequencer::new(port.clone(), &values).unwrap();
     
         for idx in 0..3 {
-            let value = sequencer.get_port_value(&port).unwrap();
-            assert_eq!(value, Some(values[idx]));
+            let value = sequencer.get_port_value(Sequencer::OUTPUT).unwrap();
+            assert_eq!(value, Some(values[idx].clone()));
             let _ = sequencer.tick().unwrap();
         }
     }
@@ -117,14 +158,35 @@ mod tests {
     #[test]
     fn sequencer_loops_when_end_of_values_reached() {
         let port: PortIdentifier = "qq".to_owned();
-        let values: Vec<PortValue> = vec![1, 2, 3];
+        let values = port_values(&[1, 2, 3]);
         let mut sequencer = Sequencer::new(port.clone(), &values).unwrap();
     
-        let expected: Vec<PortValue> = vec![1, 2, 3, 1, 2, 3, 1, 2, 3];
+        let expected = port_values(&[1, 2, 3, 1, 2, 3, 1, 2, 3]);
         for idx in 0..9 {
-            let value = sequencer.get_port_value(&port).unwrap();
-            assert_eq!(value, Some(expected[idx]));
+            let value = sequencer.get_port_value(Sequencer::OUTPUT).unwrap();
+            assert_eq!(value, Some(expected[idx].clone()));
             let _ = sequencer.tick().unwrap();
         }
     }
+
+    #[test]
+    fn sequencer_cannot_have_port_values_provided() {
+        let port: PortIdentifier = "qq".to_owned();
+        let mut sequencer = Sequencer::new(port.clone(), &port_values(&[1])).unwrap();
+
+        let result = sequencer.provide_port_value(Sequencer::OUTPUT, PortValue::ZERO);
+        assert_eq!(result, Err(DeviceError::NotAnInputPort { port }));
+
+        let result = sequencer.provide_port_value(PortId(1), PortValue::ZERO);
+        assert_eq!(result, Err(DeviceError::UnknownPort { port: "#1".to_owned() }));
+    }
+
+    #[test]
+    fn sequencer_masks_values_to_port_width() {
+        let values = port_values(&[5, 6]);
+        let mut sequencer = Sequencer::with_width("qq".to_owned(), 2, &values).unwrap();
+        assert_eq!(sequencer.get_port_value(Sequencer::OUTPUT), Ok(Some(PortValue::from(1u32))));
+        sequencer.tick().unwrap();
+        assert_eq!(sequencer.get_port_value(Sequencer::OUTPUT), Ok(Some(PortValue::from(2u32))));
+    }
 }
@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::device::image::{Endianness, Image, ImageError, WordLayout};
+use crate::device::PortValue;
+
+/// How many words [`Dump::to_hex`] writes on each line.
+const WORDS_PER_LINE: usize = 8;
+
+/// A snapshot of the words stored in part of a memory, taken with
+/// [`Memory::dump`](crate::device::memory::Memory::dump) or
+/// [`Rom::dump`](crate::device::rom::Rom::dump), or read from a golden file to compare against.
+///
+/// Addresses are counted in words. Unlike an [`Image`], a dump can hold words with unknown bits,
+/// so it is what to use when checking the results of a four-state simulation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dump {
+    /// How many bits wide each word is.
+    width: u32,
+    words: BTreeMap<u64, PortValue>,
+}
+
+/// A word that differs between two dumps, as found by [`Dump::diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordDiff {
+    pub address: u64,
+
+    /// The word in the expected dump, or `None` if it doesn't have one at this address.
+    pub expected: Option<PortValue>,
+
+    /// The word in the actual dump, or `None` if it doesn't have one at this address.
+    pub actual: Option<PortValue>,
+}
+
+impl fmt::Display for WordDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}: ", self.address)?;
+        match (&self.expected, &self.actual) {
+            (Some(expected), Some(actual)) => write!(f, "expected {expected:#x}, got {actual:#x}"),
+            (Some(expected), None) => write!(f, "expected {expected:#x}, got nothing"),
+            (None, Some(actual)) => write!(f, "expected nothing, got {actual:#x}"),
+            (None, None) => write!(f, "no difference"),
+        }
+    }
+}
+
+impl Dump {
+    /// Create an empty dump of `width`-bit words.
+    pub fn new(width: u32) -> Dump {
+        Dump { width, words: BTreeMap::new() }
+    }
+
+    /// Get how many bits wide each word is.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Store a word at the given address, replacing anything already there.
+    pub fn insert(&mut self, address: u64, value: PortValue) {
+        self.words.insert(address, value);
+    }
+
+    /// Get the word at the given address, if the dump has one.
+    pub fn word(&self, address: u64) -> Option<&PortValue> {
+        self.words.get(&address)
+    }
+
+    /// Get every word in the dump, lowest address first.
+    pub fn words(&self) -> impl Iterator<Item = (u64, &PortValue)> + '_ {
+        self.words.iter().map(|(address, value)| (*address, value))
+    }
+
+    /// Whether the dump has no words.
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Group the bytes of an image into words as given by `layout`, such as to compare a memory
+    /// against a golden Intel HEX or binary file.
+    ///
+    /// Fails if the layout's words are empty or too wide ([`ImageError::InvalidLayout`]).
+    pub fn from_image(image: &Image, layout: WordLayout) -> Result<Dump, ImageError> {
+        let width = layout_width(layout)?;
+        Ok(Dump { width, words: image.words(layout).into_iter().collect() })
+    }
+
+    /// Split each word into bytes as given by `layout`, for writing as a binary image or Intel
+    /// HEX. This is the reverse of [`Dump::from_image`].
+    ///
+    /// Fails if the layout's words are empty or too wide ([`ImageError::InvalidLayout`]), if a
+    /// word has unknown bits or doesn't fit in a word of the layout ([`ImageError::InvalidWord`]),
+    /// or if its bytes' addresses don't fit in 64 bits ([`ImageError::AddressOutOfRange`]).
+    pub fn to_image(&self, layout: WordLayout) -> Result<Image, ImageError> {
+        let width = layout_width(layout)?;
+        let mut image = Image::new();
+        for (address, value) in self.words() {
+            let invalid = |message: String| ImageError::InvalidWord { address, message };
+            if !value.is_known() {
+                return Err(invalid("cannot write unknown bits as bytes".to_owned()));
+            }
+            if value.bit_length() > width {
+                return Err(invalid(format!("{value:#x} doesn't fit in {} bytes", layout.bytes)));
+            }
+            let base = address.checked_mul(layout.bytes as u64)
+                .ok_or(ImageError::AddressOutOfRange { address })?;
+            let mut bytes: Vec<u8> = (0..layout.bytes)
+                .map(|index| value.slice(index * 8, 8).low_u64() as u8)
+                .collect();
+            if layout.endianness == Endianness::Big {
+                bytes.reverse();
+            }
+            image.insert(base, &bytes);
+        }
+        Ok(image)
+    }
+
+    /// Write the dump as text, one line per run of up to 8 consecutive words, each line starting
+    /// with the address of its first word. For example:
+    ///
+    /// ```text
+    /// 00000010: 0000002a deadbeef xxxxxxxx
+    /// ```
+    ///
+    /// Every word is written with the same number of hex digits. Unknown bits are written as by
+    /// [`PortValue`]'s [`LowerHex`](fmt::LowerHex) implementation.
+    pub fn to_hex(&self) -> String {
+        let digits = self.width.div_ceil(4) as usize;
+        let mut text = String::new();
+        let mut next = None;
+        let mut on_line = 0;
+        for (address, value) in self.words() {
+            if next != Some(address) || on_line == WORDS_PER_LINE {
+                if next.is_some() {
+                    text.push('\n');
+                }
+                text.push_str(&format!("{address:08x}:"));
+                on_line = 0;
+            }
+            text.push_str(&format!(" {:0>digits$}", format!("{value:x}")));
+            next = address.checked_add(1);
+            on_line += 1;
+        }
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Parse text written by [`Dump::to_hex`] back into a dump of `width`-bit words. Blank lines
+    /// are ignored.
+    ///
+    /// Hex digits may be written as `x` or `z` if all four of their bits are X or Z. Digits only
+    /// partly unknown (`X` or `Z`) can't be read back, so are rejected, as are words with bits set
+    /// beyond `width` ([`ImageError::InvalidRecord`]).
+    pub fn from_hex(text: &str, width: u32) -> Result<Dump, ImageError> {
+        let mut dump = Dump::new(width);
+        for (index, record) in text.lines().enumerate() {
+            let line = index + 1;
+            let invalid = |message: String| ImageError::InvalidRecord { line, message };
+            let record = record.trim();
+            if record.is_empty() {
+                continue;
+            }
+            let Some((address, words)) = record.split_once(':') else {
+                return Err(invalid("expected an address followed by `:`".to_owned()));
+            };
+            let address = u64::from_str_radix(address, 16)
+                .map_err(|_| invalid(format!("`{address}` is not a hex address")))?;
+            for (offset, word) in words.split_whitespace().enumerate() {
+                let value = parse_hex_word(word)
+                    .ok_or_else(|| invalid(format!("`{word}` is not a hex word")))?;
+                if value.bit_length() > width {
+                    return Err(invalid(format!("`{word}` doesn't fit in {width} bits")));
+                }
+                let address = address.checked_add(offset as u64)
+                    .ok_or_else(|| invalid(format!("`{word}` is past the last address")))?;
+                dump.insert(address, value);
+            }
+        }
+        Ok(dump)
+    }
+
+    /// Compare this dump, holding the words expected, against `actual`, returning every address
+    /// at which they differ, lowest first.
+    ///
+    /// Words are compared exactly, so an X expected only matches an X, and a word that is in one
+    /// dump but not the other is a difference even if it is 0.
+    pub fn diff(&self, actual: &Dump) -> Vec<WordDiff> {
+        let mut addresses: Vec<u64> = self.words.keys().chain(actual.words.keys())
+            .copied()
+            .collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+        addresses.into_iter()
+            .filter(|address| self.word(*address) != actual.word(*address))
+            .map(|address| WordDiff {
+                address,
+                expected: self.word(address).cloned(),
+                actual: actual.word(address).cloned(),
+            })
+            .collect()
+    }
+}
+
+/// Get how many bits wide the words of `layout` are, failing if they are empty or too wide to be
+/// held as a value ([`ImageError::InvalidLayout`]).
+fn layout_width(layout: WordLayout) -> Result<u32, ImageError> {
+    layout.bytes.checked_mul(8)
+        .filter(|width| *width > 0)
+        .ok_or(ImageError::InvalidLayout { bytes: layout.bytes })
+}
+
+/// Parse a word written in hex, allowing `x` and `z` for digits whose bits are all X or Z.
+fn parse_hex_word(word: &str) -> Option<PortValue> {
+    let mut binary = String::with_capacity(word.len() * 4);
+    for digit in word.chars() {
+        match digit {
+            'x' | 'z' => (0..4).for_each(|_| binary.push(digit)),
+            _ => binary.push_str(&format!("{:04b}", digit.to_digit(16)?)),
+        }
+    }
+    binary.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::device::dump::{Dump, WordDiff};
+    use crate::device::image::{Endianness, Image, ImageError, WordLayout};
+    use crate::device::PortValue;
+
+    /// A dump of 16-bit words with a gap and an unknown word.
+    fn sample() -> Dump {
+        let mut dump = Dump::new(16);
+        for address in 0..10 {
+            dump.insert(address, PortValue::from(address as u32 * 0x101));
+        }
+        dump.insert(0x20, PortValue::from(0xbeefu32));
+        dump.insert(0x21, "xxxx_xxxx_0000_zzzz".parse().unwrap());
+        dump
+    }
+
+    #[test]
+    fn dump_is_written_as_hex_lines() {
+        assert_eq!(sample().to_hex(), "\
+            00000000: 0000 0101 0202 0303 0404 0505 0606 0707\n\
+            00000008: 0808 0909\n\
+            00000020: beef xx0z\n");
+        assert_eq!(Dump::new(8).to_hex(), "");
+    }
+
+    #[test]
+    fn hex_dump_can_be_read_back() {
+        let dump = sample();
+        assert_eq!(Dump::from_hex(&dump.to_hex(), 16), Ok(dump));
+
+        let texts = ["0000 1234", "00g0: 1234", "0000: 12g4", "0000: 1X34", "0000: 12345",
+            "ffffffffffffffff: 0000 0000"];
+        for text in texts {
+            assert!(matches!(
+                Dump::from_hex(text, 16),
+                Err(ImageError::InvalidRecord { line: 1, .. })
+            ), "{text}");
+        }
+    }
+
+    #[test]
+    fn dump_converts_to_and_from_images() {
+        let mut dump = Dump::new(16);
+        dump.insert(1, PortValue::from(0x1234u32));
+        let layout = WordLayout::new(2, Endianness::Big);
+        let image = dump.to_image(layout).unwrap();
+        assert_eq!(image, Image::from_binary(&[0x12, 0x34], 2));
+        assert_eq!(Dump::from_image(&image, layout), Ok(dump.clone()));
+        for bytes in [0, 1 << 29] {
+            let layout = WordLayout::new(bytes, Endianness::Little);
+            assert_eq!(Dump::from_image(&image, layout), Err(ImageError::InvalidLayout { bytes }));
+            assert_eq!(dump.to_image(layout), Err(ImageError::InvalidLayout { bytes }));
+        }
+
+        dump.insert(2, PortValue::x(16));
+        assert!(matches!(dump.to_image(layout), Err(ImageError::InvalidWord { address: 2, .. })));
+        assert!(matches!(
+            sample().to_image(WordLayout::new(1, Endianness::Little)),
+            Err(ImageError::InvalidWord { address: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn diff_reports_changed_missing_and_extra_words() {
+        let expected = sample();
+        let mut actual = sample();
+        actual.insert(3, PortValue::from(0x0304u32));
+        actual.insert(0x30, PortValue::ZERO);
+        actual.words.remove(&0x21);
+
+        let diff = expected.diff(&actual);
+        assert_eq!(diff, [
+            WordDiff {
+                address: 3,
+                expected: Some(PortValue::from(0x0303u32)),
+                actual: Some(PortValue::from(0x0304u32)),
+            },
+            WordDiff { address: 0x21, expected: expected.word(0x21).cloned(), actual: None },
+            WordDiff { address: 0x30, expected: None, actual: Some(PortValue::ZERO) },
+        ]);
+        assert_eq!(diff[0].to_string(), "0x3: expected 0x303, got 0x304");
+        assert_eq!(diff[1].to_string(), "0x21: expected 0xxx0z, got nothing");
+        assert!(expected.diff(&sample()).is_empty());
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use crate::device::{DeviceError, PortValue};
+
+/// The order of the bytes in a word wider than one byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Endianness {
+    /// The byte at the lowest address is the least significant.
+    #[default]
+    Little,
+    /// The byte at the lowest address is the most significant.
+    Big,
+}
+
+/// How the bytes of an [`Image`] are grouped into the words stored by a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WordLayout {
+    /// How many bytes make up each word. Word `n` is made of the bytes starting at address
+    /// `n * bytes`.
+    pub bytes: u32,
+    pub endianness: Endianness,
+}
+
+impl WordLayout {
+    /// A layout of `bytes`-byte words in the given byte order.
+    pub fn new(bytes: u32, endianness: Endianness) -> WordLayout {
+        WordLayout { bytes, endianness }
+    }
+}
+
+/// The formats an [`Image`] can be read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    /// The bytes of the file, stored from address 0.
+    Binary,
+    /// Intel HEX, as produced by most assemblers and `objcopy -O ihex`.
+    IntelHex,
+    /// Motorola S-record, as produced by `objcopy -O srec`.
+    SRecord,
+}
+
+/// Represents an error reading or writing an [`Image`] or [`Dump`](crate::device::dump::Dump).
+///
+/// Errors in a text format give the line they were found on, counting from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageError {
+    /// The file couldn't be read.
+    Io { path: String, message: String },
+
+    /// A line isn't a valid record.
+    InvalidRecord { line: usize, message: String },
+
+    /// A record's checksum doesn't match its contents.
+    ChecksumMismatch { line: usize, expected: u8, actual: u8 },
+
+    /// A byte's address is too large for the format being written.
+    AddressOutOfRange { address: u64 },
+
+    /// A word can't be turned into bytes, because it has unknown bits or doesn't fit.
+    InvalidWord { address: u64, message: String },
+
+    /// A [`WordLayout`]'s words are empty, or too wide to be held as a value.
+    InvalidLayout { bytes: u32 },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io { path, message } => write!(f, "cannot read `{path}`: {message}"),
+            ImageError::InvalidRecord { line, message } =>
+                write!(f, "invalid record on line {line}: {message}"),
+            ImageError::ChecksumMismatch { line, expected, actual } => write!(f,
+                "checksum of record on line {line} is {actual:#04x}, expected {expected:#04x}"),
+            ImageError::AddressOutOfRange { address } =>
+                write!(f, "address {address:#x} is out of range for this format"),
+            ImageError::InvalidWord { address, message } =>
+                write!(f, "invalid word at address {address:#x}: {message}"),
+            ImageError::InvalidLayout { bytes } => write!(f, "cannot use {bytes}-byte words"),
+        }
+    }
+}
+
+impl Error for ImageError {}
+
+/// The contents of a firmware image or memory dump: bytes at (not necessarily contiguous)
+/// addresses.
+///
+/// An image is loaded into a device with [`Memory::load`](crate::device::memory::Memory::load) or
+/// [`Rom::load`](crate::device::rom::Rom::load), which group its bytes into words as given by a
+/// [`WordLayout`]. If a record gives a byte that an earlier record already gave, the later one
+/// wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    bytes: BTreeMap<u64, u8>,
+}
+
+impl Image {
+    /// Create an empty image.
+    pub fn new() -> Image {
+        Image::default()
+    }
+
+    /// Create an image holding the given bytes, starting at address `base`.
+    pub fn from_binary(data: &[u8], base: u64) -> Image {
+        let mut image = Image::new();
+        image.insert(base, data);
+        image
+    }
+
+    /// Parse an image from Intel HEX records.
+    ///
+    /// Data, end of file, extended segment address and extended linear address records are
+    /// understood; start address records are ignored. Anything after the end of file record is
+    /// ignored, as are blank lines.
+    pub fn from_intel_hex(text: &str) -> Result<Image, ImageError> {
+        let mut image = Image::new();
+        let mut base = 0;
+        for (index, record) in text.lines().enumerate() {
+            let line = index + 1;
+            let record = record.trim();
+            if record.is_empty() {
+                continue;
+            }
+            let Some(hex) = record.strip_prefix(':') else {
+                return Err(invalid(line, "Intel HEX records start with `:`"));
+            };
+            let bytes = parse_hex(hex, line)?;
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(invalid(line, "the length of the record doesn't match its byte count"));
+            }
+            let (body, checksum) = bytes.split_at(bytes.len() - 1);
+            let expected = body.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+                .wrapping_neg();
+            check_sum(line, expected, checksum[0])?;
+
+            let offset = u16::from_be_bytes([body[1], body[2]]) as u64;
+            let data = &body[4..];
+            match body[3] {
+                0x00 => image.insert(base + offset, data),
+                0x01 => break,
+                0x02 => base = be_value(data, 2, line)? << 4,
+                0x04 => base = be_value(data, 2, line)? << 16,
+                0x03 | 0x05 => {}
+                kind => return Err(invalid(line, &format!("unknown record type {kind:02x}"))),
+            }
+        }
+        Ok(image)
+    }
+
+    /// Parse an image from Motorola S-records.
+    ///
+    /// Data is taken from S1, S2 and S3 records. Header, count and start address records are
+    /// checked but otherwise ignored, as are blank lines.
+    pub fn from_srecord(text: &str) -> Result<Image, ImageError> {
+        let mut image = Image::new();
+        for (index, record) in text.lines().enumerate() {
+            let line = index + 1;
+            let record = record.trim();
+            if record.is_empty() {
+                continue;
+            }
+            let mut chars = record.chars();
+            let (Some('S'), Some(kind)) = (chars.next(), chars.next()) else {
+                return Err(invalid(line, "S-records start with `S` and a record type"));
+            };
+            let address_bytes = match kind {
+                '0' | '1' | '5' | '9' => 2,
+                '2' | '6' | '8' => 3,
+                '3' | '7' => 4,
+                _ => return Err(invalid(line, &format!("unknown record type S{kind}"))),
+            };
+            let bytes = parse_hex(chars.as_str(), line)?;
+            if bytes.len() < address_bytes + 2 || bytes.len() != bytes[0] as usize + 1 {
+                return Err(invalid(line, "the length of the record doesn't match its byte count"));
+            }
+            let (body, checksum) = bytes.split_at(bytes.len() - 1);
+            let expected = !body.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            check_sum(line, expected, checksum[0])?;
+
+            if matches!(kind, '1' | '2' | '3') {
+                let address = be_value(&body[1..=address_bytes], address_bytes, line)?;
+                image.insert(address, &body[address_bytes + 1..]);
+            }
+        }
+        Ok(image)
+    }
+
+    /// Read an image from a file in the given format.
+    pub fn read(path: impl AsRef<Path>, format: ImageFormat) -> Result<Image, ImageError> {
+        let path = path.as_ref();
+        let io_error = |error: std::io::Error| ImageError::Io {
+            path: path.display().to_string(),
+            message: error.to_string(),
+        };
+        match format {
+            ImageFormat::Binary => Ok(Image::from_binary(&fs::read(path).map_err(io_error)?, 0)),
+            ImageFormat::IntelHex => Image::from_intel_hex(&fs::read_to_string(path)
+                .map_err(io_error)?),
+            ImageFormat::SRecord => Image::from_srecord(&fs::read_to_string(path)
+                .map_err(io_error)?),
+        }
+    }
+
+    /// Get the bytes at the given addresses, with 0 for any the image doesn't have. This is the
+    /// reverse of [`Image::from_binary`].
+    pub fn to_binary(&self, addresses: Range<u64>) -> Vec<u8> {
+        let mut data = vec![0; addresses.end.saturating_sub(addresses.start) as usize];
+        for (address, byte) in self.bytes.range(addresses.clone()) {
+            data[(address - addresses.start) as usize] = *byte;
+        }
+        data
+    }
+
+    /// Write the image as Intel HEX records, with up to 16 bytes in each data record and extended
+    /// linear address records wherever the top 16 bits of the address change.
+    ///
+    /// Fails if a byte's address doesn't fit in 32 bits ([`ImageError::AddressOutOfRange`]).
+    pub fn to_intel_hex(&self) -> Result<String, ImageError> {
+        let mut text = String::new();
+        let mut upper = 0;
+        let mut start = 0;
+        let mut data: Vec<u8> = Vec::new();
+        for (address, byte) in self.bytes() {
+            if address > u32::MAX as u64 {
+                return Err(ImageError::AddressOutOfRange { address });
+            }
+            let follows = address == start + data.len() as u64 && address >> 16 == start >> 16;
+            if data.is_empty() || !follows || data.len() == 16 {
+                if !data.is_empty() {
+                    text.push_str(&hex_record(0x00, start as u16, &data));
+                }
+                data.clear();
+                start = address;
+                if address >> 16 != upper {
+                    upper = address >> 16;
+                    text.push_str(&hex_record(0x04, 0, &(upper as u16).to_be_bytes()));
+                }
+            }
+            data.push(byte);
+        }
+        if !data.is_empty() {
+            text.push_str(&hex_record(0x00, start as u16, &data));
+        }
+        text.push_str(&hex_record(0x01, 0, &[]));
+        Ok(text)
+    }
+
+    /// Store the given bytes, starting at address `base`.
+    pub fn insert(&mut self, base: u64, data: &[u8]) {
+        for (address, byte) in (base..).zip(data) {
+            self.bytes.insert(address, *byte);
+        }
+    }
+
+    /// Get the byte at the given address, if the image has one.
+    pub fn byte(&self, address: u64) -> Option<u8> {
+        self.bytes.get(&address).copied()
+    }
+
+    /// Get every byte in the image, lowest address first.
+    pub fn bytes(&self) -> impl Iterator<Item = (u64, u8)> + '_ {
+        self.bytes.iter().map(|(address, byte)| (*address, *byte))
+    }
+
+    /// Whether the image has no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Group the image's bytes into words, returning each word's address (counted in words) and
+    /// value, lowest address first. Bytes of a word that the image doesn't have are taken as 0.
+    pub fn words(&self, layout: WordLayout) -> Vec<(u64, PortValue)> {
+        let size = layout.bytes.max(1) as u64;
+        let mut words: Vec<(u64, PortValue)> = Vec::new();
+        let mut current: Option<(u64, Vec<u8>)> = None;
+        for (address, byte) in self.bytes() {
+            let (word, offset) = (address / size, (address % size) as usize);
+            if current.as_ref().is_some_and(|(current, _)| *current != word) {
+                let (word, bytes) = current.take().unwrap();
+                words.push((word, word_value(bytes, layout.endianness)));
+            }
+            current.get_or_insert_with(|| (word, vec![0; size as usize])).1[offset] = byte;
+        }
+        if let Some((word, bytes)) = current {
+            words.push((word, word_value(bytes, layout.endianness)));
+        }
+        words
+    }
+
+    /// Group the image's bytes into words (see [`Image::words`]), and check that every word's
+    /// address fits in `address_width` bits and value in `data_width` bits, as needed to load the
+    /// image into a device with those widths.
+    pub(crate) fn words_to_load(&self, layout: WordLayout, address_width: u32, data_width: u32)
+        -> Result<Vec<(PortValue, PortValue)>, DeviceError>
+    {
+        if layout.bytes == 0 {
+            return Err(DeviceError::DeviceSpecific {
+                message: "words must be at least one byte".to_owned(),
+            });
+        }
+        self.words(layout).into_iter()
+            .map(|(address, value)| {
+                let address = PortValue::from(address);
+                if address.bit_length() > address_width {
+                    return Err(DeviceError::DeviceSpecific {
+                        message: format!("word address {address:#x} of the image doesn't fit in \
+                            {address_width} bits"),
+                    });
+                }
+                if value.bit_length() > data_width {
+                    return Err(DeviceError::DeviceSpecific {
+                        message: format!("word {value:#x} at address {address:#x} of the image \
+                            doesn't fit in {data_width} bits"),
+                    });
+                }
+                Ok((address, value))
+            })
+            .collect()
+    }
+}
+
+/// Build a word's value from its bytes, lowest address first.
+fn word_value(mut bytes: Vec<u8>, endianness: Endianness) -> PortValue {
+    if endianness == Endianness::Big {
+        bytes.reverse();
+    }
+    let limbs: Vec<u64> = bytes.chunks(8)
+        .map(|chunk| chunk.iter().rev().fold(0, |limb, byte| limb << 8 | *byte as u64))
+        .collect();
+    PortValue::from_limbs(&limbs)
+}
+
+/// Write a single Intel HEX record, including its checksum and a newline.
+fn hex_record(kind: u8, offset: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(offset.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    bytes.push(bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg());
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+    format!(":{hex}\n")
+}
+
+/// An [`ImageError::InvalidRecord`] on the given line.
+fn invalid(line: usize, message: &str) -> ImageError {
+    ImageError::InvalidRecord { line, message: message.to_owned() }
+}
+
+/// Check a record's checksum.
+fn check_sum(line: usize, expected: u8, actual: u8) -> Result<(), ImageError> {
+    match expected == actual {
+        true => Ok(()),
+        false => Err(ImageError::ChecksumMismatch { line, expected, actual }),
+    }
+}
+
+/// Parse pairs of hex digits into bytes.
+fn parse_hex(hex: &str, line: usize) -> Result<Vec<u8>, ImageError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(invalid(line, "expected pairs of hex digits"));
+    }
+    (0..hex.len()).step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16)
+            .map_err(|_| invalid(line, &format!("`{}` is not a hex byte", &hex[index..index + 2]))))
+        .collect()
+}
+
+/// Read a big-endian number from a record field, which must be exactly `length` bytes.
+fn be_value(bytes: &[u8], length: usize, line: usize) -> Result<u64, ImageError> {
+    match bytes.len() == length {
+        true => Ok(bytes.iter().fold(0, |value, byte| value << 8 | *byte as u64)),
+        false => Err(invalid(line, &format!("expected a {length}-byte address"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::device::image::{Endianness, Image, ImageError, ImageFormat, WordLayout};
+    use crate::device::PortValue;
+
+    #[test]
+    fn binary_images_start_at_their_base() {
+        let image = Image::from_binary(&[1, 2, 3], 0x10);
+        let bytes: Vec<(u64, u8)> = image.bytes().collect();
+        assert_eq!(bytes, [(0x10, 1), (0x11, 2), (0x12, 3)]);
+        assert_eq!(image.byte(0), None);
+    }
+
+    #[test]
+    fn words_are_built_in_either_byte_order() {
+        let image = Image::from_binary(&[0x78, 0x56, 0x34, 0x12, 0xaa], 4);
+        assert_eq!(image.words(WordLayout::new(4, Endianness::Little)), [
+            (1, PortValue::from(0x1234_5678u32)),
+            (2, PortValue::from(0xaau32)),
+        ]);
+        assert_eq!(image.words(WordLayout::new(2, Endianness::Big)), [
+            (2, PortValue::from(0x7856u32)),
+            (3, PortValue::from(0x3412u32)),
+            (4, PortValue::from(0xaa00u32)),
+        ]);
+
+        // Words wider than 64 bits
+        let image = Image::from_binary(&[0xff; 9], 0);
+        assert_eq!(image.words(WordLayout::new(9, Endianness::Little))[0].1.bit_length(), 72);
+    }
+
+    #[test]
+    fn intel_hex_records_are_parsed() {
+        let text = "\
+            :0400000001020304F2\n\
+            \n\
+            :020000040001F9\n\
+            :02001000AABB89\n\
+            :00000001FF\n\
+            :0100000000FF\n";
+        let image = Image::from_intel_hex(text).unwrap();
+        let bytes: Vec<(u64, u8)> = image.bytes().collect();
+        assert_eq!(bytes, [(0, 1), (1, 2), (2, 3), (3, 4), (0x1_0010, 0xaa), (0x1_0011, 0xbb)]);
+    }
+
+    #[test]
+    fn intel_hex_errors_name_the_line() {
+        assert_eq!(
+            Image::from_intel_hex(":00000001FF\n").map(|image| image.is_empty()),
+            Ok(true),
+        );
+        assert_eq!(
+            Image::from_intel_hex("\n:0100000000FE\n"),
+            Err(ImageError::ChecksumMismatch { line: 2, expected: 0xff, actual: 0xfe }),
+        );
+        for text in ["0100000000FF", ":0200000000FF", ":01000000G0FF", ":0100000600F9"] {
+            assert!(matches!(
+                Image::from_intel_hex(text),
+                Err(ImageError::InvalidRecord { line: 1, .. })
+            ), "{text}");
+        }
+    }
+
+    #[test]
+    fn srecords_are_parsed() {
+        let text = "\
+            S00600004844521B\n\
+            S1060010010203E3\n\
+            S2060100000A0BE3\n\
+            S30700000020FFEEEB\n\
+            S5030003F9\n\
+            S9030000FC\n";
+        let image = Image::from_srecord(text).unwrap();
+        let bytes: Vec<(u64, u8)> = image.bytes().collect();
+        assert_eq!(bytes, [
+            (0x10, 1), (0x11, 2), (0x12, 3), (0x20, 0xff), (0x21, 0xee),
+            (0x1_0000, 0x0a), (0x1_0001, 0x0b),
+        ]);
+    }
+
+    #[test]
+    fn srecord_errors_name_the_line() {
+        assert_eq!(
+            Image::from_srecord("S1060010010203E4"),
+            Err(ImageError::ChecksumMismatch { line: 1, expected: 0xe3, actual: 0xe4 }),
+        );
+        for text in ["S4030000FC", "1060010010203E3", "S1070010010203E3", "S10200FD"] {
+            assert!(matches!(
+                Image::from_srecord(text),
+                Err(ImageError::InvalidRecord { line: 1, .. })
+            ), "{text}");
+        }
+    }
+
+    #[test]
+    fn binary_output_fills_gaps_with_zero() {
+        let mut image = Image::from_binary(&[1, 2], 4);
+        image.insert(8, &[3]);
+        assert_eq!(image.to_binary(3..9), [0, 1, 2, 0, 0, 3]);
+        assert_eq!(image.to_binary(5..5), []);
+    }
+
+    #[test]
+    fn intel_hex_output_can_be_read_back() {
+        let mut image = Image::from_binary(&[0xaa; 20], 0xfff0);
+        image.insert(0x2_0000, &[1, 2, 3]);
+        let text = image.to_intel_hex().unwrap();
+        assert_eq!(text.lines().collect::<Vec<&str>>(), [
+            ":10FFF000AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA61",
+            ":020000040001F9",
+            ":04000000AAAAAAAA54",
+            ":020000040002F8",
+            ":03000000010203F7",
+            ":00000001FF",
+        ]);
+        assert_eq!(Image::from_intel_hex(&text), Ok(image));
+
+        let image = Image::from_binary(&[0], 1 << 32);
+        assert_eq!(image.to_intel_hex(), Err(ImageError::AddressOutOfRange { address: 1 << 32 }));
+    }
+
+    #[test]
+    fn images_can_be_read_from_files() {
+        let path = std::env::temp_dir().join(format!("image-test-{}.hex", std::process::id()));
+        std::fs::write(&path, ":020000001234B8\n:00000001FF\n").unwrap();
+        let hex = Image::read(&path, ImageFormat::IntelHex).unwrap();
+        let binary = Image::read(&path, ImageFormat::Binary).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(hex, Image::from_binary(&[0x12, 0x34], 0));
+        assert_eq!(binary.byte(0), Some(b':'));
+        assert!(matches!(
+            Image::read(&path, ImageFormat::SRecord),
+            Err(ImageError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+use crate::device::{
+    check_width, output_port_error, provide_all_into, provide_into, Device, DeviceError,
+    PortDescriptor, PortId, PortValue, DEFAULT_PORT_WIDTH,
+};
+
+/// Which logic function a [`Gate`] computes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GateKind {
+    And,
+    Or,
+    Xor,
+    Nand,
+    Nor,
+    Xnor,
+    /// Inverts its only input.
+    Not,
+}
+
+impl GateKind {
+    /// Whether the output is inverted after combining the inputs.
+    fn is_inverted(&self) -> bool {
+        matches!(self, GateKind::Nand | GateKind::Nor | GateKind::Xnor | GateKind::Not)
+    }
+}
+
+/// A bitwise logic gate with any number of inputs, all the same width as its output.
+///
+/// The inputs are called `in0`, `in1` and so on, and the output `out`. The output depends on every
+/// input, but resolves as soon as the inputs provided are enough to decide it: a 0 on any input of
+/// an AND gate decides that bit, for example. Unknown input bits give X output bits wherever they
+/// could make a difference.
+pub struct Gate {
+    kind: GateKind,
+    specified_this_tick: Vec<Option<PortValue>>,
+    ports: Vec<PortDescriptor>,
+}
+
+impl Gate {
+    /// The handle of the gate's output port, which comes after every input.
+    pub fn output(&self) -> PortId {
+        PortId(self.ports.len() - 1)
+    }
+
+    /// The handle of the gate's `index`th input port.
+    pub fn input(index: usize) -> PortId {
+        PortId(index)
+    }
+
+    /// Create a gate with the given number of [`DEFAULT_PORT_WIDTH`]-bit inputs.
+    pub fn new(kind: GateKind, inputs: usize) -> Result<Gate, DeviceError> {
+        Gate::with_width(kind, inputs, DEFAULT_PORT_WIDTH)
+    }
+
+    /// Create a gate with the given number of inputs, all `width` bits wide.
+    ///
+    /// Fails if the width is invalid ([`DeviceError::InvalidWidth`]), or if there are no inputs,
+    /// or a NOT gate doesn't have exactly one ([`DeviceError::DeviceSpecific`]).
+    pub fn with_width(kind: GateKind, inputs: usize, width: u32) -> Result<Gate, DeviceError> {
+        check_width(width)?;
+        let valid = match kind {
+            GateKind::Not => inputs == 1,
+            _ => inputs >= 1,
+        };
+        if !valid {
+            return Err(DeviceError::DeviceSpecific {
+                message: format!("a {kind:?} gate cannot have {inputs} inputs"),
+            });
+        }
+
+        let mut ports: Vec<PortDescriptor> = (0..inputs)
+            .map(|index| PortDescriptor::input(&format!("in{index}"), width))
+            .collect();
+        ports.push(PortDescriptor::output("out", width));
+
+        Ok(Gate { kind, specified_this_tick: vec![None; inputs], ports })
+    }
+
+    /// Create a NOT gate, whose output is `width` bits wide.
+    pub fn not(width: u32) -> Result<Gate, DeviceError> {
+        Gate::with_width(GateKind::Not, 1, width)
+    }
+
+    /// Get which logic function this gate computes.
+    pub fn kind(&self) -> GateKind {
+        self.kind
+    }
+
+    /// Get the width of every port.
+    fn width(&self) -> u32 {
+        self.ports[0].width
+    }
+
+}
+
+impl Device for Gate {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        if output != self.output() {
+            return Err(output_port_error(&self.ports, output));
+        }
+        Ok((0..self.specified_this_tick.len()).map(PortId).collect())
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        if port != self.output() {
+            return Err(output_port_error(&self.ports, port));
+        }
+
+        // Treat inputs that haven't been provided as all X, and see if the output is known anyway
+        let width = self.width();
+        let unknown = PortValue::x(width);
+        let mut inputs = self.specified_this_tick.iter()
+            .map(|input| input.as_ref().unwrap_or(&unknown));
+        let first = inputs.next().expect("A gate should always have at least one input").clone();
+        let combined = inputs.fold(first, |combined, input| match self.kind {
+            GateKind::And | GateKind::Nand => &combined & input,
+            GateKind::Or | GateKind::Nor => &combined | input,
+            GateKind::Xor | GateKind::Xnor => &combined ^ input,
+            GateKind::Not => unreachable!("A NOT gate should only have one input"),
+        });
+        let value = match self.kind.is_inverted() {
+            true => combined.not(width),
+            false => combined.mask(width),
+        };
+
+        let missing = self.specified_this_tick.iter().any(Option::is_none);
+        match missing && !value.is_known() {
+            true => Ok(None),
+            false => Ok(Some(value)),
+        }
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick.iter_mut().for_each(|input| *input = None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::device::logic::{Gate, GateKind};
+    use crate::device::{Device, DeviceError, PortId, PortValue};
+
+    /// Parse a four-state value, for brevity.
+    fn v(value: &str) -> PortValue {
+        value.parse().unwrap()
+    }
+
+    /// Give a gate the given inputs, and read its output.
+    fn evaluate(kind: GateKind, inputs: &[&str]) -> Option<PortValue> {
+        let mut gate = Gate::with_width(kind, inputs.len(), 4).unwrap();
+        for (index, input) in inputs.iter().enumerate() {
+            gate.provide_port_value(Gate::input(index), v(input)).unwrap();
+        }
+        gate.get_port_value(gate.output()).unwrap()
+    }
+
+    #[test]
+    fn gates_compute_their_functions_bitwise() {
+        let inputs = ["1100", "1010"];
+        let cases = [
+            (GateKind::And, "1000"),
+            (GateKind::Or, "1110"),
+            (GateKind::Xor, "0110"),
+            (GateKind::Nand, "0111"),
+            (GateKind::Nor, "0001"),
+            (GateKind::Xnor, "1001"),
+        ];
+        for (kind, expected) in cases {
+            assert_eq!(evaluate(kind, &inputs), Some(v(expected)), "{kind:?}");
+        }
+        assert_eq!(evaluate(GateKind::Not, &["1100"]), Some(v("0011")));
+    }
+
+    #[test]
+    fn gates_take_any_number_of_inputs() {
+        let inputs = ["1111", "1110", "1100", "1000"];
+        assert_eq!(evaluate(GateKind::And, &inputs), Some(v("1000")));
+        assert_eq!(evaluate(GateKind::Xor, &inputs), Some(v("0101")));
+        assert_eq!(evaluate(GateKind::Nor, &["0001"]), Some(v("1110")));
+
+        let gate = Gate::new(GateKind::Or, 5).unwrap();
+        assert_eq!(gate.get_ports().len(), 6);
+        assert_eq!(gate.output(), PortId(5));
+        assert_eq!(gate.get_output_dependencies(gate.output()).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn gates_resolve_once_the_inputs_provided_decide_the_output() {
+        let mut gate = Gate::with_width(GateKind::And, 2, 4).unwrap();
+        gate.provide_port_value(Gate::input(0), v("0001")).unwrap();
+        assert_eq!(gate.get_port_value(gate.output()), Ok(None));
+
+        let mut gate = Gate::with_width(GateKind::Nand, 2, 4).unwrap();
+        gate.provide_port_value(Gate::input(1), v("0000")).unwrap();
+        assert_eq!(gate.get_port_value(gate.output()), Ok(Some(v("1111"))));
+    }
+
+    #[test]
+    fn gates_propagate_unknown_bits() {
+        assert_eq!(evaluate(GateKind::And, &["0x1x", "0011"]), Some(v("001x")));
+        assert_eq!(evaluate(GateKind::Or, &["z0x1", "1000"]), Some(v("10x1")));
+        assert_eq!(evaluate(GateKind::Not, &["01xz"]), Some(v("10xx")));
+    }
+
+    #[test]
+    fn gates_mask_inputs_to_their_width() {
+        assert_eq!(evaluate(GateKind::Not, &["1_0000"]), Some(v("1111")));
+    }
+
+    #[test]
+    fn gates_need_a_sensible_number_of_inputs() {
+        assert!(matches!(
+            Gate::new(GateKind::And, 0),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+        assert!(matches!(
+            Gate::new(GateKind::Not, 2),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+        assert!(matches!(
+            Gate::with_width(GateKind::Or, 2, 0),
+            Err(DeviceError::InvalidWidth { width: 0 })
+        ));
+        assert_eq!(Gate::not(8).unwrap().kind(), GateKind::Not);
+    }
+
+    #[test]
+    fn gates_reject_bad_ports() {
+        let mut gate = Gate::new(GateKind::Xor, 2).unwrap();
+        assert_eq!(
+            gate.provide_port_value(gate.output(), PortValue::ZERO),
+            Err(DeviceError::NotAnInputPort { port: "out".to_owned() }),
+        );
+        assert_eq!(
+            gate.get_port_value(Gate::input(1)),
+            Err(DeviceError::NotAnOutputPort { port: "in1".to_owned() }),
+        );
+        gate.provide_port_value(Gate::input(0), PortValue::ZERO).unwrap();
+        assert_eq!(
+            gate.provide_port_value(Gate::input(0), PortValue::ZERO),
+            Err(DeviceError::AlreadyProvided { port: "in0".to_owned() }),
+        );
+
+        // Ticking clears the inputs
+        gate.tick().unwrap();
+        gate.provide_port_value(Gate::input(0), PortValue::ZERO).unwrap();
+    }
+}
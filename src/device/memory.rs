@@ -1,32 +1,129 @@
 use std::collections::{HashMap, HashSet};
-use crate::device::{Device, DeviceError, PortIdentifier, PortValue};
+use std::ops::Range;
+use crate::device::dump::Dump;
+use crate::device::image::{Image, WordLayout};
+use crate::device::memory::storage::{Storage, StorageKind};
+use crate::device::{
+    check_width, output_port_error, provide_all_into, provide_into, Device, DeviceError,
+    PortDescriptor, PortId, PortValue, DEFAULT_PORT_WIDTH,
+};
+
+pub mod storage;
+
+/// Number of input ports on a [`Memory`]. These come first in its port list, so an input port's
+/// [`PortId`] is also its index in `specified_this_tick`.
+const INPUT_PORT_COUNT: usize = 4;
 
 pub struct Memory {
-    data: HashMap<u32, u32>,
-    specified_this_tick: HashMap<PortIdentifier, PortValue>,
-    in_ports: HashSet<PortIdentifier>,
-    out_ports: HashSet<PortIdentifier>,
+    data: Box<dyn Storage>,
+    specified_this_tick: [Option<PortValue>; INPUT_PORT_COUNT],
+    ports: Vec<PortDescriptor>,
+
+    /// Whether addresses that have never been written read as all X rather than 0.
+    four_state: bool,
 }
 
 impl Memory {
+    /// Read address
+    pub const RA: PortId = PortId(0);
+    /// Write enable
+    pub const WE: PortId = PortId(1);
+    /// Write address
+    pub const WA: PortId = PortId(2);
+    /// Write value
+    pub const WV: PortId = PortId(3);
+    /// Read value
+    pub const RV: PortId = PortId(4);
+
+    /// Create a memory with [`DEFAULT_PORT_WIDTH`]-bit addresses and data.
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Memory {
+        Memory::with_widths(DEFAULT_PORT_WIDTH, DEFAULT_PORT_WIDTH)
+            .expect("The default port width should always be a valid width")
+    }
+
+    /// Create a memory whose address ports (`ra` and `wa`) are `address_width` bits wide, and
+    /// whose data ports (`wv` and `rv`) are `data_width` bits wide. Write enable is always a single
+    /// bit.
+    pub fn with_widths(address_width: u32, data_width: u32) -> Result<Memory, DeviceError> {
+        Memory::with_storage(address_width, data_width, StorageKind::default())
+    }
+
+    /// Create a memory with the given widths (see [`Memory::with_widths`]), which keeps its words
+    /// in the given kind of [`Storage`] backend. The backend makes no difference to how the memory
+    /// behaves, only to how fast it is and how much space it takes.
+    ///
+    /// Fails if a width is invalid ([`DeviceError::InvalidWidth`]), or if the backend can't cover
+    /// addresses that wide ([`DeviceError::DeviceSpecific`]).
+    pub fn with_storage(address_width: u32, data_width: u32, storage: StorageKind)
+        -> Result<Memory, DeviceError>
+    {
+        check_width(address_width)?;
+        check_width(data_width)?;
+
         // Thought - make it a bit more restricted (and closer to real life) by having only one
         // address input, so you cannot read one address while writing another?
-        let mut in_ports: HashSet<PortIdentifier> = HashSet::new();
-        in_ports.insert("ra".to_owned());  // Read address
-        in_ports.insert("we".to_owned());  // Write enable
-        in_ports.insert("wa".to_owned());  // Write address
-        in_ports.insert("wv".to_owned());  // Write value
-
-        let mut out_ports: HashSet<PortIdentifier> = HashSet::new();
-        out_ports.insert("rv".to_owned());  // Read value
+        // The order of these must match the port handles above
+        let ports = vec![
+            PortDescriptor::input("ra", address_width),
+            PortDescriptor::input("we", 1),
+            PortDescriptor::input("wa", address_width),
+            PortDescriptor::input("wv", data_width),
+            PortDescriptor::output("rv", data_width),
+        ];
         
-        Memory {
-            data: HashMap::new(),
-            specified_this_tick: HashMap::new(),
-            in_ports,
-            out_ports,
+        Ok(Memory {
+            data: storage.create(address_width)?,
+            specified_this_tick: Default::default(),
+            ports,
+            four_state: false,
+        })
+    }
+
+    /// Set whether this memory uses four-state logic, in which case reading an address that has
+    /// never been written gives all [`LogicBit::X`](crate::device::value::LogicBit::X) rather than
+    /// 0, so that reads of uninitialised memory can be spotted.
+    ///
+    /// Regardless of this setting, reading from an address with unknown bits gives all X, and
+    /// writing with an unknown write enable stores all X, as the write may or may not happen.
+    pub fn set_four_state(&mut self, four_state: bool) {
+        self.four_state = four_state;
+    }
+
+    /// Store the contents of an image, grouping its bytes into words as given by `layout`. Word
+    /// `n` of the image is stored at address `n`, replacing anything already there.
+    ///
+    /// Fails, without storing anything, if a word's address or value is too wide for this memory
+    /// ([`DeviceError::DeviceSpecific`]).
+    pub fn load(&mut self, image: &Image, layout: WordLayout) -> Result<(), DeviceError> {
+        let address_width = self.ports[Self::RA.0].width;
+        let words = image.words_to_load(layout, address_width, self.data_width())?;
+        for (address, value) in words {
+            self.data.insert(address, value);
         }
+        Ok(())
+    }
+
+    /// Take a snapshot of the words stored at the given addresses. Only addresses that have been
+    /// written or loaded are included.
+    pub fn dump(&self, addresses: Range<u64>) -> Dump {
+        let mut dump = Dump::new(self.data_width());
+        for (address, value) in self.data.words() {
+            if let Some(address) = address.to_u64().filter(|address| addresses.contains(address)) {
+                dump.insert(address, value.clone());
+            }
+        }
+        dump
+    }
+
+    /// Get the width of the data ports.
+    fn data_width(&self) -> u32 {
+        self.ports[Self::RV.0].width
+    }
+
+    /// Get the value provided to the given input port this tick, if there is one.
+    fn provided(&self, port: PortId) -> Option<&PortValue> {
+        self.specified_this_tick[port.0].as_ref()
     }
 }
 
@@ -35,89 +132,95 @@ impl Device for Memory {
     // know the best way of commonising them.
     // I just want to get something working, however, so I'm leaving that particular problem until
     // later.
-    fn get_input_ports(&self) -> HashSet<PortIdentifier> {
-        self.in_ports.to_owned()
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
     }
 
-    fn get_output_ports(&self) -> HashSet<PortIdentifier> {
-        self.out_ports.to_owned()
-    }
-
-    fn get_output_dependencies(&self, output: &PortIdentifier) -> Result<HashSet<PortIdentifier>, DeviceError> {
-        if output.as_str() != "rv" {
-            return Err(DeviceError);
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        if output != Self::RV {
+            return Err(output_port_error(&self.ports, output));
         }
-        Ok(HashSet::from_iter(vec!["ra".to_owned()]))
+        Ok(HashSet::from([Self::RA]))
     }
 
-    fn provide_port_value(&mut self, port: PortIdentifier, value: PortValue)
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
         -> Result<(), DeviceError>
     {
-        let mut values = HashMap::new();
-        values.insert(port, value);
-        self.provide_port_values(values)
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
     }
 
-    fn provide_port_values(&mut self, values: HashMap<PortIdentifier, PortValue>)
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
         -> Result<(), DeviceError> {
-        // Check for problems
-        for port in values.keys() {
-            if self.specified_this_tick.contains_key(port)
-            || !self.in_ports.contains(port) {
-                return Err(DeviceError)
-            }
+        // The read value is worked out lazily in `get_port_value()`, so all we need to do here is
+        // remember what we were given
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        if port != Self::RV {
+            return Err(output_port_error(&self.ports, port));
         }
         
-        // Do stuff
-        let mut result: HashMap<PortIdentifier, PortValue> = HashMap::new();
-        for port in values.keys() {
-            self.specified_this_tick.insert(port.clone(), *values.get(port).unwrap());
-            match port.as_str() {
-                "ra" => {
-                    result.insert(
-                        "rv".to_owned(),
-                        *self.data
-                            .get(values.get(port).unwrap())
-                            .unwrap_or(&0u32)
-                    );
-                }
-                // None of the other ports affect the output value
-                _ => {}
-            }
+        let Some(address) = self.provided(Self::RA) else {
+            return Ok(None);
+        };
+        if !address.is_known() {
+            return Ok(Some(PortValue::x(self.data_width())));
         }
-        Ok(())
+        let value = match self.data.get(address) {
+            Some(value) => value.clone(),
+            None if self.four_state => PortValue::x(self.data_width()),
+            None => PortValue::ZERO,
+        };
+        Ok(Some(value))
     }
 
-    fn get_port_value(&self, port: &PortIdentifier) -> Result<Option<PortValue>, DeviceError> {
-        if port.as_str() != "rv" {
-            return Err(DeviceError);
-        }
-        
-        Ok(match self.specified_this_tick.get("ra") {
-            None => None,
-            Some(addr) => Some(*self.data.get(addr).unwrap_or(&0u32)),
-        })
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick = Default::default();
     }
 
-    fn tick(&mut self) -> Result<(), DeviceError> {
-        if !self.specified_this_tick.contains_key("we") {
+    fn check_tick(&self) -> Result<(), DeviceError> {
+        let Some(write_enable) = self.provided(Self::WE) else {
             // Need to know if we are writing to memory this tick         
-            return Err(DeviceError);
-        }
-        if *self.specified_this_tick.get("we").unwrap() != 0 {
+            return Err(DeviceError::MissingInput {
+                port: self.port_name(Self::WE),
+                message: "write enable must be provided every tick".to_owned(),
+            });
+        };
+        if !write_enable.is_zero() {
             // We are writing, so we need to know the address and value
-            if !self.specified_this_tick.contains_key("wa")
-                || !self.specified_this_tick.contains_key("wv") {      
-                return Err(DeviceError);
+            for port in [Self::WA, Self::WV] {
+                if self.provided(port).is_none() {
+                    return Err(DeviceError::MissingInput {
+                        port: self.port_name(port),
+                        message: "write address and value must be provided when write enable \
+                            is set".to_owned(),
+                    });
+                }
+            }
+            if !self.provided(Self::WA).unwrap().is_known() {
+                return Err(DeviceError::DeviceSpecific {
+                    message: "cannot write to an address with unknown bits".to_owned(),
+                });
             }
-            self.data.insert(
-                *self.specified_this_tick.get("wa").unwrap(),
-                *self.specified_this_tick.get("wv").unwrap()
-            );
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) -> Result<(), DeviceError> {
+        self.check_tick()?;
+        let write_enable = self.provided(Self::WE).unwrap();
+        if !write_enable.is_zero() {
+            // If we don't know whether we're writing, we don't know what's there afterwards
+            let value = match write_enable.is_known() {
+                true => self.provided(Self::WV).unwrap().clone(),
+                false => PortValue::x(self.data_width()),
+            };
+            self.data.insert(self.provided(Self::WA).unwrap().clone(), value);
         }
         
         // Clear provided values
-        self.specified_this_tick = HashMap::new();
+        self.clear_port_values();
         
         Ok(())
     }
@@ -126,8 +229,11 @@ impl Device for Memory {
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
-    use crate::device::{Device, PortIdentifier, PortValue};
+    use crate::device::{Device, DeviceError, PortId, PortValue};
+    use crate::device::dump::Dump;
+    use crate::device::image::{Endianness, Image, WordLayout};
     use crate::device::memory::Memory;
+    use crate::device::memory::storage::StorageKind;
 
     #[test]
     fn memory_can_be_instantiated() {
@@ -137,26 +243,26 @@ mod tests {
     #[test]
     fn memory_cannot_have_unknown_ports_specified() {
         let mut memory = Memory::new();
-        let mut ports: HashMap<PortIdentifier, PortValue> = HashMap::new();
-        ports.insert("qq".to_owned(), 0);
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(PortId(7), PortValue::from(0u32));
         let result = memory.provide_port_values(ports);
-        assert!(result.is_err());
+        assert_eq!(result, Err(DeviceError::UnknownPort { port: "#7".to_owned() }));
     }
 
     #[test]
     fn memory_cannot_have_output_ports_specified() {
         let mut memory = Memory::new();
-        let mut ports: HashMap<PortIdentifier, PortValue> = HashMap::new();
-        ports.insert("rv".to_owned(), 0);
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::RV, PortValue::from(0u32));
         let result = memory.provide_port_values(ports);
-        assert!(result.is_err());
+        assert_eq!(result, Err(DeviceError::NotAnInputPort { port: "rv".to_owned() }));
     }
 
     #[test]
     fn memory_cannot_have_ports_specified_twice() {
         let mut memory = Memory::new();
-        let mut ports: HashMap<PortIdentifier, PortValue> = HashMap::new();
-        ports.insert("ra".to_owned(), 0);
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::RA, PortValue::from(0u32));
         
         // First time
         let result = memory.provide_port_values(ports.to_owned());
@@ -164,22 +270,22 @@ mod tests {
 
         // Second time
         let result = memory.provide_port_values(ports);
-        assert!(result.is_err());
+        assert_eq!(result, Err(DeviceError::AlreadyProvided { port: "ra".to_owned() }));
     }
     
     #[test]
     fn memory_does_not_resolve_if_write_enable_not_given() {
         let mut memory = Memory::new();
         let result = memory.tick();
-        assert!(result.is_err());
+        assert!(matches!(result, Err(DeviceError::MissingInput { port, .. }) if port == "we"));
     }
     
     #[test]
     fn memory_resolves_if_write_enable_is_zero_and_other_write_ports_not_given() {
         let mut memory = Memory::new();
-        let mut ports: HashMap<PortIdentifier, PortValue> = HashMap::new();
-        ports.insert("we".to_owned(), 0);
-        let _ = memory.provide_port_values(ports).unwrap();
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::WE, PortValue::from(0u32));
+        memory.provide_port_values(ports).unwrap();
         let result = memory.tick();
         assert!(result.is_ok());
     }
@@ -187,24 +293,31 @@ mod tests {
     #[test]
     fn memory_does_not_resolve_if_write_enable_is_nonzero_and_other_write_ports_not_given() {
         let mut memory = Memory::new();
-        let mut ports: HashMap<PortIdentifier, PortValue> = HashMap::new();
-        ports.insert("we".to_owned(), 1);
-        let _ = memory.provide_port_values(ports).unwrap();
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::WE, PortValue::from(1u32));
+        memory.provide_port_values(ports).unwrap();
         let result = memory.tick();
-        assert!(result.is_err());
+        assert!(matches!(result, Err(DeviceError::MissingInput { .. })));
     }
     
+    #[test]
+    fn memory_cannot_read_value_of_input_port() {
+        let memory = Memory::new();
+        let result = memory.get_port_value(Memory::RA);
+        assert_eq!(result, Err(DeviceError::NotAnOutputPort { port: "ra".to_owned() }));
+    }
+
     #[test]
     fn memory_can_be_written_to() {
-        let address: PortValue = 2;
-        let value: PortValue = 3;
+        let address = PortValue::from(2u32);
+        let value = PortValue::from(3u32);
         let mut memory = Memory::new();
-        let mut ports: HashMap<PortIdentifier, PortValue> = HashMap::new();
-        ports.insert("we".to_owned(), 1);
-        ports.insert("wa".to_owned(), address);
-        ports.insert("wv".to_owned(), value);
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::WE, PortValue::from(1u32));
+        ports.insert(Memory::WA, address.clone());
+        ports.insert(Memory::WV, value.clone());
         
-        let _ = memory.provide_port_values(ports).unwrap();
+        memory.provide_port_values(ports).unwrap();
         let result = memory.tick();
         assert!(result.is_ok());
         assert_eq!(memory.data.get(&address).unwrap(), &value);
@@ -212,15 +325,15 @@ mod tests {
 
     #[test]
     fn memory_can_be_read() {
-        let address: PortValue = 2;
-        let value: PortValue = 3;
+        let address = PortValue::from(2u32);
+        let value = PortValue::from(3u32);
         let mut memory = Memory::new();
-        memory.data.insert(address, value);
-        let mut ports: HashMap<PortIdentifier, PortValue> = HashMap::new();
-        ports.insert("ra".to_owned(), address);
+        memory.data.insert(address.clone(), value.clone());
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::RA, address.clone());
     
         memory.provide_port_values(ports).unwrap();
-        let result = memory.get_port_value(&"rv".to_owned());
+        let result = memory.get_port_value(Memory::RV);
         assert!(result.is_ok());
         let result = result.unwrap();
         assert!(result.is_some());
@@ -229,18 +342,203 @@ mod tests {
     
     #[test]
     fn can_provide_port_values_again_after_tick() {
-        let address: PortValue = 2;
-        let value: PortValue = 3;
+        let address = PortValue::from(2u32);
+        let value = PortValue::from(3u32);
         let mut memory = Memory::new();
-        let mut ports: HashMap<PortIdentifier, PortValue> = HashMap::new();
-        ports.insert("we".to_owned(), 1);
-        ports.insert("wa".to_owned(), address);
-        ports.insert("wv".to_owned(), value);
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::WE, PortValue::from(1u32));
+        ports.insert(Memory::WA, address.clone());
+        ports.insert(Memory::WV, value.clone());
 
-        _ = memory.provide_port_values(ports.clone()).unwrap();
+        memory.provide_port_values(ports.clone()).unwrap();
         _ = memory.tick();
         
         let result = memory.provide_port_values(ports);
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn memory_can_have_port_values_provided_again_after_clearing() {
+        let mut memory = Memory::new();
+        memory.provide_port_value(Memory::RA, PortValue::from(1u32)).unwrap();
+
+        memory.clear_port_values();
+
+        assert_eq!(memory.get_port_value(Memory::RV), Ok(None));
+        assert!(memory.provide_port_value(Memory::RA, PortValue::from(1u32)).is_ok());
+    }
+
+    #[test]
+    fn memory_check_tick_fails_when_tick_would_fail_and_changes_nothing() {
+        let address = PortValue::from(2u32);
+        let mut memory = Memory::new();
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::WE, PortValue::from(1u32));
+        ports.insert(Memory::WA, address.clone());
+        memory.provide_port_values(ports).unwrap();
+        assert!(matches!(
+            memory.check_tick(),
+            Err(DeviceError::MissingInput { port, .. }) if port == "wv"
+        ));
+
+        memory.provide_port_value(Memory::WV, PortValue::from(3u32)).unwrap();
+        assert!(memory.check_tick().is_ok());
+        assert_eq!(memory.data.get(&address), None);
+    }
+
+    #[test]
+    fn memory_cannot_be_instantiated_with_invalid_widths() {
+        assert!(matches!(Memory::with_widths(0, 8), Err(DeviceError::InvalidWidth { width: 0 })));
+        assert!(matches!(Memory::with_widths(8, 0), Err(DeviceError::InvalidWidth { width: 0 })));
+    }
+
+    #[test]
+    fn memory_masks_values_to_port_widths() {
+        let mut memory = Memory::with_widths(4, 8).unwrap();
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::WE, PortValue::from(1u32));
+        ports.insert(Memory::WA, PortValue::from(0x12u32));
+        ports.insert(Memory::WV, PortValue::from(0x1ffu32));
+        memory.provide_port_values(ports).unwrap();
+        memory.tick().unwrap();
+
+        // Only the lowest 4 bits of the address and 8 bits of the value were kept
+        memory.provide_port_value(Memory::RA, PortValue::from(0x2u32)).unwrap();
+        assert_eq!(memory.get_port_value(Memory::RV), Ok(Some(PortValue::from(0xffu32))));
+    }
+
+    #[test]
+    fn memory_can_store_values_wider_than_64_bits() {
+        let address = PortValue::from(u128::MAX);
+        let value = PortValue::from_limbs(&[1, 2, 3]);
+        let mut memory = Memory::with_widths(128, 192).unwrap();
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::WE, PortValue::from(1u32));
+        ports.insert(Memory::WA, address.clone());
+        ports.insert(Memory::WV, value.clone());
+        memory.provide_port_values(ports).unwrap();
+        memory.tick().unwrap();
+
+        memory.provide_port_value(Memory::RA, address).unwrap();
+        assert_eq!(memory.get_port_value(Memory::RV), Ok(Some(value)));
+    }
+
+    #[test]
+    fn four_state_memory_reads_uninitialised_addresses_as_x() {
+        let mut memory = Memory::with_widths(8, 4).unwrap();
+        memory.set_four_state(true);
+        memory.provide_port_value(Memory::RA, PortValue::from(1u32)).unwrap();
+        assert_eq!(memory.get_port_value(Memory::RV), Ok(Some(PortValue::x(4))));
+    }
+
+    #[test]
+    fn memory_reads_unknown_addresses_as_x() {
+        let mut memory = Memory::with_widths(8, 4).unwrap();
+        memory.provide_port_value(Memory::RA, PortValue::z(8)).unwrap();
+        assert_eq!(memory.get_port_value(Memory::RV), Ok(Some(PortValue::x(4))));
+    }
+
+    #[test]
+    fn memory_stores_x_when_write_enable_is_unknown() {
+        let address = PortValue::from(2u32);
+        let mut memory = Memory::with_widths(8, 4).unwrap();
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::WE, PortValue::x(1));
+        ports.insert(Memory::WA, address.clone());
+        ports.insert(Memory::WV, PortValue::from(3u32));
+        memory.provide_port_values(ports).unwrap();
+        memory.tick().unwrap();
+        assert_eq!(memory.data.get(&address), Some(&PortValue::x(4)));
+    }
+
+    #[test]
+    fn memory_can_be_loaded_from_an_image() {
+        let image = Image::from_binary(&[0x34, 0x12, 0x78, 0x56], 2);
+        let mut memory = Memory::with_widths(8, 16).unwrap();
+        memory.load(&image, WordLayout::new(2, Endianness::Big)).unwrap();
+        assert_eq!(memory.data.get(&PortValue::from(1u32)), Some(&PortValue::from(0x3412u32)));
+        assert_eq!(memory.data.get(&PortValue::from(2u32)), Some(&PortValue::from(0x7856u32)));
+
+        // Nothing is stored if any of the image doesn't fit
+        let mut memory = Memory::with_widths(1, 16).unwrap();
+        let result = memory.load(&image, WordLayout::new(2, Endianness::Little));
+        assert!(matches!(result, Err(DeviceError::DeviceSpecific { .. })));
+        assert!(memory.data.is_empty());
+        let mut memory = Memory::with_widths(8, 12).unwrap();
+        let result = memory.load(&image, WordLayout::new(2, Endianness::Little));
+        assert!(matches!(result, Err(DeviceError::DeviceSpecific { .. })));
+    }
+
+    #[test]
+    fn memory_region_can_be_dumped_and_checked_against_a_golden_file() {
+        let mut memory = Memory::with_widths(8, 16).unwrap();
+        for (address, value) in [(0x10u32, 0x1234u32), (0x11, 0x5678), (0x40, 0xffff)] {
+            let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+            ports.insert(Memory::WE, PortValue::from(1u32));
+            ports.insert(Memory::WA, PortValue::from(address));
+            ports.insert(Memory::WV, PortValue::from(value));
+            memory.provide_port_values(ports).unwrap();
+            memory.tick().unwrap();
+        }
+
+        let dump = memory.dump(0x10..0x20);
+        assert_eq!(dump.to_hex(), "00000010: 1234 5678\n");
+
+        // The golden file expects the second word to be different
+        let layout = WordLayout::new(2, Endianness::Little);
+        let golden = Image::from_intel_hex(":0400200034127956C7\n:00000001FF\n").unwrap();
+        let diff = Dump::from_image(&golden, layout).unwrap().diff(&dump);
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].to_string(), "0x11: expected 0x5679, got 0x5678");
+    }
+
+    #[test]
+    fn memory_behaves_the_same_with_every_storage_backend() {
+        let kinds = [StorageKind::Sparse, StorageKind::Dense, StorageKind::Paged];
+        let reads: Vec<Vec<Option<PortValue>>> = kinds.into_iter().map(|kind| {
+            let mut memory = Memory::with_storage(16, 8, kind).unwrap();
+            memory.set_four_state(true);
+            let writes = [(0x0001u32, "1"), (0xffff, "1"), (0x1234, "x"), (0x0001, "1")];
+            for (index, (address, we)) in writes.into_iter().enumerate() {
+                let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+                ports.insert(Memory::WE, we.parse().unwrap());
+                ports.insert(Memory::WA, PortValue::from(address));
+                ports.insert(Memory::WV, PortValue::from(index as u32 + 0x10));
+                memory.provide_port_values(ports).unwrap();
+                memory.tick().unwrap();
+            }
+
+            [PortValue::from(1u32), PortValue::from(0xffffu32), PortValue::from(0x1234u32),
+                PortValue::from(2u32), "1x".parse().unwrap()].into_iter()
+                .map(|address| {
+                    memory.provide_port_value(Memory::RA, address).unwrap();
+                    let value = memory.get_port_value(Memory::RV).unwrap();
+                    memory.clear_port_values();
+                    value
+                })
+                .collect()
+        }).collect();
+
+        assert_eq!(reads[0], [
+            Some(PortValue::from(0x13u32)), Some(PortValue::from(0x11u32)), Some(PortValue::x(8)),
+            Some(PortValue::x(8)), Some(PortValue::x(8)),
+        ]);
+        assert_eq!(reads[1], reads[0]);
+        assert_eq!(reads[2], reads[0]);
+        assert!(matches!(
+            Memory::with_storage(32, 8, StorageKind::Dense),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+    }
+
+    #[test]
+    fn memory_cannot_write_to_unknown_address() {
+        let mut memory = Memory::with_widths(8, 4).unwrap();
+        let mut ports: HashMap<PortId, PortValue> = HashMap::new();
+        ports.insert(Memory::WE, PortValue::from(1u32));
+        ports.insert(Memory::WA, PortValue::z(8));
+        ports.insert(Memory::WV, PortValue::from(3u32));
+        memory.provide_port_values(ports).unwrap();
+        assert!(matches!(memory.tick(), Err(DeviceError::DeviceSpecific { .. })));
+    }
 }
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use crate::device::{DeviceError, PortValue};
+
+/// The widest addresses a [`DenseStorage`] can cover. Every address is allocated up front, so
+/// this keeps a dense memory to a few million words.
+pub const MAX_DENSE_ADDRESS_WIDTH: u32 = 24;
+
+/// Number of address bits picking a word within a page of a [`PagedStorage`].
+const PAGE_BITS: u32 = 12;
+
+/// Holds the words stored in a [`Memory`](super::Memory).
+///
+/// Addresses given to a backend are always known, and already masked to the memory's address
+/// width. Every backend behaves the same from the outside; they differ only in speed and in how
+/// much space they take.
+pub trait Storage {
+    /// Get the word stored at the given address, or `None` if nothing has been stored there.
+    fn get(&self, address: &PortValue) -> Option<&PortValue>;
+
+    /// Store a word at the given address, replacing anything already there.
+    fn insert(&mut self, address: PortValue, value: PortValue);
+
+    /// Get every address that has had something stored at it, with the word stored there, in no
+    /// particular order.
+    fn words(&self) -> Box<dyn Iterator<Item = (PortValue, &PortValue)> + '_>;
+
+    /// Whether nothing has been stored.
+    fn is_empty(&self) -> bool {
+        self.words().next().is_none()
+    }
+}
+
+/// Which [`Storage`] backend a [`Memory`](super::Memory) uses, chosen when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum StorageKind {
+    /// A hash map of only the addresses written: small, but every access is hashed.
+    #[default]
+    Sparse,
+    /// An array of every address: fastest, but only for addresses of up to
+    /// [`MAX_DENSE_ADDRESS_WIDTH`] bits.
+    Dense,
+    /// Arrays of 4096 words, allocated as they are first written: nearly as fast as dense for
+    /// programs that keep to a few regions of a large address space.
+    Paged,
+}
+
+impl StorageKind {
+    /// Create an empty backend of this kind, for addresses `address_width` bits wide.
+    ///
+    /// Fails if a dense backend is asked for with addresses wider than
+    /// [`MAX_DENSE_ADDRESS_WIDTH`] ([`DeviceError::DeviceSpecific`]).
+    pub fn create(self, address_width: u32) -> Result<Box<dyn Storage>, DeviceError> {
+        Ok(match self {
+            StorageKind::Sparse => Box::new(SparseStorage::default()),
+            StorageKind::Dense => Box::new(DenseStorage::new(address_width)?),
+            StorageKind::Paged => Box::new(PagedStorage::default()),
+        })
+    }
+}
+
+/// A [`Storage`] backend keeping only the words written, in a hash map.
+#[derive(Debug, Clone, Default)]
+pub struct SparseStorage {
+    words: HashMap<PortValue, PortValue>,
+}
+
+impl Storage for SparseStorage {
+    fn get(&self, address: &PortValue) -> Option<&PortValue> {
+        self.words.get(address)
+    }
+
+    fn insert(&mut self, address: PortValue, value: PortValue) {
+        self.words.insert(address, value);
+    }
+
+    fn words(&self) -> Box<dyn Iterator<Item = (PortValue, &PortValue)> + '_> {
+        Box::new(self.words.iter().map(|(address, value)| (address.clone(), value)))
+    }
+}
+
+/// A [`Storage`] backend with a slot for every address, indexed directly.
+#[derive(Debug, Clone)]
+pub struct DenseStorage {
+    words: Vec<Option<PortValue>>,
+}
+
+impl DenseStorage {
+    /// Create a backend covering every `address_width`-bit address.
+    ///
+    /// Fails if the addresses are wider than [`MAX_DENSE_ADDRESS_WIDTH`]
+    /// ([`DeviceError::DeviceSpecific`]).
+    pub fn new(address_width: u32) -> Result<DenseStorage, DeviceError> {
+        if address_width > MAX_DENSE_ADDRESS_WIDTH {
+            return Err(DeviceError::DeviceSpecific {
+                message: format!("dense storage cannot cover {address_width}-bit addresses: at \
+                    most {MAX_DENSE_ADDRESS_WIDTH} bits are allowed"),
+            });
+        }
+        Ok(DenseStorage { words: vec![None; 1 << address_width] })
+    }
+}
+
+impl Storage for DenseStorage {
+    fn get(&self, address: &PortValue) -> Option<&PortValue> {
+        self.words[address.low_u64() as usize].as_ref()
+    }
+
+    fn insert(&mut self, address: PortValue, value: PortValue) {
+        self.words[address.low_u64() as usize] = Some(value);
+    }
+
+    fn words(&self) -> Box<dyn Iterator<Item = (PortValue, &PortValue)> + '_> {
+        Box::new(self.words.iter().enumerate().filter_map(|(address, value)| {
+            Some((PortValue::from(address as u64), value.as_ref()?))
+        }))
+    }
+}
+
+/// A [`Storage`] backend splitting addresses into pages of 4096 words, each allocated when it is
+/// first written.
+#[derive(Debug, Clone, Default)]
+pub struct PagedStorage {
+    /// The pages written so far, keyed by address with the offset within the page shifted off.
+    pages: HashMap<PortValue, Box<[Option<PortValue>]>>,
+}
+
+impl PagedStorage {
+    /// Split an address into the key of its page, and its offset within the page.
+    fn locate(address: &PortValue) -> (PortValue, usize) {
+        (address >> PAGE_BITS, address.slice(0, PAGE_BITS).low_u64() as usize)
+    }
+}
+
+impl Storage for PagedStorage {
+    fn get(&self, address: &PortValue) -> Option<&PortValue> {
+        let (page, offset) = PagedStorage::locate(address);
+        self.pages.get(&page)?[offset].as_ref()
+    }
+
+    fn insert(&mut self, address: PortValue, value: PortValue) {
+        let (page, offset) = PagedStorage::locate(&address);
+        let page = self.pages.entry(page)
+            .or_insert_with(|| vec![None; 1 << PAGE_BITS].into_boxed_slice());
+        page[offset] = Some(value);
+    }
+
+    fn words(&self) -> Box<dyn Iterator<Item = (PortValue, &PortValue)> + '_> {
+        Box::new(self.pages.iter().flat_map(|(page, words)| {
+            words.iter().enumerate().filter_map(move |(offset, value)| {
+                let address = &(page << PAGE_BITS) | &PortValue::from(offset as u64);
+                Some((address, value.as_ref()?))
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::device::memory::storage::{StorageKind, MAX_DENSE_ADDRESS_WIDTH};
+    use crate::device::{DeviceError, PortValue};
+
+    const KINDS: [StorageKind; 3] = [StorageKind::Sparse, StorageKind::Dense, StorageKind::Paged];
+
+    #[test]
+    fn every_backend_stores_and_lists_words() {
+        for kind in KINDS {
+            let mut storage = kind.create(16).unwrap();
+            assert!(storage.is_empty(), "{kind:?}");
+            for address in [0u32, 0xfff, 0x1000, 0xffff] {
+                storage.insert(PortValue::from(address), PortValue::from(address + 1));
+            }
+            storage.insert(PortValue::from(0x1000u32), PortValue::x(4));
+
+            assert_eq!(storage.get(&PortValue::from(0xfffu32)), Some(&PortValue::from(0x1000u32)));
+            assert_eq!(storage.get(&PortValue::from(0x1000u32)), Some(&PortValue::x(4)));
+            assert_eq!(storage.get(&PortValue::from(1u32)), None, "{kind:?}");
+            let mut words: Vec<(u64, PortValue)> = storage.words()
+                .map(|(address, value)| (address.to_u64().unwrap(), value.clone()))
+                .collect();
+            words.sort();
+            assert_eq!(words, [
+                (0, PortValue::from(1u32)),
+                (0xfff, PortValue::from(0x1000u32)),
+                (0x1000, PortValue::x(4)),
+                (0xffff, PortValue::from(0x1_0000u32)),
+            ], "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn paged_backend_handles_addresses_wider_than_64_bits() {
+        let mut storage = StorageKind::Paged.create(128).unwrap();
+        let address = PortValue::from(u128::MAX);
+        storage.insert(address.clone(), PortValue::from(7u32));
+        assert_eq!(storage.get(&address), Some(&PortValue::from(7u32)));
+        assert_eq!(storage.words().next(), Some((address, &PortValue::from(7u32))));
+    }
+
+    #[test]
+    fn dense_backend_is_bounded() {
+        assert!(matches!(
+            StorageKind::Dense.create(MAX_DENSE_ADDRESS_WIDTH + 1),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+    }
+}
@@ -0,0 +1,755 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use crate::device::dump::Dump;
+use crate::device::image::{Image, WordLayout};
+use crate::device::memory::storage::{Storage, StorageKind};
+use crate::device::register_file::ReadDuringWrite;
+use crate::device::value::LogicBit;
+use crate::device::{
+    check_width, output_port_error, provide_all_into, provide_into, Device, DeviceError,
+    PortDescriptor, PortId, PortValue, DEFAULT_PORT_WIDTH,
+};
+
+/// Number of input ports for each write port of a [`MultiPortMemory`]: enable, address and value.
+const WRITE_PORT_INPUTS: usize = 3;
+
+/// A memory with any number of read and write ports, such as an SRAM block or the data array of
+/// a cache.
+///
+/// Each read port has an address input `ra0`, `ra1` and so on, and a value output `rv0`, `rv1` and
+/// so on. Each write port has an enable `we0`, address `wa0` and value `wv0`, and so on. Reading
+/// an address that has never been written gives 0, or all X in four-state mode; reading an
+/// address with unknown bits gives all X.
+///
+/// On each tick, every enabled write port stores its value at the address it gives. If more than
+/// one write port writes the same address, the highest numbered one wins. Every write enable must
+/// be provided every tick, and the address and value of a write port whenever it is enabled.
+/// Whether reads see this tick's writes is set by [`MultiPortMemory::set_read_during_write`].
+///
+/// By default reads are asynchronous, so `rv0` gives the word at `ra0` in the same tick. With
+/// [`MultiPortMemory::set_synchronous_read`], the word read is instead latched on the tick and
+/// only appears on `rv0` the tick after, as in most real SRAM blocks.
+///
+/// A memory made with [`MultiPortMemory::dual_clock`] has its read and write ports in separate
+/// clock domains, each with its own clock enable input: `rce` for the read ports and `wce` for
+/// the write ports. Each side is only clocked on the ticks where its clock enable is set, so two
+/// clocks of different rates can be modelled by setting the enables on the ticks where each
+/// clock has an edge. Otherwise, every port is clocked on every tick.
+pub struct MultiPortMemory {
+    data: Box<dyn Storage>,
+    specified_this_tick: Vec<Option<PortValue>>,
+    ports: Vec<PortDescriptor>,
+    read_ports: usize,
+    write_ports: usize,
+    /// Whether there are `rce` and `wce` clock enable inputs, for separate read and write clocks.
+    clock_enables: bool,
+
+    read_during_write: ReadDuringWrite,
+    synchronous_read: bool,
+
+    /// The word each read port read on the last tick, or `None` if it hasn't read anything yet.
+    /// Only used when reads are synchronous.
+    latched: Vec<Option<PortValue>>,
+
+    /// Whether addresses that have never been written read as all X rather than 0.
+    four_state: bool,
+}
+
+impl MultiPortMemory {
+    /// Create a memory with the given number of read and write ports, and
+    /// [`DEFAULT_PORT_WIDTH`]-bit addresses and data.
+    pub fn new(read_ports: usize, write_ports: usize) -> Result<MultiPortMemory, DeviceError> {
+        MultiPortMemory::with_widths(read_ports, write_ports, DEFAULT_PORT_WIDTH,
+            DEFAULT_PORT_WIDTH)
+    }
+
+    /// Create a memory with the given number of read and write ports, whose address ports are
+    /// `address_width` bits wide and whose data ports are `data_width` bits wide. Write enables
+    /// are always a single bit.
+    pub fn with_widths(read_ports: usize, write_ports: usize, address_width: u32, data_width: u32)
+        -> Result<MultiPortMemory, DeviceError>
+    {
+        MultiPortMemory::with_storage(read_ports, write_ports, address_width, data_width,
+            StorageKind::default())
+    }
+
+    /// Create a memory with the given ports and widths (see [`MultiPortMemory::with_widths`]),
+    /// whose read and write ports are in separate clock domains, with the clock enables `rce` and
+    /// `wce`. The write clock enable must be provided every tick. The read clock enable only
+    /// matters when reads are synchronous, as asynchronous reads aren't clocked.
+    pub fn dual_clock(read_ports: usize, write_ports: usize, address_width: u32,
+        data_width: u32) -> Result<MultiPortMemory, DeviceError>
+    {
+        let mut memory = MultiPortMemory::with_storage(read_ports, write_ports, address_width,
+            data_width, StorageKind::default())?;
+        // The clock enables go after the other inputs, and before the read values
+        let inputs = memory.specified_this_tick.len();
+        memory.ports.splice(inputs..inputs,
+            [PortDescriptor::input("rce", 1), PortDescriptor::input("wce", 1)]);
+        memory.specified_this_tick.extend([None, None]);
+        memory.clock_enables = true;
+        Ok(memory)
+    }
+
+    /// Create a memory with the given ports and widths (see [`MultiPortMemory::with_widths`]),
+    /// which keeps its words in the given kind of [`Storage`] backend.
+    ///
+    /// Fails if a width is invalid ([`DeviceError::InvalidWidth`]), if there are no read ports or
+    /// no write ports, or if the backend can't cover addresses that wide
+    /// ([`DeviceError::DeviceSpecific`]).
+    pub fn with_storage(read_ports: usize, write_ports: usize, address_width: u32,
+        data_width: u32, storage: StorageKind) -> Result<MultiPortMemory, DeviceError>
+    {
+        check_width(address_width)?;
+        check_width(data_width)?;
+        if read_ports == 0 || write_ports == 0 {
+            return Err(DeviceError::DeviceSpecific {
+                message: "a multi-port memory must have at least one read port and one write port"
+                    .to_owned(),
+            });
+        }
+
+        // The order of these must match the port handles below
+        let mut ports: Vec<PortDescriptor> = (0..read_ports)
+            .map(|index| PortDescriptor::input(&format!("ra{index}"), address_width))
+            .collect();
+        for index in 0..write_ports {
+            ports.push(PortDescriptor::input(&format!("we{index}"), 1));
+            ports.push(PortDescriptor::input(&format!("wa{index}"), address_width));
+            ports.push(PortDescriptor::input(&format!("wv{index}"), data_width));
+        }
+        let inputs = ports.len();
+        ports.extend((0..read_ports)
+            .map(|index| PortDescriptor::output(&format!("rv{index}"), data_width)));
+
+        Ok(MultiPortMemory {
+            data: storage.create(address_width)?,
+            specified_this_tick: vec![None; inputs],
+            ports,
+            read_ports,
+            write_ports,
+            clock_enables: false,
+            read_during_write: ReadDuringWrite::default(),
+            synchronous_read: false,
+            latched: vec![None; read_ports],
+            four_state: false,
+        })
+    }
+
+    /// The handle of the `index`th read port's address input.
+    pub fn read_address(&self, index: usize) -> PortId {
+        PortId(index)
+    }
+
+    /// The handle of the `index`th read port's value output.
+    pub fn read_value(&self, index: usize) -> PortId {
+        PortId(self.specified_this_tick.len() + index)
+    }
+
+    /// The handle of the `index`th write port's enable input.
+    pub fn write_enable(&self, index: usize) -> PortId {
+        PortId(self.read_ports + index * WRITE_PORT_INPUTS)
+    }
+
+    /// The handle of the `index`th write port's address input.
+    pub fn write_address(&self, index: usize) -> PortId {
+        PortId(self.write_enable(index).0 + 1)
+    }
+
+    /// The handle of the `index`th write port's value input.
+    pub fn write_value(&self, index: usize) -> PortId {
+        PortId(self.write_enable(index).0 + 2)
+    }
+
+    /// The handle of the read ports' clock enable input, if the memory has separate read and
+    /// write clocks.
+    pub fn read_clock_enable(&self) -> Option<PortId> {
+        self.clock_enables.then(|| PortId(self.read_ports + self.write_ports * WRITE_PORT_INPUTS))
+    }
+
+    /// The handle of the write ports' clock enable input, if the memory has separate read and
+    /// write clocks.
+    pub fn write_clock_enable(&self) -> Option<PortId> {
+        self.read_clock_enable().map(|port| PortId(port.0 + 1))
+    }
+
+    /// Set what reads give for an address that is being written this tick. With synchronous
+    /// reads, this is what is latched, so [`ReadDuringWrite::OldValue`] and
+    /// [`ReadDuringWrite::NewValue`] model read-first and write-first SRAM blocks.
+    pub fn set_read_during_write(&mut self, read_during_write: ReadDuringWrite) {
+        self.read_during_write = read_during_write;
+    }
+
+    /// Set whether reads are synchronous, in which case each read port latches the word at its
+    /// address on the tick, and gives it until the next tick. The read values then don't depend
+    /// on anything provided this tick, so they break any combinational path through the memory.
+    ///
+    /// A read port whose address isn't provided on a tick keeps giving the word it last read, as
+    /// does every read port on a tick where `rce` isn't set, if there is one. Until a read port
+    /// first reads something, it gives 0, or all X in four-state mode.
+    pub fn set_synchronous_read(&mut self, synchronous_read: bool) {
+        self.synchronous_read = synchronous_read;
+    }
+
+    /// Set whether this memory uses four-state logic, in which case reading an address that has
+    /// never been written gives all [`LogicBit::X`](crate::device::value::LogicBit::X) rather than
+    /// 0, so that reads of uninitialised memory can be spotted.
+    ///
+    /// Regardless of this setting, reading from an address with unknown bits gives all X, and
+    /// writing with an unknown write or clock enable only keeps the bits that are the same
+    /// whether or not the write happens.
+    pub fn set_four_state(&mut self, four_state: bool) {
+        self.four_state = four_state;
+    }
+
+    /// Store the contents of an image, grouping its bytes into words as given by `layout`. Word
+    /// `n` of the image is stored at address `n`, replacing anything already there.
+    ///
+    /// Fails, without storing anything, if a word's address or value is too wide for this memory
+    /// ([`DeviceError::DeviceSpecific`]).
+    pub fn load(&mut self, image: &Image, layout: WordLayout) -> Result<(), DeviceError> {
+        let address_width = self.ports[self.read_address(0).0].width;
+        let words = image.words_to_load(layout, address_width, self.data_width())?;
+        for (address, value) in words {
+            self.data.insert(address, value);
+        }
+        Ok(())
+    }
+
+    /// Take a snapshot of the words stored at the given addresses. Only addresses that have been
+    /// written or loaded are included.
+    pub fn dump(&self, addresses: Range<u64>) -> Dump {
+        let mut dump = Dump::new(self.data_width());
+        for (address, value) in self.data.words() {
+            if let Some(address) = address.to_u64().filter(|address| addresses.contains(address)) {
+                dump.insert(address, value.clone());
+            }
+        }
+        dump
+    }
+
+    /// Get the width of the data ports.
+    fn data_width(&self) -> u32 {
+        self.ports[self.read_value(0).0].width
+    }
+
+    /// Get the word currently stored at the given address, which must be known.
+    fn stored(&self, address: &PortValue) -> PortValue {
+        match self.data.get(address) {
+            Some(value) => value.clone(),
+            None if self.four_state => PortValue::x(self.data_width()),
+            None => PortValue::ZERO,
+        }
+    }
+
+    /// Get the value provided to the given input port this tick, if there is one.
+    fn provided(&self, port: PortId) -> Option<&PortValue> {
+        self.specified_this_tick[port.0].as_ref()
+    }
+
+    /// Get the value provided to the given input port this tick, failing with `message` if there
+    /// isn't one.
+    fn required(&self, port: PortId, message: &str) -> Result<&PortValue, DeviceError> {
+        self.provided(port).ok_or_else(|| DeviceError::MissingInput {
+            port: self.port_name(port),
+            message: message.to_owned(),
+        })
+    }
+
+    /// Work out whether the given side of the memory is clocked this tick, from its clock enable
+    /// if it has one. A clock enable that hasn't been provided counts as not set.
+    fn clocked(&self, clock_enable: Option<PortId>) -> LogicBit {
+        match clock_enable {
+            Some(port) => self.provided(port).map_or(LogicBit::Zero, |enable| enable.state(0)),
+            None => LogicBit::One,
+        }
+    }
+
+    /// Work out which address the given write port writes this tick, which may have unknown bits,
+    /// with what, and whether the write certainly happens, or `None` if it definitely doesn't
+    /// write.
+    ///
+    /// Only fails if inputs are missing, so this is safe to use when working out read values.
+    fn write_target(&self, index: usize)
+        -> Result<Option<(&PortValue, &PortValue, bool)>, DeviceError>
+    {
+        if let Some(port) = self.write_clock_enable() {
+            self.required(port, "the write clock enable must be provided every tick")?;
+        }
+        let clocked = self.clocked(self.write_clock_enable());
+        if clocked == LogicBit::Zero {
+            return Ok(None);
+        }
+        let enable = self.required(self.write_enable(index),
+            "every write enable must be provided every tick")?;
+        if enable.is_zero() {
+            return Ok(None);
+        }
+
+        let message = "write address and value must be provided when write enable is set";
+        let address = self.required(self.write_address(index), message)?;
+        let value = self.required(self.write_value(index), message)?;
+        Ok(Some((address, value, enable.is_known() && clocked == LogicBit::One)))
+    }
+
+    /// Work out which address the given write port writes this tick, with what, and whether the
+    /// write certainly happens, or `None` if it definitely doesn't write.
+    ///
+    /// Fails if the write address has unknown bits.
+    fn write(&self, index: usize) -> Result<Option<(&PortValue, &PortValue, bool)>, DeviceError> {
+        let write = self.write_target(index)?;
+        if write.is_some_and(|(address, _, _)| !address.is_known()) {
+            return Err(DeviceError::DeviceSpecific {
+                message: "cannot write to an address with unknown bits".to_owned(),
+            });
+        }
+        Ok(write)
+    }
+
+    /// Work out what a read of the given address gives this tick, from the values provided so
+    /// far, taking into account any writes to it. A write to an address with unknown bits might
+    /// be to this one, so makes the read all X.
+    fn read_during_write(&self, address: &PortValue) -> Result<PortValue, DeviceError> {
+        let mut value = self.stored(address);
+        if self.read_during_write == ReadDuringWrite::OldValue {
+            return Ok(value);
+        }
+        for index in 0..self.write_ports {
+            let Some((target, written, certain)) = self.write_target(index)? else {
+                continue;
+            };
+            if target.is_known() && target != address {
+                continue;
+            }
+            value = match self.read_during_write {
+                ReadDuringWrite::NewValue if target.is_known() => match certain {
+                    true => written.clone(),
+                    false => written.either(&value),
+                },
+                _ => PortValue::x(self.data_width()),
+            };
+        }
+        Ok(value)
+    }
+
+    /// Get the word the given read port last latched, for when reads are synchronous.
+    fn latched(&self, index: usize) -> PortValue {
+        match &self.latched[index] {
+            Some(value) => value.clone(),
+            None if self.four_state => PortValue::x(self.data_width()),
+            None => PortValue::ZERO,
+        }
+    }
+
+    /// Work out what the given read port reads this tick, or `None` if not enough has been
+    /// provided yet to tell.
+    fn read(&self, index: usize) -> Option<PortValue> {
+        let address = self.provided(self.read_address(index))?;
+        if !address.is_known() {
+            return Some(PortValue::x(self.data_width()));
+        }
+        // Bad write addresses are left for `check_tick` to report, so the only way this fails is
+        // if the write ports haven't all been provided yet
+        self.read_during_write(address).ok()
+    }
+}
+
+impl Device for MultiPortMemory {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        let index = output.0.checked_sub(self.specified_this_tick.len())
+            .filter(|index| *index < self.read_ports)
+            .ok_or_else(|| output_port_error(&self.ports, output))?;
+        if self.synchronous_read {
+            return Ok(HashSet::new());
+        }
+        let mut deps = HashSet::from([self.read_address(index)]);
+        if self.read_during_write != ReadDuringWrite::OldValue {
+            let write_inputs = self.read_ports..self.read_ports + self.write_ports
+                * WRITE_PORT_INPUTS;
+            deps.extend(write_inputs.map(PortId).chain(self.write_clock_enable()));
+        }
+        Ok(deps)
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        let index = port.0.checked_sub(self.specified_this_tick.len())
+            .filter(|index| *index < self.read_ports)
+            .ok_or_else(|| output_port_error(&self.ports, port))?;
+        if !self.synchronous_read {
+            return Ok(self.read(index));
+        }
+        Ok(Some(self.latched(index)))
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick.iter_mut().for_each(|input| *input = None);
+    }
+
+    fn check_tick(&self) -> Result<(), DeviceError> {
+        for index in 0..self.write_ports {
+            self.write(index)?;
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) -> Result<(), DeviceError> {
+        let mut written = Vec::new();
+        for index in 0..self.write_ports {
+            if let Some((address, value, certain)) = self.write(index)? {
+                written.push((address.clone(), value.clone(), certain));
+            }
+        }
+        // Reads are latched before anything is written, so that they see the old contents
+        let clocked = self.clocked(self.read_clock_enable());
+        if self.synchronous_read && clocked != LogicBit::Zero {
+            for index in 0..self.read_ports {
+                let Some(value) = self.read(index) else {
+                    continue;
+                };
+                self.latched[index] = Some(match clocked {
+                    LogicBit::One => value,
+                    _ => value.either(&self.latched(index)),
+                });
+            }
+        }
+        // Later write ports overwrite earlier ones to the same address
+        for (address, value, certain) in written {
+            let value = match certain {
+                true => value,
+                false => value.either(&self.stored(&address)),
+            };
+            self.data.insert(address, value);
+        }
+        self.clear_port_values();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::{Controller, ControllerError};
+    use crate::device::debug::constant::Constant;
+    use crate::device::multi_port_memory::MultiPortMemory;
+    use crate::device::register_file::ReadDuringWrite;
+    use crate::device::{Device, DeviceError, PortId, PortValue};
+
+    /// Provide a write to the given write port, or disable it if `write` is `None`.
+    fn write(memory: &mut MultiPortMemory, index: usize, write: Option<(u32, u32)>) {
+        let Some((address, value)) = write else {
+            memory.provide_port_value(memory.write_enable(index), PortValue::ZERO).unwrap();
+            return;
+        };
+        memory.provide_port_value(memory.write_enable(index), PortValue::from(1u32)).unwrap();
+        memory.provide_port_value(memory.write_address(index), PortValue::from(address)).unwrap();
+        memory.provide_port_value(memory.write_value(index), PortValue::from(value)).unwrap();
+    }
+
+    /// Read the given address through the given read port.
+    fn read(memory: &mut MultiPortMemory, index: usize, address: u32) -> Option<PortValue> {
+        memory.provide_port_value(memory.read_address(index), PortValue::from(address)).unwrap();
+        memory.get_port_value(memory.read_value(index)).unwrap()
+    }
+
+    #[test]
+    fn multi_port_memory_ports_are_laid_out_by_read_and_write_port() {
+        let memory = MultiPortMemory::with_widths(2, 2, 10, 32).unwrap();
+        let names: Vec<&str> = memory.get_ports().iter().map(|port| port.name.as_str()).collect();
+        assert_eq!(names, ["ra0", "ra1", "we0", "wa0", "wv0", "we1", "wa1", "wv1", "rv0", "rv1"]);
+        assert_eq!(memory.get_ports()[memory.write_address(1).0].width, 10);
+        assert_eq!(memory.get_ports()[memory.read_value(1).0].width, 32);
+        assert_eq!(memory.read_value(1), PortId(9));
+    }
+
+    #[test]
+    fn multi_port_memory_reads_and_writes_every_port_independently() {
+        let mut memory = MultiPortMemory::with_widths(3, 2, 8, 16).unwrap();
+        write(&mut memory, 0, Some((0x10, 0x1234)));
+        write(&mut memory, 1, Some((0x20, 0x5678)));
+        memory.tick().unwrap();
+
+        write(&mut memory, 0, Some((0x20, 0x9abc)));
+        write(&mut memory, 1, Some((0x20, 0xdef0)));
+        assert_eq!(read(&mut memory, 0, 0x10), Some(PortValue::from(0x1234u32)));
+        assert_eq!(read(&mut memory, 1, 0x20), Some(PortValue::from(0x5678u32)));
+        assert_eq!(read(&mut memory, 2, 0x30), Some(PortValue::ZERO));
+        memory.tick().unwrap();
+
+        // The highest numbered write port won
+        assert_eq!(read(&mut memory, 0, 0x20), Some(PortValue::from(0xdef0u32)));
+        assert_eq!(memory.dump(0..0x100).to_hex(), "00000010: 1234\n00000020: def0\n");
+    }
+
+    #[test]
+    fn multi_port_memory_read_during_write_behaviour_is_configurable() {
+        let cases = [
+            (ReadDuringWrite::OldValue, PortValue::from(1u32), 1),
+            (ReadDuringWrite::NewValue, PortValue::from(2u32), 4),
+            (ReadDuringWrite::Undefined, PortValue::x(8), 4),
+        ];
+        for (read_during_write, expected, deps) in cases {
+            let mut memory = MultiPortMemory::with_widths(2, 1, 4, 8).unwrap();
+            memory.set_read_during_write(read_during_write);
+            let dependencies = memory.get_output_dependencies(memory.read_value(0)).unwrap();
+            assert_eq!(dependencies.len(), deps, "{read_during_write:?}");
+            write(&mut memory, 0, Some((3, 1)));
+            memory.tick().unwrap();
+
+            write(&mut memory, 0, Some((3, 2)));
+            assert_eq!(read(&mut memory, 0, 3), Some(expected), "{read_during_write:?}");
+            assert_eq!(read(&mut memory, 1, 4), Some(PortValue::ZERO), "{read_during_write:?}");
+        }
+    }
+
+    #[test]
+    fn multi_port_memory_waits_for_write_ports_to_read_new_values() {
+        let mut memory = MultiPortMemory::with_widths(1, 2, 4, 8).unwrap();
+        memory.set_read_during_write(ReadDuringWrite::NewValue);
+        assert_eq!(read(&mut memory, 0, 1), None);
+        write(&mut memory, 0, Some((1, 5)));
+        assert_eq!(memory.get_port_value(memory.read_value(0)), Ok(None));
+        write(&mut memory, 1, None);
+        assert_eq!(memory.get_port_value(memory.read_value(0)), Ok(Some(PortValue::from(5u32))));
+    }
+
+    #[test]
+    fn multi_port_memory_synchronous_reads_appear_on_the_next_tick() {
+        let mut memory = MultiPortMemory::with_widths(1, 1, 4, 8).unwrap();
+        memory.set_synchronous_read(true);
+        memory.set_four_state(true);
+        assert!(memory.get_output_dependencies(memory.read_value(0)).unwrap().is_empty());
+        assert_eq!(memory.get_port_value(memory.read_value(0)), Ok(Some(PortValue::x(8))));
+
+        // The read is latched before the write, so sees the old contents
+        write(&mut memory, 0, Some((2, 7)));
+        assert_eq!(read(&mut memory, 0, 2), Some(PortValue::x(8)));
+        memory.tick().unwrap();
+        assert_eq!(memory.get_port_value(memory.read_value(0)), Ok(Some(PortValue::x(8))));
+
+        write(&mut memory, 0, None);
+        memory.provide_port_value(memory.read_address(0), PortValue::from(2u32)).unwrap();
+        memory.tick().unwrap();
+        assert_eq!(memory.get_port_value(memory.read_value(0)), Ok(Some(PortValue::from(7u32))));
+
+        // Without an address, the last word read is kept
+        write(&mut memory, 0, None);
+        memory.tick().unwrap();
+        assert_eq!(memory.get_port_value(memory.read_value(0)), Ok(Some(PortValue::from(7u32))));
+    }
+
+    #[test]
+    fn multi_port_memory_synchronous_reads_can_be_write_first() {
+        let mut memory = MultiPortMemory::with_widths(1, 1, 4, 8).unwrap();
+        memory.set_synchronous_read(true);
+        memory.set_read_during_write(ReadDuringWrite::NewValue);
+        write(&mut memory, 0, Some((2, 7)));
+        memory.provide_port_value(memory.read_address(0), PortValue::from(2u32)).unwrap();
+        memory.tick().unwrap();
+        assert_eq!(memory.get_port_value(memory.read_value(0)), Ok(Some(PortValue::from(7u32))));
+    }
+
+    #[test]
+    fn multi_port_memory_checks_write_ports() {
+        let mut memory = MultiPortMemory::with_widths(1, 2, 4, 8).unwrap();
+        write(&mut memory, 0, None);
+        assert!(matches!(
+            memory.check_tick(),
+            Err(DeviceError::MissingInput { port, .. }) if port == "we1"
+        ));
+
+        memory.provide_port_value(memory.write_enable(1), PortValue::from(1u32)).unwrap();
+        memory.provide_port_value(memory.write_address(1), PortValue::x(4)).unwrap();
+        assert!(matches!(
+            memory.check_tick(),
+            Err(DeviceError::MissingInput { port, .. }) if port == "wv1"
+        ));
+        memory.provide_port_value(memory.write_value(1), PortValue::ZERO).unwrap();
+        assert!(matches!(memory.tick(), Err(DeviceError::DeviceSpecific { .. })));
+
+        // An unknown write enable only keeps the bits that are the same either way
+        memory.clear_port_values();
+        write(&mut memory, 0, None);
+        memory.provide_port_value(memory.write_enable(1), PortValue::x(1)).unwrap();
+        memory.provide_port_value(memory.write_address(1), PortValue::from(1u32)).unwrap();
+        memory.provide_port_value(memory.write_value(1), PortValue::from(3u32)).unwrap();
+        memory.set_read_during_write(ReadDuringWrite::NewValue);
+        assert_eq!(read(&mut memory, 0, 1), Some("000000xx".parse().unwrap()));
+        memory.tick().unwrap();
+        write(&mut memory, 0, None);
+        write(&mut memory, 1, None);
+        assert_eq!(read(&mut memory, 0, 1), Some("000000xx".parse().unwrap()));
+        memory.tick().unwrap();
+
+        // Floating value bits don't leave the old value known either
+        write(&mut memory, 0, None);
+        memory.provide_port_value(memory.write_enable(1), PortValue::x(1)).unwrap();
+        memory.provide_port_value(memory.write_address(1), PortValue::from(1u32)).unwrap();
+        memory.provide_port_value(memory.write_value(1), "zzzzzzzz".parse().unwrap()).unwrap();
+        memory.tick().unwrap();
+        write(&mut memory, 0, None);
+        write(&mut memory, 1, None);
+        assert_eq!(read(&mut memory, 0, 1), Some(PortValue::x(8)));
+    }
+
+    #[test]
+    fn multi_port_memory_reads_leave_unknown_write_addresses_to_tick() {
+        for read_during_write in [ReadDuringWrite::NewValue, ReadDuringWrite::Undefined] {
+            let mut memory = MultiPortMemory::with_widths(1, 1, 4, 8).unwrap();
+            memory.set_read_during_write(read_during_write);
+            // The write might be to any address, so might be to the one read
+            memory.provide_port_value(memory.write_enable(0), PortValue::from(1u32)).unwrap();
+            memory.provide_port_value(memory.write_address(0), PortValue::x(4)).unwrap();
+            memory.provide_port_value(memory.write_value(0), PortValue::from(3u32)).unwrap();
+            assert_eq!(read(&mut memory, 0, 2), Some(PortValue::x(8)), "{read_during_write:?}");
+            assert!(matches!(memory.check_tick(), Err(DeviceError::DeviceSpecific { .. })));
+        }
+
+        // Through a controller, the bad write is an error from the tick
+        let mut controller = Controller::new();
+        let mut memory = MultiPortMemory::with_widths(1, 1, 4, 8).unwrap();
+        memory.set_read_during_write(ReadDuringWrite::NewValue);
+        controller.add_device("Memory".to_owned(), Box::new(memory)).unwrap();
+        let constants = [("One", 1, PortValue::from(1u32)), ("Unknown", 4, PortValue::x(4)),
+            ("Address", 4, PortValue::from(2u32)), ("Value", 8, PortValue::from(3u32))];
+        for (name, width, value) in constants {
+            let constant = Constant::with_width("qq".to_owned(), width, value).unwrap();
+            controller.add_device(name.to_owned(), Box::new(constant)).unwrap();
+        }
+        let connections = [("One", "we0"), ("Unknown", "wa0"), ("Value", "wv0"),
+            ("Address", "ra0")];
+        for (from, to_port) in connections {
+            controller.add_connection(
+                &from.to_owned(), &"qq".to_owned(), &"Memory".to_owned(), &to_port.to_owned(),
+            ).unwrap();
+        }
+        assert!(matches!(
+            controller.tick(),
+            Err(ControllerError::Device { device, source: DeviceError::DeviceSpecific { .. } })
+                if device == "Memory"
+        ));
+    }
+
+    #[test]
+    fn multi_port_memory_rejects_bad_configurations_and_ports() {
+        for (read_ports, write_ports) in [(0, 1), (1, 0)] {
+            assert!(matches!(
+                MultiPortMemory::new(read_ports, write_ports),
+                Err(DeviceError::DeviceSpecific { .. })
+            ));
+        }
+        assert!(matches!(
+            MultiPortMemory::with_widths(1, 1, 0, 8),
+            Err(DeviceError::InvalidWidth { width: 0 })
+        ));
+
+        let mut memory = MultiPortMemory::new(2, 1).unwrap();
+        assert_eq!(
+            memory.provide_port_value(memory.read_value(0), PortValue::ZERO),
+            Err(DeviceError::NotAnInputPort { port: "rv0".to_owned() }),
+        );
+        assert_eq!(
+            memory.get_port_value(memory.write_value(0)),
+            Err(DeviceError::NotAnOutputPort { port: "wv0".to_owned() }),
+        );
+        assert_eq!(
+            memory.get_output_dependencies(PortId(99)),
+            Err(DeviceError::UnknownPort { port: "#99".to_owned() }),
+        );
+    }
+
+    #[test]
+    fn multi_port_memory_dual_clock_clocks_each_side_separately() {
+        let mut memory = MultiPortMemory::dual_clock(1, 1, 4, 8).unwrap();
+        memory.set_synchronous_read(true);
+        let rce = memory.read_clock_enable().unwrap();
+        let wce = memory.write_clock_enable().unwrap();
+        let names: Vec<&str> = memory.get_ports().iter().map(|port| port.name.as_str()).collect();
+        assert_eq!(names, ["ra0", "we0", "wa0", "wv0", "rce", "wce", "rv0"]);
+        assert_eq!(memory.read_value(0), PortId(6));
+
+        // The write clock enable must always be provided, and gates every write port
+        write(&mut memory, 0, Some((2, 7)));
+        assert!(matches!(
+            memory.check_tick(),
+            Err(DeviceError::MissingInput { port, .. }) if port == "wce"
+        ));
+        memory.provide_port_value(wce, PortValue::ZERO).unwrap();
+        memory.tick().unwrap();
+        assert!(memory.dump(0..16).is_empty());
+
+        // Writing on one clock, while the read side isn't clocked
+        write(&mut memory, 0, Some((2, 7)));
+        memory.provide_port_value(wce, PortValue::from(1u32)).unwrap();
+        memory.provide_port_value(memory.read_address(0), PortValue::from(2u32)).unwrap();
+        memory.provide_port_value(rce, PortValue::ZERO).unwrap();
+        memory.tick().unwrap();
+        assert_eq!(memory.get_port_value(memory.read_value(0)), Ok(Some(PortValue::ZERO)));
+
+        // Reading on the other
+        write(&mut memory, 0, Some((2, 5)));
+        memory.provide_port_value(wce, "x".parse().unwrap()).unwrap();
+        memory.provide_port_value(memory.read_address(0), PortValue::from(2u32)).unwrap();
+        memory.provide_port_value(rce, PortValue::from(1u32)).unwrap();
+        memory.tick().unwrap();
+        assert_eq!(memory.get_port_value(memory.read_value(0)), Ok(Some(PortValue::from(7u32))));
+        assert_eq!(memory.dump(0..16).word(2), Some(&"000001x1".parse().unwrap()));
+
+        // An unknown read clock enable only keeps the bits that are the same either way
+        write(&mut memory, 0, None);
+        memory.provide_port_value(wce, PortValue::ZERO).unwrap();
+        memory.provide_port_value(memory.read_address(0), PortValue::from(3u32)).unwrap();
+        memory.provide_port_value(rce, "x".parse().unwrap()).unwrap();
+        memory.tick().unwrap();
+        let expected = "00000xxx".parse().unwrap();
+        assert_eq!(memory.get_port_value(memory.read_value(0)), Ok(Some(expected)));
+    }
+
+    #[test]
+    fn multi_port_memory_synchronous_read_breaks_write_through_feedback_loops() {
+        // Feeding read values back to write values is a combinational loop when reads see new
+        // values, unless the reads are synchronous
+        for (synchronous_read, allowed) in [(false, false), (true, true)] {
+            let mut controller = Controller::new();
+            let mut memory = MultiPortMemory::with_widths(1, 1, 2, 2).unwrap();
+            memory.set_read_during_write(ReadDuringWrite::NewValue);
+            memory.set_synchronous_read(synchronous_read);
+            controller.add_device("Memory".to_owned(), Box::new(memory)).unwrap();
+            let one = Constant::with_width("qq".to_owned(), 1, PortValue::from(1u32)).unwrap();
+            controller.add_device("One".to_owned(), Box::new(one)).unwrap();
+            let address = Constant::with_width("qq".to_owned(), 2, PortValue::from(1u32)).unwrap();
+            controller.add_device("Address".to_owned(), Box::new(address)).unwrap();
+
+            let connections = [("One", "qq", "Memory", "we0"), ("Address", "qq", "Memory", "wa0"),
+                ("Address", "qq", "Memory", "ra0")];
+            for (from, from_port, to, to_port) in connections {
+                controller.add_connection(
+                    &from.to_owned(), &from_port.to_owned(),
+                    &to.to_owned(), &to_port.to_owned(),
+                ).unwrap();
+            }
+            let feedback = controller.add_connection(
+                &"Memory".to_owned(), &"rv0".to_owned(), &"Memory".to_owned(), &"wv0".to_owned(),
+            );
+            assert_eq!(feedback.is_ok(), allowed, "{synchronous_read}");
+            if allowed {
+                let state = controller.tick().unwrap();
+                assert_eq!(state.value_by_name("Memory", "rv0"), Some(&PortValue::ZERO));
+            }
+        }
+    }
+}
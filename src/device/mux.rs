@@ -0,0 +1,416 @@
+use std::collections::{HashMap, HashSet};
+use crate::device::{
+    check_width, output_port_error, provide_all_into, provide_into, Device, DeviceError,
+    PortDescriptor, PortId, PortValue, DEFAULT_PORT_WIDTH,
+};
+
+/// Get the width of a select input able to pick between `ways` options. Always at least 1 bit.
+pub(crate) fn select_width(ways: usize) -> u32 {
+    (usize::BITS - ways.saturating_sub(1).leading_zeros()).max(1)
+}
+
+/// Check that a mux or demux has at least one way to select.
+fn check_ways(ways: usize) -> Result<(), DeviceError> {
+    match ways >= 1 {
+        true => Ok(()),
+        false => Err(DeviceError::DeviceSpecific {
+            message: "there must be at least one way to select".to_owned(),
+        }),
+    }
+}
+
+/// Decode a select value: `Ok(Some(way))` if it picks one of the given number of ways,
+/// `Ok(None)` if it is out of range, or `Err(())` if it has unknown bits.
+pub(crate) fn decode_select(select: &PortValue, ways: usize) -> Result<Option<usize>, ()> {
+    if !select.is_known() {
+        return Err(());
+    }
+    Ok(select.to_u64()
+        .and_then(|way| usize::try_from(way).ok())
+        .filter(|way| *way < ways))
+}
+
+/// An N-way multiplexer, which passes the input picked by `sel` through to `out`.
+///
+/// The inputs are called `in0`, `in1` and so on. The output only depends on `sel` and whichever
+/// input it picks (see [`Device::has_dynamic_dependencies`]), so the other inputs may be left
+/// unprovided, or even fed back from `out`. If `sel` picks an input that doesn't exist, `out` has
+/// no value; if `sel` has unknown bits, `out` is all X.
+pub struct Mux {
+    specified_this_tick: Vec<Option<PortValue>>,
+    ports: Vec<PortDescriptor>,
+}
+
+impl Mux {
+    /// Select
+    pub const SELECT: PortId = PortId(0);
+
+    /// The handle of the `index`th data input.
+    pub fn input(index: usize) -> PortId {
+        PortId(index + 1)
+    }
+
+    /// The handle of the output, which comes after every input.
+    pub fn output(&self) -> PortId {
+        PortId(self.ports.len() - 1)
+    }
+
+    /// Create a mux choosing between the given number of [`DEFAULT_PORT_WIDTH`]-bit inputs.
+    pub fn new(inputs: usize) -> Result<Mux, DeviceError> {
+        Mux::with_width(inputs, DEFAULT_PORT_WIDTH)
+    }
+
+    /// Create a mux choosing between the given number of `width`-bit inputs. The select input is
+    /// just wide enough to pick any of them.
+    pub fn with_width(inputs: usize, width: u32) -> Result<Mux, DeviceError> {
+        check_width(width)?;
+        check_ways(inputs)?;
+
+        // The order of these must match the port handles above
+        let mut ports = vec![PortDescriptor::input("sel", select_width(inputs))];
+        ports.extend((0..inputs).map(|index| PortDescriptor::input(&format!("in{index}"), width)));
+        ports.push(PortDescriptor::output("out", width));
+
+        Ok(Mux { specified_this_tick: vec![None; inputs + 1], ports })
+    }
+
+    /// Get the number of data inputs.
+    fn ways(&self) -> usize {
+        self.specified_this_tick.len() - 1
+    }
+
+}
+
+impl Device for Mux {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        if output != self.output() {
+            return Err(output_port_error(&self.ports, output));
+        }
+        Ok((0..self.specified_this_tick.len()).map(PortId).collect())
+    }
+
+    fn has_dynamic_dependencies(&self) -> bool {
+        true
+    }
+
+    fn get_current_output_dependencies(&self, output: PortId, deps: &mut Vec<PortId>)
+        -> Result<(), DeviceError>
+    {
+        if output != self.output() {
+            return Err(output_port_error(&self.ports, output));
+        }
+        deps.clear();
+        deps.push(Self::SELECT);
+        if let Some(select) = &self.specified_this_tick[Self::SELECT.0] {
+            if let Ok(Some(way)) = decode_select(select, self.ways()) {
+                deps.push(Self::input(way));
+            }
+        }
+        Ok(())
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        if port != self.output() {
+            return Err(output_port_error(&self.ports, port));
+        }
+        let Some(select) = &self.specified_this_tick[Self::SELECT.0] else {
+            return Ok(None);
+        };
+        Ok(match decode_select(select, self.ways()) {
+            Err(()) => Some(PortValue::x(self.ports[port.0].width)),
+            Ok(None) => None,
+            Ok(Some(way)) => self.specified_this_tick[Self::input(way).0].clone(),
+        })
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick.iter_mut().for_each(|input| *input = None);
+    }
+}
+
+/// An N-way demultiplexer, which passes `in` through to the output picked by `sel`, and outputs 0
+/// everywhere else.
+///
+/// The outputs are called `out0`, `out1` and so on. Each output only needs `in` if `sel` picks it
+/// (see [`Device::has_dynamic_dependencies`]). If `sel` picks an output that doesn't exist, every
+/// output is 0; if `sel` has unknown bits, every output is all X.
+pub struct Demux {
+    specified_this_tick: [Option<PortValue>; 2],
+    ports: Vec<PortDescriptor>,
+}
+
+impl Demux {
+    /// Select
+    pub const SELECT: PortId = PortId(0);
+    /// Data in
+    pub const INPUT: PortId = PortId(1);
+
+    /// The handle of the `index`th output.
+    pub fn output(index: usize) -> PortId {
+        PortId(index + 2)
+    }
+
+    /// Create a demux with the given number of [`DEFAULT_PORT_WIDTH`]-bit outputs.
+    pub fn new(outputs: usize) -> Result<Demux, DeviceError> {
+        Demux::with_width(outputs, DEFAULT_PORT_WIDTH)
+    }
+
+    /// Create a demux with the given number of `width`-bit outputs. The select input is just wide
+    /// enough to pick any of them.
+    pub fn with_width(outputs: usize, width: u32) -> Result<Demux, DeviceError> {
+        check_width(width)?;
+        check_ways(outputs)?;
+
+        // The order of these must match the port handles above
+        let mut ports = vec![
+            PortDescriptor::input("sel", select_width(outputs)),
+            PortDescriptor::input("in", width),
+        ];
+        ports.extend((0..outputs)
+            .map(|index| PortDescriptor::output(&format!("out{index}"), width)));
+
+        Ok(Demux { specified_this_tick: Default::default(), ports })
+    }
+
+    /// Get the number of outputs.
+    fn ways(&self) -> usize {
+        self.ports.len() - 2
+    }
+
+    /// Get which way the given output port is, checking that it is an output port.
+    fn way(&self, port: PortId) -> Result<usize, DeviceError> {
+        match port.0 {
+            0 | 1 => Err(DeviceError::NotAnOutputPort { port: self.port_name(port) }),
+            index if index < self.ports.len() => Ok(index - 2),
+            _ => Err(DeviceError::UnknownPort { port: self.port_name(port) }),
+        }
+    }
+}
+
+impl Device for Demux {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        self.way(output)?;
+        Ok(HashSet::from([Self::SELECT, Self::INPUT]))
+    }
+
+    fn has_dynamic_dependencies(&self) -> bool {
+        true
+    }
+
+    fn get_current_output_dependencies(&self, output: PortId, deps: &mut Vec<PortId>)
+        -> Result<(), DeviceError>
+    {
+        let way = self.way(output)?;
+        deps.clear();
+        deps.push(Self::SELECT);
+        if let Some(select) = &self.specified_this_tick[Self::SELECT.0] {
+            if decode_select(select, self.ways()) == Ok(Some(way)) {
+                deps.push(Self::INPUT);
+            }
+        }
+        Ok(())
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        let way = self.way(port)?;
+        let Some(select) = &self.specified_this_tick[Self::SELECT.0] else {
+            return Ok(None);
+        };
+        Ok(match decode_select(select, self.ways()) {
+            Err(()) => Some(PortValue::x(self.ports[port.0].width)),
+            Ok(Some(selected)) if selected == way =>
+                self.specified_this_tick[Self::INPUT.0].clone(),
+            Ok(_) => Some(PortValue::ZERO),
+        })
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::Controller;
+    use crate::device::debug::constant::Constant;
+    use crate::device::mux::{Demux, Mux};
+    use crate::device::{Device, DeviceError, PortId, PortValue};
+
+    /// Get the inputs that the given output of a device currently depends on.
+    fn current_dependencies(device: &dyn Device, output: PortId)
+        -> Result<Vec<PortId>, DeviceError>
+    {
+        let mut deps = Vec::new();
+        device.get_current_output_dependencies(output, &mut deps)?;
+        Ok(deps)
+    }
+
+    #[test]
+    fn mux_select_is_just_wide_enough() {
+        for (inputs, width) in [(1, 1), (2, 1), (3, 2), (4, 2), (5, 3), (256, 8)] {
+            let mux = Mux::new(inputs).unwrap();
+            assert_eq!(mux.get_ports()[Mux::SELECT.0].width, width, "{inputs} inputs");
+            assert_eq!(mux.get_ports().len(), inputs + 2);
+        }
+        assert!(matches!(Mux::new(0), Err(DeviceError::DeviceSpecific { .. })));
+        assert!(matches!(Mux::with_width(2, 0), Err(DeviceError::InvalidWidth { width: 0 })));
+    }
+
+    #[test]
+    fn mux_passes_through_the_selected_input() {
+        let mut mux = Mux::with_width(3, 8).unwrap();
+        for index in 0..3 {
+            mux.provide_port_value(Mux::input(index), PortValue::from(index as u32 + 10)).unwrap();
+        }
+        mux.provide_port_value(Mux::SELECT, PortValue::from(2u32)).unwrap();
+        assert_eq!(mux.get_port_value(mux.output()), Ok(Some(PortValue::from(12u32))));
+    }
+
+    #[test]
+    fn mux_only_needs_the_selected_input() {
+        let mut mux = Mux::new(4).unwrap();
+        let output = mux.output();
+        assert_eq!(current_dependencies(&mux, output), Ok(vec![Mux::SELECT]));
+        assert_eq!(mux.get_port_value(output), Ok(None));
+
+        mux.provide_port_value(Mux::SELECT, PortValue::from(1u32)).unwrap();
+        assert_eq!(current_dependencies(&mux, output), Ok(vec![Mux::SELECT, Mux::input(1)]));
+        assert_eq!(mux.get_port_value(output), Ok(None));
+
+        mux.provide_port_value(Mux::input(1), PortValue::from(7u32)).unwrap();
+        assert_eq!(mux.get_port_value(output), Ok(Some(PortValue::from(7u32))));
+        assert_eq!(mux.get_output_dependencies(output).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn mux_handles_out_of_range_and_unknown_selects() {
+        let mut mux = Mux::with_width(3, 4).unwrap();
+        mux.provide_port_value(Mux::SELECT, PortValue::from(3u32)).unwrap();
+        assert_eq!(mux.get_port_value(mux.output()), Ok(None));
+
+        mux.clear_port_values();
+        mux.provide_port_value(Mux::SELECT, "1x".parse().unwrap()).unwrap();
+        assert_eq!(mux.get_port_value(mux.output()), Ok(Some(PortValue::x(4))));
+    }
+
+    #[test]
+    fn mux_rejects_bad_ports() {
+        let mut mux = Mux::new(2).unwrap();
+        assert_eq!(
+            mux.provide_port_value(mux.output(), PortValue::ZERO),
+            Err(DeviceError::NotAnInputPort { port: "out".to_owned() }),
+        );
+        assert_eq!(
+            mux.get_port_value(Mux::input(0)),
+            Err(DeviceError::NotAnOutputPort { port: "in0".to_owned() }),
+        );
+        assert_eq!(
+            mux.get_port_value(PortId(9)),
+            Err(DeviceError::UnknownPort { port: "#9".to_owned() }),
+        );
+        mux.provide_port_value(Mux::SELECT, PortValue::ZERO).unwrap();
+        assert_eq!(
+            mux.provide_port_value(Mux::SELECT, PortValue::ZERO),
+            Err(DeviceError::AlreadyProvided { port: "sel".to_owned() }),
+        );
+    }
+
+    #[test]
+    fn demux_routes_the_input_to_the_selected_output() {
+        let mut demux = Demux::with_width(4, 8).unwrap();
+        demux.provide_port_value(Demux::SELECT, PortValue::from(2u32)).unwrap();
+
+        // The unselected outputs are known straight away
+        assert_eq!(demux.get_port_value(Demux::output(0)), Ok(Some(PortValue::ZERO)));
+        assert_eq!(demux.get_port_value(Demux::output(2)), Ok(None));
+        assert_eq!(
+            current_dependencies(&demux, Demux::output(2)),
+            Ok(vec![Demux::SELECT, Demux::INPUT]),
+        );
+        assert_eq!(current_dependencies(&demux, Demux::output(3)), Ok(vec![Demux::SELECT]));
+
+        demux.provide_port_value(Demux::INPUT, PortValue::from(0x5au32)).unwrap();
+        assert_eq!(demux.get_port_value(Demux::output(2)), Ok(Some(PortValue::from(0x5au32))));
+        assert_eq!(demux.get_port_value(Demux::output(3)), Ok(Some(PortValue::ZERO)));
+    }
+
+    #[test]
+    fn demux_handles_out_of_range_and_unknown_selects() {
+        let mut demux = Demux::with_width(3, 4).unwrap();
+        demux.provide_port_value(Demux::SELECT, PortValue::from(3u32)).unwrap();
+        for way in 0..3 {
+            assert_eq!(demux.get_port_value(Demux::output(way)), Ok(Some(PortValue::ZERO)));
+        }
+
+        demux.clear_port_values();
+        demux.provide_port_value(Demux::SELECT, "x0".parse().unwrap()).unwrap();
+        assert_eq!(demux.get_port_value(Demux::output(0)), Ok(Some(PortValue::x(4))));
+    }
+
+    #[test]
+    fn demux_rejects_bad_ports() {
+        let demux = Demux::new(2).unwrap();
+        assert_eq!(
+            demux.get_port_value(Demux::INPUT),
+            Err(DeviceError::NotAnOutputPort { port: "in".to_owned() }),
+        );
+        assert_eq!(
+            demux.get_output_dependencies(Demux::output(2)),
+            Err(DeviceError::UnknownPort { port: "#4".to_owned() }),
+        );
+    }
+
+    #[test]
+    fn mux_allows_feedback_through_unselected_inputs_in_a_controller() {
+        let mut controller = Controller::new();
+        controller.add_device("Mux".to_owned(), Box::new(Mux::with_width(2, 8).unwrap())).unwrap();
+        let select = Constant::with_width("qq".to_owned(), 1, PortValue::ZERO).unwrap();
+        controller.add_device("Select".to_owned(), Box::new(select)).unwrap();
+        let value = Constant::with_width("qq".to_owned(), 8, PortValue::from(9u32)).unwrap();
+        controller.add_device("Value".to_owned(), Box::new(value)).unwrap();
+
+        let connections = [("Select", "qq", "Mux", "sel"), ("Value", "qq", "Mux", "in0"),
+            ("Mux", "out", "Mux", "in1")];
+        for (from, from_port, to, to_port) in connections {
+            controller.add_connection(
+                &from.to_owned(), &from_port.to_owned(),
+                &to.to_owned(), &to_port.to_owned(),
+            ).unwrap();
+        }
+
+        let state = controller.tick().unwrap();
+        assert_eq!(state.value_by_name("Mux", "out"), Some(&PortValue::from(9u32)));
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::{HashMap, HashSet};
+use crate::device::{
+    check_width, output_port_error, provide_all_into, provide_into, Device, DeviceError,
+    PortDescriptor, PortId, PortValue, DEFAULT_PORT_WIDTH,
+};
+
+/// Number of input ports on a [`Register`]. These come first in its port list, so an input port's
+/// [`PortId`] is also its index in `specified_this_tick`.
+const INPUT_PORT_COUNT: usize = 3;
+
+/// A bank of D flip-flops, which stores a value from one tick to the next.
+///
+/// The stored value is always available on `q`, and doesn't depend on anything provided this
+/// tick, so a register breaks any combinational path through it. On each tick, if `rst` is set
+/// the stored value is cleared to 0; otherwise, if `en` is set, `d` is stored. Both `en` and
+/// `rst` must be provided every tick, and `d` whenever it is going to be stored.
+pub struct Register {
+    /// The stored value, or `None` if nothing has been stored yet.
+    value: Option<PortValue>,
+    specified_this_tick: [Option<PortValue>; INPUT_PORT_COUNT],
+    ports: [PortDescriptor; INPUT_PORT_COUNT + 1],
+
+    /// Whether a register that has never been written reads as all X rather than 0.
+    four_state: bool,
+}
+
+impl Register {
+    /// Data in
+    pub const D: PortId = PortId(0);
+    /// Enable
+    pub const EN: PortId = PortId(1);
+    /// Synchronous reset
+    pub const RST: PortId = PortId(2);
+    /// Data out
+    pub const Q: PortId = PortId(3);
+
+    /// Create a register holding a [`DEFAULT_PORT_WIDTH`]-bit value.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Register {
+        Register::with_width(DEFAULT_PORT_WIDTH)
+            .expect("The default port width should always be a valid width")
+    }
+
+    /// Create a register whose data ports (`d` and `q`) are `width` bits wide. Enable and reset
+    /// are always a single bit.
+    pub fn with_width(width: u32) -> Result<Register, DeviceError> {
+        check_width(width)?;
+
+        // The order of these must match the port handles above
+        let ports = [
+            PortDescriptor::input("d", width),
+            PortDescriptor::input("en", 1),
+            PortDescriptor::input("rst", 1),
+            PortDescriptor::output("q", width),
+        ];
+
+        Ok(Register {
+            value: None,
+            specified_this_tick: Default::default(),
+            ports,
+            four_state: false,
+        })
+    }
+
+    /// Set whether this register uses four-state logic, in which case it reads as all
+    /// [`LogicBit::X`](crate::device::value::LogicBit::X) rather than 0 until it is first written
+    /// or reset.
+    ///
+    /// Regardless of this setting, an unknown enable or reset leaves X in any bit that would end
+    /// up different depending on whether the write or reset happens.
+    pub fn set_four_state(&mut self, four_state: bool) {
+        self.four_state = four_state;
+    }
+
+    /// Get the width of the data ports.
+    fn width(&self) -> u32 {
+        self.ports[Self::Q.0].width
+    }
+
+    /// Get the value currently on `q`.
+    fn stored(&self) -> PortValue {
+        match &self.value {
+            Some(value) => value.clone(),
+            None if self.four_state => PortValue::x(self.width()),
+            None => PortValue::ZERO,
+        }
+    }
+
+    /// Get the value provided to the given input port this tick, if there is one.
+    fn provided(&self, port: PortId) -> Option<&PortValue> {
+        self.specified_this_tick[port.0].as_ref()
+    }
+
+    /// Get the value provided to a control input this tick, failing if there isn't one.
+    fn control(&self, port: PortId) -> Result<&PortValue, DeviceError> {
+        self.provided(port).ok_or_else(|| DeviceError::MissingInput {
+            port: self.port_name(port),
+            message: "enable and reset must be provided every tick".to_owned(),
+        })
+    }
+
+    /// Work out what will be stored after this tick, from the values provided so far.
+    fn next_value(&self) -> Result<PortValue, DeviceError> {
+        let enable = self.control(Self::EN)?;
+        let reset = self.control(Self::RST)?;
+
+        // Don't need `d` if it definitely isn't going to be stored
+        let loaded = match enable.is_zero() || reset.bit(0) {
+            true => self.stored(),
+            false => {
+                let Some(d) = self.provided(Self::D) else {
+                    return Err(DeviceError::MissingInput {
+                        port: self.port_name(Self::D),
+                        message: "data must be provided when enable is set".to_owned(),
+                    });
+                };
+                match enable.is_known() {
+                    true => d.clone(),
+                    false => d.either(&self.stored()),
+                }
+            }
+        };
+        Ok(match reset.is_known() {
+            true if reset.bit(0) => PortValue::ZERO,
+            true => loaded,
+            false => loaded.either(&PortValue::ZERO),
+        })
+    }
+}
+
+impl Device for Register {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        if output != Self::Q {
+            return Err(output_port_error(&self.ports, output));
+        }
+        // The stored value was decided last tick
+        Ok(HashSet::new())
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        if port != Self::Q {
+            return Err(output_port_error(&self.ports, port));
+        }
+        Ok(Some(self.stored()))
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick = Default::default();
+    }
+
+    fn check_tick(&self) -> Result<(), DeviceError> {
+        self.next_value().map(|_| ())
+    }
+
+    fn tick(&mut self) -> Result<(), DeviceError> {
+        self.value = Some(self.next_value()?);
+        self.clear_port_values();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::device::{Device, DeviceError, PortValue};
+    use crate::device::register::Register;
+
+    /// Provide the register with the given inputs, leaving out any that are `None`.
+    fn provide(register: &mut Register, d: Option<&str>, en: &str, rst: &str) {
+        let mut values = HashMap::from([
+            (Register::EN, en.parse().unwrap()),
+            (Register::RST, rst.parse().unwrap()),
+        ]);
+        if let Some(d) = d {
+            values.insert(Register::D, d.parse().unwrap());
+        }
+        register.provide_port_values(values).unwrap();
+    }
+
+    fn q(register: &Register) -> PortValue {
+        register.get_port_value(Register::Q).unwrap().unwrap()
+    }
+
+    #[test]
+    fn register_starts_at_zero() {
+        let register = Register::new();
+        assert_eq!(q(&register), PortValue::ZERO);
+    }
+
+    #[test]
+    fn register_output_depends_on_no_inputs() {
+        let register = Register::new();
+        assert!(register.get_output_dependencies(Register::Q).unwrap().is_empty());
+        assert_eq!(
+            register.get_output_dependencies(Register::D),
+            Err(DeviceError::NotAnOutputPort { port: "d".to_owned() }),
+        );
+    }
+
+    #[test]
+    fn register_stores_d_only_when_enabled() {
+        let mut register = Register::with_width(8).unwrap();
+
+        provide(&mut register, Some("1010_0101"), "1", "0");
+        // The new value doesn't appear until after the tick
+        assert_eq!(q(&register), PortValue::ZERO);
+        register.tick().unwrap();
+        assert_eq!(q(&register), PortValue::from(0xa5u32));
+
+        provide(&mut register, Some("1111_1111"), "0", "0");
+        register.tick().unwrap();
+        assert_eq!(q(&register), PortValue::from(0xa5u32));
+    }
+
+    #[test]
+    fn register_reset_takes_priority_over_enable() {
+        let mut register = Register::with_width(8).unwrap();
+        provide(&mut register, Some("1010_0101"), "1", "0");
+        register.tick().unwrap();
+
+        provide(&mut register, Some("1111_1111"), "1", "1");
+        register.tick().unwrap();
+        assert_eq!(q(&register), PortValue::ZERO);
+    }
+
+    #[test]
+    fn register_only_needs_d_when_it_is_stored() {
+        let mut register = Register::new();
+        provide(&mut register, None, "0", "0");
+        register.tick().unwrap();
+        provide(&mut register, None, "1", "1");
+        register.tick().unwrap();
+
+        provide(&mut register, None, "1", "0");
+        assert!(matches!(
+            register.check_tick(),
+            Err(DeviceError::MissingInput { port, .. }) if port == "d"
+        ));
+    }
+
+    #[test]
+    fn register_needs_enable_and_reset_every_tick() {
+        let mut register = Register::new();
+        register.provide_port_value(Register::EN, PortValue::from(0u32)).unwrap();
+        assert!(matches!(
+            register.tick(),
+            Err(DeviceError::MissingInput { port, .. }) if port == "rst"
+        ));
+
+        // Start again with only reset provided
+        register.clear_port_values();
+        register.provide_port_value(Register::RST, PortValue::from(0u32)).unwrap();
+        assert!(matches!(
+            register.tick(),
+            Err(DeviceError::MissingInput { port, .. }) if port == "en"
+        ));
+    }
+
+    #[test]
+    fn register_masks_d_to_its_width() {
+        let mut register = Register::with_width(4).unwrap();
+        register.provide_port_value(Register::D, PortValue::from(0xabu32)).unwrap();
+        register.provide_port_value(Register::EN, PortValue::from(1u32)).unwrap();
+        register.provide_port_value(Register::RST, PortValue::from(0u32)).unwrap();
+        register.tick().unwrap();
+        assert_eq!(q(&register), PortValue::from(0xbu32));
+    }
+
+    #[test]
+    fn register_cannot_have_outputs_or_repeated_inputs_provided() {
+        let mut register = Register::new();
+        assert_eq!(
+            register.provide_port_value(Register::Q, PortValue::ZERO),
+            Err(DeviceError::NotAnInputPort { port: "q".to_owned() }),
+        );
+        register.provide_port_value(Register::D, PortValue::ZERO).unwrap();
+        assert_eq!(
+            register.provide_port_value(Register::D, PortValue::ZERO),
+            Err(DeviceError::AlreadyProvided { port: "d".to_owned() }),
+        );
+    }
+
+    #[test]
+    fn four_state_register_starts_unknown() {
+        let mut register = Register::with_width(4).unwrap();
+        register.set_four_state(true);
+        assert_eq!(q(&register), PortValue::x(4));
+    }
+
+    #[test]
+    fn register_keeps_only_certain_bits_with_unknown_controls() {
+        let mut register = Register::with_width(4).unwrap();
+        provide(&mut register, Some("0011"), "1", "0");
+        register.tick().unwrap();
+
+        // The write may or may not happen, so bits that differ between `d` and `q` are unknown
+        provide(&mut register, Some("0101"), "x", "0");
+        register.tick().unwrap();
+        assert_eq!(q(&register), "0xx1".parse().unwrap());
+
+        // Likewise if it may or may not be reset
+        provide(&mut register, None, "0", "z");
+        register.tick().unwrap();
+        assert_eq!(q(&register), "0xxx".parse().unwrap());
+    }
+
+    #[test]
+    fn register_does_not_let_z_through_with_unknown_controls() {
+        let mut register = Register::with_width(4).unwrap();
+        provide(&mut register, Some("1111"), "1", "0");
+        register.tick().unwrap();
+
+        // A floating `d` that may or may not be stored doesn't leave the old value known
+        provide(&mut register, Some("zzzz"), "x", "0");
+        register.tick().unwrap();
+        assert_eq!(q(&register), PortValue::x(4));
+
+        // Nor does a stored Z become 0 when the reset may or may not happen
+        provide(&mut register, Some("zz00"), "1", "0");
+        register.tick().unwrap();
+        provide(&mut register, None, "0", "x");
+        register.tick().unwrap();
+        assert_eq!(q(&register), "xx00".parse().unwrap());
+    }
+}
@@ -0,0 +1,643 @@
+use std::collections::{HashMap, HashSet};
+use crate::device::mux::{decode_select, select_width};
+use crate::device::{
+    check_width, output_port_error, provide_all_into, provide_into, Device, DeviceError,
+    PortDescriptor, PortId, PortValue, DEFAULT_PORT_WIDTH,
+};
+
+/// The most registers a [`RegisterFile`] can have.
+pub const MAX_REGISTERS: usize = 4096;
+
+/// Number of input ports for each write port of a [`RegisterFile`]: enable, address and data.
+const WRITE_PORT_INPUTS: usize = 3;
+
+/// What the read ports of a [`RegisterFile`] or
+/// [`MultiPortMemory`](crate::device::multi_port_memory::MultiPortMemory) give for a location that
+/// is being written this tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ReadDuringWrite {
+    /// Reads give the value from before the write, which only appears after the tick. Read data
+    /// then only depends on the read address.
+    #[default]
+    OldValue,
+    /// Reads give the value being written, as if writes happened in the first half of the tick
+    /// and reads in the second. Read data then depends on every write port's inputs too.
+    NewValue,
+    /// Reads give all X, as real hardware with no defined behaviour might give anything. Read data
+    /// depends on every write port's inputs too, to know whether the location is being written.
+    Undefined,
+}
+
+/// A bank of registers with any number of read and write ports, such as a CPU's integer registers.
+///
+/// Each read port has an address input `ra0`, `ra1` and so on, and a data output `rd0`, `rd1` and
+/// so on. Each write port has an enable `we0`, address `wa0` and data `wd0`, and so on. Addresses
+/// are just wide enough to pick any register. Reading a register that doesn't exist, or an address
+/// with unknown bits, gives all X.
+///
+/// On each tick, every enabled write port stores its data in the register it addresses. If more
+/// than one write port writes the same register, the highest numbered one wins. Every write enable
+/// must be provided every tick, and the address and data of a write port whenever it is enabled.
+/// Whether reads see this tick's writes is set by [`RegisterFile::set_read_during_write`].
+///
+/// One register can be hard-wired to zero (see [`RegisterFile::set_zero_register`]), in which case
+/// it always reads as 0 and writes to it are ignored.
+pub struct RegisterFile {
+    /// The stored values, or `None` for registers that have never been written.
+    registers: Vec<Option<PortValue>>,
+    specified_this_tick: Vec<Option<PortValue>>,
+    ports: Vec<PortDescriptor>,
+    read_ports: usize,
+    write_ports: usize,
+
+    zero_register: Option<usize>,
+    read_during_write: ReadDuringWrite,
+
+    /// Whether a register that has never been written reads as all X rather than 0.
+    four_state: bool,
+}
+
+impl RegisterFile {
+    /// Create a register file with the given number of [`DEFAULT_PORT_WIDTH`]-bit registers, read
+    /// ports and write ports.
+    pub fn new(registers: usize, read_ports: usize, write_ports: usize)
+        -> Result<RegisterFile, DeviceError>
+    {
+        RegisterFile::with_width(registers, read_ports, write_ports, DEFAULT_PORT_WIDTH)
+    }
+
+    /// Create a register file with the given number of `width`-bit registers, read ports and write
+    /// ports.
+    ///
+    /// Fails if the width is invalid ([`DeviceError::InvalidWidth`]), if there are no registers or
+    /// more than [`MAX_REGISTERS`], or if there are no read ports or no write ports
+    /// ([`DeviceError::DeviceSpecific`]).
+    pub fn with_width(registers: usize, read_ports: usize, write_ports: usize, width: u32)
+        -> Result<RegisterFile, DeviceError>
+    {
+        check_width(width)?;
+        if !(1..=MAX_REGISTERS).contains(&registers) {
+            return Err(DeviceError::DeviceSpecific {
+                message: format!("a register file must have between 1 and {MAX_REGISTERS} \
+                    registers, not {registers}"),
+            });
+        }
+        if read_ports == 0 || write_ports == 0 {
+            return Err(DeviceError::DeviceSpecific {
+                message: "a register file must have at least one read port and one write port"
+                    .to_owned(),
+            });
+        }
+
+        // The order of these must match the port handles below
+        let address_width = select_width(registers);
+        let mut ports: Vec<PortDescriptor> = (0..read_ports)
+            .map(|index| PortDescriptor::input(&format!("ra{index}"), address_width))
+            .collect();
+        for index in 0..write_ports {
+            ports.push(PortDescriptor::input(&format!("we{index}"), 1));
+            ports.push(PortDescriptor::input(&format!("wa{index}"), address_width));
+            ports.push(PortDescriptor::input(&format!("wd{index}"), width));
+        }
+        let inputs = ports.len();
+        ports.extend((0..read_ports)
+            .map(|index| PortDescriptor::output(&format!("rd{index}"), width)));
+
+        Ok(RegisterFile {
+            registers: vec![None; registers],
+            specified_this_tick: vec![None; inputs],
+            ports,
+            read_ports,
+            write_ports,
+            zero_register: None,
+            read_during_write: ReadDuringWrite::default(),
+            four_state: false,
+        })
+    }
+
+    /// The handle of the `index`th read port's address input.
+    pub fn read_address(&self, index: usize) -> PortId {
+        PortId(index)
+    }
+
+    /// The handle of the `index`th read port's data output.
+    pub fn read_data(&self, index: usize) -> PortId {
+        PortId(self.specified_this_tick.len() + index)
+    }
+
+    /// The handle of the `index`th write port's enable input.
+    pub fn write_enable(&self, index: usize) -> PortId {
+        PortId(self.read_ports + index * WRITE_PORT_INPUTS)
+    }
+
+    /// The handle of the `index`th write port's address input.
+    pub fn write_address(&self, index: usize) -> PortId {
+        PortId(self.write_enable(index).0 + 1)
+    }
+
+    /// The handle of the `index`th write port's data input.
+    pub fn write_data(&self, index: usize) -> PortId {
+        PortId(self.write_enable(index).0 + 2)
+    }
+
+    /// Hard-wire the given register to zero, or stop any register being hard-wired if `None`.
+    ///
+    /// Fails if the register doesn't exist ([`DeviceError::DeviceSpecific`]).
+    pub fn set_zero_register(&mut self, register: Option<usize>) -> Result<(), DeviceError> {
+        if let Some(register) = register.filter(|register| *register >= self.registers.len()) {
+            return Err(DeviceError::DeviceSpecific {
+                message: format!("there is no register {register} to hard-wire to zero"),
+            });
+        }
+        self.zero_register = register;
+        Ok(())
+    }
+
+    /// Set what reads give for a register that is being written this tick.
+    pub fn set_read_during_write(&mut self, read_during_write: ReadDuringWrite) {
+        self.read_during_write = read_during_write;
+    }
+
+    /// Set whether this register file uses four-state logic, in which case registers read as all
+    /// [`LogicBit::X`](crate::device::value::LogicBit::X) rather than 0 until they are first
+    /// written.
+    ///
+    /// Regardless of this setting, an unknown write enable leaves X in any bit that would end up
+    /// different depending on whether the write happens.
+    pub fn set_four_state(&mut self, four_state: bool) {
+        self.four_state = four_state;
+    }
+
+    /// Get the width of the data ports.
+    fn width(&self) -> u32 {
+        self.ports[self.read_data(0).0].width
+    }
+
+    /// Get the value currently stored in the given register.
+    fn stored(&self, register: usize) -> PortValue {
+        match &self.registers[register] {
+            _ if self.zero_register == Some(register) => PortValue::ZERO,
+            Some(value) => value.clone(),
+            None if self.four_state => PortValue::x(self.width()),
+            None => PortValue::ZERO,
+        }
+    }
+
+    /// Get the value provided to the given input port this tick, if there is one.
+    fn provided(&self, port: PortId) -> Option<&PortValue> {
+        self.specified_this_tick[port.0].as_ref()
+    }
+
+    /// Get the value provided to the given input port this tick, failing with `message` if there
+    /// isn't one.
+    fn required(&self, port: PortId, message: &str) -> Result<&PortValue, DeviceError> {
+        self.provided(port).ok_or_else(|| DeviceError::MissingInput {
+            port: self.port_name(port),
+            message: message.to_owned(),
+        })
+    }
+
+    /// Work out where the given write port writes this tick, and with what, or `None` if it
+    /// definitely doesn't write.
+    ///
+    /// Only fails if inputs are missing, so this is safe to use when working out read data.
+    fn write_target(&self, index: usize)
+        -> Result<Option<(WriteTarget<'_>, &PortValue, &PortValue)>, DeviceError>
+    {
+        let enable = self.required(self.write_enable(index),
+            "every write enable must be provided every tick")?;
+        if enable.is_zero() {
+            return Ok(None);
+        }
+
+        let message = "write address and data must be provided when write enable is set";
+        let address = self.required(self.write_address(index), message)?;
+        let data = self.required(self.write_data(index), message)?;
+        let target = match decode_select(address, self.registers.len()) {
+            Ok(Some(register)) => WriteTarget::Register(register),
+            Ok(None) => WriteTarget::OutOfRange(address),
+            Err(()) => WriteTarget::Unknown,
+        };
+        Ok(Some((target, enable, data)))
+    }
+
+    /// Work out which register the given write port writes this tick, and with what, or `None` if
+    /// it definitely doesn't write.
+    ///
+    /// Fails if the write address doesn't pick out a register.
+    fn write(&self, index: usize) -> Result<Option<(usize, &PortValue, &PortValue)>, DeviceError> {
+        let Some((target, enable, data)) = self.write_target(index)? else {
+            return Ok(None);
+        };
+        match target {
+            WriteTarget::Register(register) => Ok(Some((register, enable, data))),
+            WriteTarget::OutOfRange(address) => Err(DeviceError::DeviceSpecific {
+                message: format!("cannot write to register {address}, as there are only {}",
+                    self.registers.len()),
+            }),
+            WriteTarget::Unknown => Err(DeviceError::DeviceSpecific {
+                message: "cannot write to an address with unknown bits".to_owned(),
+            }),
+        }
+    }
+
+    /// Work out what the given register will hold after this tick, from the values provided so
+    /// far.
+    ///
+    /// A write to a register that doesn't exist is ignored, and a write to an address with unknown
+    /// bits leaves the register all X, as it might be the one written.
+    fn next_value(&self, register: usize) -> Result<PortValue, DeviceError> {
+        let mut value = self.stored(register);
+        if self.zero_register == Some(register) {
+            return Ok(value);
+        }
+        for index in 0..self.write_ports {
+            let Some((target, enable, data)) = self.write_target(index)? else {
+                continue;
+            };
+            value = match target {
+                WriteTarget::Register(target) if target == register => match enable.is_known() {
+                    true => data.clone(),
+                    false => data.either(&value),
+                },
+                WriteTarget::Unknown => PortValue::x(self.width()),
+                _ => continue,
+            };
+        }
+        Ok(value)
+    }
+
+    /// Work out whether any write port might write the given register this tick, from the values
+    /// provided so far.
+    fn is_written(&self, register: usize) -> Result<bool, DeviceError> {
+        if self.zero_register == Some(register) {
+            return Ok(false);
+        }
+        for index in 0..self.write_ports {
+            match self.write_target(index)? {
+                Some((WriteTarget::Register(target), _, _)) if target == register =>
+                    return Ok(true),
+                Some((WriteTarget::Unknown, _, _)) => return Ok(true),
+                _ => {}
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Where a write port of a [`RegisterFile`] writes this tick.
+enum WriteTarget<'a> {
+    Register(usize),
+    /// The address, which is past the last register.
+    OutOfRange(&'a PortValue),
+    /// The address has unknown bits, so the write might go to any register.
+    Unknown,
+}
+
+impl Device for RegisterFile {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        let index = output.0.checked_sub(self.specified_this_tick.len())
+            .filter(|index| *index < self.read_ports)
+            .ok_or_else(|| output_port_error(&self.ports, output))?;
+        let mut deps = HashSet::from([self.read_address(index)]);
+        if self.read_during_write != ReadDuringWrite::OldValue {
+            deps.extend((self.read_ports..self.specified_this_tick.len()).map(PortId));
+        }
+        Ok(deps)
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        let index = port.0.checked_sub(self.specified_this_tick.len())
+            .filter(|index| *index < self.read_ports)
+            .ok_or_else(|| output_port_error(&self.ports, port))?;
+        let Some(address) = self.provided(self.read_address(index)) else {
+            return Ok(None);
+        };
+        let Ok(Some(register)) = decode_select(address, self.registers.len()) else {
+            return Ok(Some(PortValue::x(self.width())));
+        };
+        let value = match self.read_during_write {
+            ReadDuringWrite::OldValue => return Ok(Some(self.stored(register))),
+            ReadDuringWrite::NewValue => self.next_value(register),
+            ReadDuringWrite::Undefined => self.is_written(register).map(|written| match written {
+                true => PortValue::x(self.width()),
+                false => self.stored(register),
+            }),
+        };
+        // Bad write addresses are left for `check_tick` to report, so the only way this fails is
+        // if the write ports haven't all been provided yet
+        Ok(value.ok())
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick.iter_mut().for_each(|input| *input = None);
+    }
+
+    fn check_tick(&self) -> Result<(), DeviceError> {
+        for index in 0..self.write_ports {
+            self.write(index)?;
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) -> Result<(), DeviceError> {
+        let mut written = Vec::new();
+        for index in 0..self.write_ports {
+            if let Some((register, _, _)) = self.write(index)? {
+                written.push((register, self.next_value(register)?));
+            }
+        }
+        for (register, value) in written {
+            self.registers[register] = Some(value);
+        }
+        self.clear_port_values();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::{Controller, ControllerError};
+    use crate::device::debug::constant::Constant;
+    use crate::device::register_file::{ReadDuringWrite, RegisterFile};
+    use crate::device::{Device, DeviceError, PortId, PortValue};
+
+    /// Provide a write to the given write port, or disable it if `write` is `None`.
+    fn write(file: &mut RegisterFile, index: usize, write: Option<(u32, &str)>) {
+        let Some((address, data)) = write else {
+            file.provide_port_value(file.write_enable(index), PortValue::ZERO).unwrap();
+            return;
+        };
+        file.provide_port_value(file.write_enable(index), PortValue::from(1u32)).unwrap();
+        file.provide_port_value(file.write_address(index), PortValue::from(address)).unwrap();
+        file.provide_port_value(file.write_data(index), data.parse().unwrap()).unwrap();
+    }
+
+    /// Read the given register through the given read port.
+    fn read(file: &mut RegisterFile, index: usize, register: u32) -> Option<PortValue> {
+        file.provide_port_value(file.read_address(index), PortValue::from(register)).unwrap();
+        file.get_port_value(file.read_data(index)).unwrap()
+    }
+
+    #[test]
+    fn register_file_ports_are_laid_out_by_read_and_write_port() {
+        let file = RegisterFile::with_width(32, 2, 1, 64).unwrap();
+        let names: Vec<&str> = file.get_ports().iter().map(|port| port.name.as_str()).collect();
+        assert_eq!(names, ["ra0", "ra1", "we0", "wa0", "wd0", "rd0", "rd1"]);
+        assert_eq!(file.get_ports()[file.read_address(1).0].width, 5);
+        assert_eq!(file.get_ports()[file.write_data(0).0].width, 64);
+        assert_eq!(file.read_data(1), PortId(6));
+    }
+
+    #[test]
+    fn register_file_reads_every_port_independently() {
+        let mut file = RegisterFile::with_width(8, 3, 2, 8).unwrap();
+        write(&mut file, 0, Some((3, "0000_0011")));
+        write(&mut file, 1, Some((5, "0000_0101")));
+        file.tick().unwrap();
+
+        assert_eq!(read(&mut file, 0, 5), Some(PortValue::from(5u32)));
+        assert_eq!(read(&mut file, 1, 3), Some(PortValue::from(3u32)));
+        assert_eq!(read(&mut file, 2, 5), Some(PortValue::from(5u32)));
+    }
+
+    #[test]
+    fn register_file_reads_old_values_during_writes_by_default() {
+        let mut file = RegisterFile::with_width(4, 2, 1, 8).unwrap();
+        assert_eq!(file.get_output_dependencies(file.read_data(0)).unwrap().len(), 1);
+
+        write(&mut file, 0, Some((1, "0000_1111")));
+        assert_eq!(read(&mut file, 0, 1), Some(PortValue::ZERO));
+        file.tick().unwrap();
+        assert_eq!(read(&mut file, 0, 1), Some(PortValue::from(0xfu32)));
+    }
+
+    #[test]
+    fn register_file_can_read_new_values_during_writes() {
+        let mut file = RegisterFile::with_width(4, 2, 1, 8).unwrap();
+        file.set_read_during_write(ReadDuringWrite::NewValue);
+        assert_eq!(file.get_output_dependencies(file.read_data(0)).unwrap().len(), 4);
+
+        // Nothing can be read until it is known what is being written
+        assert_eq!(read(&mut file, 0, 1), None);
+        write(&mut file, 0, Some((1, "0000_1111")));
+        assert_eq!(file.get_port_value(file.read_data(0)), Ok(Some(PortValue::from(0xfu32))));
+        assert_eq!(read(&mut file, 1, 2), Some(PortValue::ZERO));
+    }
+
+    #[test]
+    fn register_file_can_leave_reads_during_writes_undefined() {
+        let mut file = RegisterFile::with_width(4, 2, 1, 8).unwrap();
+        file.set_read_during_write(ReadDuringWrite::Undefined);
+        assert_eq!(file.get_output_dependencies(file.read_data(0)).unwrap().len(), 4);
+
+        write(&mut file, 0, Some((1, "0000_1111")));
+        assert_eq!(read(&mut file, 0, 1), Some(PortValue::x(8)));
+        assert_eq!(read(&mut file, 1, 2), Some(PortValue::ZERO));
+        file.tick().unwrap();
+        write(&mut file, 0, None);
+        assert_eq!(read(&mut file, 0, 1), Some(PortValue::from(0xfu32)));
+    }
+
+    #[test]
+    fn register_file_highest_write_port_wins() {
+        let mut file = RegisterFile::with_width(4, 1, 3, 8).unwrap();
+        write(&mut file, 0, Some((2, "0000_0001")));
+        write(&mut file, 1, Some((2, "0000_0010")));
+        write(&mut file, 2, None);
+        file.tick().unwrap();
+        assert_eq!(read(&mut file, 0, 2), Some(PortValue::from(2u32)));
+    }
+
+    #[test]
+    fn register_file_zero_register_ignores_writes() {
+        let mut file = RegisterFile::with_width(32, 2, 1, 32).unwrap();
+        file.set_zero_register(Some(0)).unwrap();
+        file.set_read_during_write(ReadDuringWrite::NewValue);
+        // Reads of the zero register don't need to wait for the write ports
+        assert_eq!(read(&mut file, 0, 0), Some(PortValue::ZERO));
+        write(&mut file, 0, Some((0, "1111")));
+        file.tick().unwrap();
+        assert_eq!(read(&mut file, 0, 0), Some(PortValue::ZERO));
+
+        assert!(matches!(
+            file.set_zero_register(Some(32)),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+    }
+
+    #[test]
+    fn register_file_reads_missing_and_unknown_registers_as_x() {
+        let mut file = RegisterFile::with_width(3, 1, 1, 4).unwrap();
+        assert_eq!(read(&mut file, 0, 3), Some(PortValue::x(4)));
+        file.clear_port_values();
+        file.provide_port_value(file.read_address(0), "x0".parse().unwrap()).unwrap();
+        assert_eq!(file.get_port_value(file.read_data(0)), Ok(Some(PortValue::x(4))));
+
+        let mut file = RegisterFile::with_width(4, 1, 1, 4).unwrap();
+        file.set_four_state(true);
+        assert_eq!(read(&mut file, 0, 2), Some(PortValue::x(4)));
+    }
+
+    #[test]
+    fn register_file_checks_write_ports() {
+        let mut file = RegisterFile::with_width(3, 1, 2, 4).unwrap();
+        write(&mut file, 0, None);
+        assert!(matches!(
+            file.check_tick(),
+            Err(DeviceError::MissingInput { port, .. }) if port == "we1"
+        ));
+
+        file.provide_port_value(file.write_enable(1), PortValue::from(1u32)).unwrap();
+        assert!(matches!(
+            file.check_tick(),
+            Err(DeviceError::MissingInput { port, .. }) if port == "wa1"
+        ));
+
+        file.clear_port_values();
+        write(&mut file, 0, Some((3, "0001")));
+        write(&mut file, 1, None);
+        assert!(matches!(file.tick(), Err(DeviceError::DeviceSpecific { .. })));
+    }
+
+    #[test]
+    fn register_file_reads_leave_bad_write_addresses_to_tick() {
+        for read_during_write in [ReadDuringWrite::NewValue, ReadDuringWrite::Undefined] {
+            let mut file = RegisterFile::with_width(3, 1, 1, 4).unwrap();
+            file.set_read_during_write(read_during_write);
+            // Writing past the last register can't change what is read
+            write(&mut file, 0, Some((3, "0001")));
+            assert_eq!(read(&mut file, 0, 2), Some(PortValue::ZERO), "{read_during_write:?}");
+            assert!(matches!(file.check_tick(), Err(DeviceError::DeviceSpecific { .. })));
+
+            // Writing an unknown address might change anything
+            file.clear_port_values();
+            file.provide_port_value(file.write_enable(0), PortValue::from(1u32)).unwrap();
+            file.provide_port_value(file.write_address(0), "x0".parse().unwrap()).unwrap();
+            file.provide_port_value(file.write_data(0), "0001".parse().unwrap()).unwrap();
+            assert_eq!(read(&mut file, 0, 2), Some(PortValue::x(4)), "{read_during_write:?}");
+            assert!(matches!(file.check_tick(), Err(DeviceError::DeviceSpecific { .. })));
+        }
+
+        // Through a controller, the bad write is an error from the tick
+        let mut controller = Controller::new();
+        let mut file = RegisterFile::with_width(3, 1, 1, 4).unwrap();
+        file.set_read_during_write(ReadDuringWrite::NewValue);
+        controller.add_device("File".to_owned(), Box::new(file)).unwrap();
+        for (name, width, value) in [("One", 1, 1u32), ("Three", 2, 3), ("Data", 4, 5)] {
+            let constant = Constant::with_width("qq".to_owned(), width, PortValue::from(value))
+                .unwrap();
+            controller.add_device(name.to_owned(), Box::new(constant)).unwrap();
+        }
+        let connections = [("One", "File", "we0"), ("Three", "File", "wa0"),
+            ("Data", "File", "wd0"), ("Three", "File", "ra0")];
+        for (from, to, to_port) in connections {
+            controller.add_connection(
+                &from.to_owned(), &"qq".to_owned(), &to.to_owned(), &to_port.to_owned(),
+            ).unwrap();
+        }
+        assert!(matches!(
+            controller.tick(),
+            Err(ControllerError::Device { device, source: DeviceError::DeviceSpecific { .. } })
+                if device == "File"
+        ));
+    }
+
+    #[test]
+    fn register_file_keeps_only_certain_bits_with_unknown_enable() {
+        let mut file = RegisterFile::with_width(2, 1, 1, 4).unwrap();
+        write(&mut file, 0, Some((1, "0011")));
+        file.tick().unwrap();
+
+        file.provide_port_value(file.write_enable(0), "x".parse().unwrap()).unwrap();
+        file.provide_port_value(file.write_address(0), PortValue::from(1u32)).unwrap();
+        file.provide_port_value(file.write_data(0), "0101".parse().unwrap()).unwrap();
+        file.tick().unwrap();
+        assert_eq!(read(&mut file, 0, 1), Some("0xx1".parse().unwrap()));
+
+        // Floating data bits don't leave the old value known either
+        file.clear_port_values();
+        file.provide_port_value(file.write_enable(0), "x".parse().unwrap()).unwrap();
+        file.provide_port_value(file.write_address(0), PortValue::from(0u32)).unwrap();
+        file.provide_port_value(file.write_data(0), "zz00".parse().unwrap()).unwrap();
+        file.tick().unwrap();
+        assert_eq!(read(&mut file, 0, 0), Some("xx00".parse().unwrap()));
+    }
+
+    #[test]
+    fn register_file_count_is_bounded() {
+        for (registers, read_ports, write_ports) in [(0, 2, 1), (4097, 2, 1), (4, 0, 1), (4, 2, 0)]
+        {
+            assert!(matches!(
+                RegisterFile::new(registers, read_ports, write_ports),
+                Err(DeviceError::DeviceSpecific { .. })
+            ));
+        }
+        assert!(matches!(
+            RegisterFile::with_width(4, 2, 1, 0),
+            Err(DeviceError::InvalidWidth { width: 0 })
+        ));
+    }
+
+    #[test]
+    fn register_file_rejects_bad_ports() {
+        let mut file = RegisterFile::new(4, 2, 1).unwrap();
+        assert_eq!(
+            file.provide_port_value(file.read_data(0), PortValue::ZERO),
+            Err(DeviceError::NotAnInputPort { port: "rd0".to_owned() }),
+        );
+        assert_eq!(
+            file.get_port_value(file.write_data(0)),
+            Err(DeviceError::NotAnOutputPort { port: "wd0".to_owned() }),
+        );
+        assert_eq!(
+            file.get_output_dependencies(PortId(99)),
+            Err(DeviceError::UnknownPort { port: "#99".to_owned() }),
+        );
+    }
+
+    #[test]
+    fn register_file_write_through_feedback_is_a_loop() {
+        // With new values read during writes, feeding read data back to write data is a
+        // combinational loop, whereas with old values it is fine
+        for (read_during_write, allowed) in [(ReadDuringWrite::OldValue, true),
+            (ReadDuringWrite::NewValue, false)]
+        {
+            let mut controller = Controller::new();
+            let mut file = RegisterFile::with_width(2, 1, 1, 8).unwrap();
+            file.set_read_during_write(read_during_write);
+            controller.add_device("File".to_owned(), Box::new(file)).unwrap();
+            let one = Constant::with_width("qq".to_owned(), 1, PortValue::from(1u32)).unwrap();
+            controller.add_device("One".to_owned(), Box::new(one)).unwrap();
+
+            let connections = [("One", "qq", "File", "we0"), ("One", "qq", "File", "wa0"),
+                ("One", "qq", "File", "ra0")];
+            for (from, from_port, to, to_port) in connections {
+                controller.add_connection(
+                    &from.to_owned(), &from_port.to_owned(),
+                    &to.to_owned(), &to_port.to_owned(),
+                ).unwrap();
+            }
+            let feedback = controller.add_connection(
+                &"File".to_owned(), &"rd0".to_owned(), &"File".to_owned(), &"wd0".to_owned(),
+            );
+            assert_eq!(feedback.is_ok(), allowed, "{read_during_write:?}");
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use crate::device::dump::Dump;
+use crate::device::image::{Image, WordLayout};
+use crate::device::{
+    check_width, output_port_error, provide_all_into, provide_into, Device, DeviceError,
+    PortDescriptor, PortId, PortValue, DEFAULT_PORT_WIDTH,
+};
+
+/// A read-only memory, whose contents are loaded from an [`Image`] before the circuit runs.
+///
+/// Its ports are named like the read side of a [`Memory`](crate::device::memory::Memory), so one
+/// can stand in for the other. Reading an address that wasn't loaded gives 0, or all X in
+/// four-state mode; reading an address with unknown bits gives all X.
+pub struct Rom {
+    data: HashMap<PortValue, PortValue>,
+    specified_this_tick: [Option<PortValue>; 1],
+    ports: [PortDescriptor; 2],
+
+    /// Whether addresses that weren't loaded read as all X rather than 0.
+    four_state: bool,
+}
+
+impl Rom {
+    /// Read address
+    pub const RA: PortId = PortId(0);
+    /// Read value
+    pub const RV: PortId = PortId(1);
+
+    /// Create an empty ROM with [`DEFAULT_PORT_WIDTH`]-bit addresses and data.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Rom {
+        Rom::with_widths(DEFAULT_PORT_WIDTH, DEFAULT_PORT_WIDTH)
+            .expect("The default port width should always be a valid width")
+    }
+
+    /// Create an empty ROM whose address port (`ra`) is `address_width` bits wide, and whose data
+    /// port (`rv`) is `data_width` bits wide.
+    pub fn with_widths(address_width: u32, data_width: u32) -> Result<Rom, DeviceError> {
+        check_width(address_width)?;
+        check_width(data_width)?;
+
+        // The order of these must match the port handles above
+        let ports = [
+            PortDescriptor::input("ra", address_width),
+            PortDescriptor::output("rv", data_width),
+        ];
+
+        Ok(Rom {
+            data: HashMap::new(),
+            specified_this_tick: Default::default(),
+            ports,
+            four_state: false,
+        })
+    }
+
+    /// Store the contents of an image, grouping its bytes into words as given by `layout`. Word
+    /// `n` of the image is stored at address `n`, replacing anything already there.
+    ///
+    /// Fails, without storing anything, if a word's address or value is too wide for this ROM
+    /// ([`DeviceError::DeviceSpecific`]).
+    pub fn load(&mut self, image: &Image, layout: WordLayout) -> Result<(), DeviceError> {
+        let words = image.words_to_load(layout, self.ports[Self::RA.0].width, self.data_width())?;
+        self.data.extend(words);
+        Ok(())
+    }
+
+    /// Set whether this ROM uses four-state logic, in which case reading an address that wasn't
+    /// loaded gives all [`LogicBit::X`](crate::device::value::LogicBit::X) rather than 0.
+    pub fn set_four_state(&mut self, four_state: bool) {
+        self.four_state = four_state;
+    }
+
+    /// Take a snapshot of the words stored at the given addresses. Only addresses that have been
+    /// loaded are included.
+    pub fn dump(&self, addresses: Range<u64>) -> Dump {
+        let mut dump = Dump::new(self.data_width());
+        for (address, value) in &self.data {
+            if let Some(address) = address.to_u64().filter(|address| addresses.contains(address)) {
+                dump.insert(address, value.clone());
+            }
+        }
+        dump
+    }
+
+    /// Get the width of the data port.
+    fn data_width(&self) -> u32 {
+        self.ports[Self::RV.0].width
+    }
+
+}
+
+impl Device for Rom {
+    fn get_ports(&self) -> &[PortDescriptor] {
+        &self.ports
+    }
+
+    fn get_output_dependencies(&self, output: PortId) -> Result<HashSet<PortId>, DeviceError> {
+        if output != Self::RV {
+            return Err(output_port_error(&self.ports, output));
+        }
+        Ok(HashSet::from([Self::RA]))
+    }
+
+    fn provide_port_value(&mut self, port: PortId, value: PortValue)
+        -> Result<(), DeviceError>
+    {
+        provide_into(&self.ports, &mut self.specified_this_tick, port, value)
+    }
+
+    fn provide_port_values(&mut self, values: HashMap<PortId, PortValue>)
+        -> Result<(), DeviceError>
+    {
+        provide_all_into(&self.ports, &mut self.specified_this_tick, values)
+    }
+
+    fn get_port_value(&self, port: PortId) -> Result<Option<PortValue>, DeviceError> {
+        if port != Self::RV {
+            return Err(output_port_error(&self.ports, port));
+        }
+
+        let Some(address) = &self.specified_this_tick[Self::RA.0] else {
+            return Ok(None);
+        };
+        let value = match (address.is_known(), self.data.get(address)) {
+            (false, _) => PortValue::x(self.data_width()),
+            (true, Some(value)) => value.clone(),
+            (true, None) if self.four_state => PortValue::x(self.data_width()),
+            (true, None) => PortValue::ZERO,
+        };
+        Ok(Some(value))
+    }
+
+    fn clear_port_values(&mut self) {
+        self.specified_this_tick = Default::default();
+    }
+
+    fn check_tick(&self) -> Result<(), DeviceError> {
+        // Nothing can be written, so there is nothing to do on a tick
+        Ok(())
+    }
+
+    fn tick(&mut self) -> Result<(), DeviceError> {
+        self.clear_port_values();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::Controller;
+    use crate::device::debug::sequencer::Sequencer;
+    use crate::device::image::{Endianness, Image, WordLayout};
+    use crate::device::rom::Rom;
+    use crate::device::{Device, DeviceError, PortValue};
+
+    /// Read the given address of a ROM.
+    fn read(rom: &mut Rom, address: PortValue) -> Option<PortValue> {
+        rom.clear_port_values();
+        rom.provide_port_value(Rom::RA, address).unwrap();
+        rom.get_port_value(Rom::RV).unwrap()
+    }
+
+    #[test]
+    fn rom_reads_loaded_words() {
+        let image = Image::from_intel_hex(":0400000078563412E8\n:00000001FF\n").unwrap();
+        let mut rom = Rom::with_widths(8, 32).unwrap();
+        rom.load(&image, WordLayout::new(4, Endianness::Little)).unwrap();
+        assert_eq!(read(&mut rom, PortValue::from(0u32)), Some(PortValue::from(0x1234_5678u32)));
+        assert_eq!(read(&mut rom, PortValue::from(1u32)), Some(PortValue::ZERO));
+        assert_eq!(read(&mut rom, PortValue::x(8)), Some(PortValue::x(32)));
+
+        rom.set_four_state(true);
+        assert_eq!(read(&mut rom, PortValue::from(1u32)), Some(PortValue::x(32)));
+    }
+
+    #[test]
+    fn rom_cannot_be_written() {
+        let mut rom = Rom::new();
+        assert_eq!(rom.get_ports().len(), 2);
+        assert_eq!(
+            rom.provide_port_value(Rom::RV, PortValue::ZERO),
+            Err(DeviceError::NotAnInputPort { port: "rv".to_owned() }),
+        );
+        assert_eq!(
+            rom.get_port_value(Rom::RA),
+            Err(DeviceError::NotAnOutputPort { port: "ra".to_owned() }),
+        );
+        assert!(rom.check_tick().is_ok());
+    }
+
+    #[test]
+    fn rom_rejects_images_that_dont_fit() {
+        let image = Image::from_binary(&[0xff, 0x01], 0);
+        let mut rom = Rom::with_widths(8, 4).unwrap();
+        assert!(matches!(
+            rom.load(&image, WordLayout::new(1, Endianness::Little)),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+        assert!(matches!(
+            rom.load(&image, WordLayout::new(0, Endianness::Little)),
+            Err(DeviceError::DeviceSpecific { .. })
+        ));
+        assert!(matches!(Rom::with_widths(0, 8), Err(DeviceError::InvalidWidth { width: 0 })));
+    }
+
+    #[test]
+    fn rom_feeds_a_circuit_from_firmware() {
+        let firmware = "S1070000DEADBEEFC0\nS9030000FC\n";
+        let mut rom = Rom::with_widths(4, 16).unwrap();
+        rom.load(&Image::from_srecord(firmware).unwrap(), WordLayout::new(2, Endianness::Big))
+            .unwrap();
+
+        let mut controller = Controller::new();
+        controller.add_device("Rom".to_owned(), Box::new(rom)).unwrap();
+        let addresses = [PortValue::from(0u32), PortValue::from(1u32)];
+        let pc = Sequencer::with_width("pc".to_owned(), 4, &addresses).unwrap();
+        controller.add_device("Pc".to_owned(), Box::new(pc)).unwrap();
+        controller.add_connection(&"Pc".to_owned(), &"pc".to_owned(),
+            &"Rom".to_owned(), &"ra".to_owned()).unwrap();
+
+        let state = controller.tick().unwrap();
+        assert_eq!(state.value_by_name("Rom", "rv"), Some(&PortValue::from(0xdeadu32)));
+        let state = controller.tick().unwrap();
+        assert_eq!(state.value_by_name("Rom", "rv"), Some(&PortValue::from(0xbeefu32)));
+    }
+}
@@ -0,0 +1,1039 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Add, BitAnd, BitOr, BitXor, Mul, Shl, Shr};
+use std::str::FromStr;
+
+/// Number of bits in each limb of a wide value.
+const LIMB_BITS: u32 = u64::BITS;
+
+/// The state of a single bit of a [`PortValue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LogicBit {
+    Zero,
+    One,
+    /// Unknown: uninitialised, or driven to conflicting values.
+    X,
+    /// High impedance: not driven at all.
+    Z,
+}
+
+/// The value carried by a port: an unsigned integer of any size, where each bit may also be
+/// unknown ([`LogicBit::X`]) or undriven ([`LogicBit::Z`]).
+///
+/// A value doesn't know its own width - that belongs to the port carrying it (see
+/// [`PortDescriptor`](crate::device::PortDescriptor)) - so operations whose result depends on a
+/// width, such as [`PortValue::wrapping_add`] or [`PortValue::not`], take one as an argument.
+///
+/// Values that fit in 64 bits are stored inline, so the common case of narrow ports never
+/// allocates. Anything wider is stored as a boxed slice of little-endian 64-bit limbs.
+///
+/// Four-state logic is optional: values built from integers only ever have `0` and `1` bits, and
+/// carry nothing extra. Unknown bits only appear when something asks for them, for example a
+/// [`Controller`](crate::controller::Controller) created with
+/// [`Controller::new_four_state`](crate::controller::Controller::new_four_state). Operations
+/// propagate them like an HDL simulator would: bitwise operations are resolved bit by bit (so
+/// `0 & X` is still `0`), whereas arithmetic with any unknown input bit gives an entirely unknown
+/// result.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PortValue {
+    /// The value of each bit. For unknown bits, this is 1 for X and 0 for Z.
+    bits: Bits,
+
+    /// Which bits are unknown (X or Z), or `None` if every bit is known.
+    unknown: Option<Box<Bits>>,
+}
+
+/// A plane of bits making up part of a [`PortValue`]: an unsigned integer of any size.
+///
+/// This is always kept normalised, so that every number has exactly one representation and the
+/// derived equality and hashing are numeric: `Wide` is only used for values that don't fit in a
+/// `u64`, and never has trailing zero limbs.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Bits {
+    Narrow(u64),
+    Wide(Box<[u64]>),
+}
+
+impl Bits {
+    const ZERO: Bits = Bits::Narrow(0);
+
+    /// Build a plane from little-endian 64-bit limbs, normalising the representation.
+    fn from_limb_vec(mut limbs: Vec<u64>) -> Bits {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        match limbs.len() {
+            0 => Bits::ZERO,
+            1 => Bits::Narrow(limbs[0]),
+            _ => Bits::Wide(limbs.into_boxed_slice()),
+        }
+    }
+
+    fn from_u128(value: u128) -> Bits {
+        Bits::from_limb_vec(vec![value as u64, (value >> LIMB_BITS) as u64])
+    }
+
+    fn limbs(&self) -> &[u64] {
+        match self {
+            Bits::Narrow(value) => std::slice::from_ref(value),
+            Bits::Wide(limbs) => limbs,
+        }
+    }
+
+    fn limb(&self, idx: usize) -> u64 {
+        self.limbs().get(idx).copied().unwrap_or(0)
+    }
+
+    fn is_zero(&self) -> bool {
+        matches!(self, Bits::Narrow(0))
+    }
+
+    fn bit_length(&self) -> u32 {
+        let limbs = self.limbs();
+        let top = limbs[limbs.len() - 1];
+        (limbs.len() as u32 - 1) * LIMB_BITS + (LIMB_BITS - top.leading_zeros())
+    }
+
+    fn bit(&self, index: u32) -> bool {
+        (self.limb((index / LIMB_BITS) as usize) >> (index % LIMB_BITS)) & 1 == 1
+    }
+
+    fn all_ones(width: u32) -> Bits {
+        if width <= LIMB_BITS {
+            return Bits::Narrow(u64::MAX).mask(width);
+        }
+        let mut limbs = vec![u64::MAX; width.div_ceil(LIMB_BITS) as usize];
+        if !width.is_multiple_of(LIMB_BITS) {
+            *limbs.last_mut().unwrap() = (1 << (width % LIMB_BITS)) - 1;
+        }
+        Bits::from_limb_vec(limbs)
+    }
+
+    fn mask(&self, width: u32) -> Bits {
+        match self {
+            Bits::Narrow(value) if width >= LIMB_BITS => Bits::Narrow(*value),
+            Bits::Narrow(value) => Bits::Narrow(value & ((1 << width) - 1)),
+            Bits::Wide(_) if width >= self.bit_length() => self.clone(),
+            Bits::Wide(limbs) => {
+                let mut limbs = limbs[..width.div_ceil(LIMB_BITS) as usize].to_vec();
+                if !width.is_multiple_of(LIMB_BITS) {
+                    *limbs.last_mut().unwrap() &= (1 << (width % LIMB_BITS)) - 1;
+                }
+                Bits::from_limb_vec(limbs)
+            }
+        }
+    }
+
+    /// Combine two planes limb by limb. `op(0, 0)` must be 0.
+    fn zip(&self, other: &Bits, op: impl Fn(u64, u64) -> u64) -> Bits {
+        if let (Bits::Narrow(lhs), Bits::Narrow(rhs)) = (self, other) {
+            return Bits::Narrow(op(*lhs, *rhs));
+        }
+        let len = self.limbs().len().max(other.limbs().len());
+        Bits::from_limb_vec((0..len).map(|idx| op(self.limb(idx), other.limb(idx))).collect())
+    }
+
+    fn and(&self, other: &Bits) -> Bits {
+        self.zip(other, |lhs, rhs| lhs & rhs)
+    }
+
+    fn or(&self, other: &Bits) -> Bits {
+        self.zip(other, |lhs, rhs| lhs | rhs)
+    }
+
+    fn xor(&self, other: &Bits) -> Bits {
+        self.zip(other, |lhs, rhs| lhs ^ rhs)
+    }
+
+    /// The bits set in this plane but not in `other`.
+    fn and_not(&self, other: &Bits) -> Bits {
+        self.zip(other, |lhs, rhs| lhs & !rhs)
+    }
+
+    fn add(&self, other: &Bits) -> Bits {
+        if let (Bits::Narrow(lhs), Bits::Narrow(rhs)) = (self, other) {
+            return Bits::from_u128(*lhs as u128 + *rhs as u128);
+        }
+        let len = self.limbs().len().max(other.limbs().len());
+        let mut limbs = Vec::with_capacity(len + 1);
+        let mut carry = false;
+        for idx in 0..len {
+            let (sum, carry_a) = self.limb(idx).overflowing_add(other.limb(idx));
+            let (sum, carry_b) = sum.overflowing_add(carry as u64);
+            limbs.push(sum);
+            carry = carry_a || carry_b;
+        }
+        limbs.push(carry as u64);
+        Bits::from_limb_vec(limbs)
+    }
+
+    fn mul(&self, other: &Bits) -> Bits {
+        if let (Bits::Narrow(lhs), Bits::Narrow(rhs)) = (self, other) {
+            return Bits::from_u128(*lhs as u128 * *rhs as u128);
+        }
+        // Schoolbook long multiplication
+        let (lhs, rhs) = (self.limbs(), other.limbs());
+        let mut limbs = vec![0u64; lhs.len() + rhs.len()];
+        for (i, l) in lhs.iter().enumerate() {
+            let mut carry: u128 = 0;
+            for (j, r) in rhs.iter().enumerate() {
+                let product = *l as u128 * *r as u128 + limbs[i + j] as u128 + carry;
+                limbs[i + j] = product as u64;
+                carry = product >> LIMB_BITS;
+            }
+            limbs[i + rhs.len()] = carry as u64;
+        }
+        Bits::from_limb_vec(limbs)
+    }
+
+    fn shl(&self, amount: u32) -> Bits {
+        if let Bits::Narrow(value) = self {
+            if amount < LIMB_BITS && value.leading_zeros() >= amount {
+                return Bits::Narrow(value << amount);
+            }
+        }
+        if self.is_zero() {
+            return Bits::ZERO;
+        }
+        let (limb_shift, bit_shift) = ((amount / LIMB_BITS) as usize, amount % LIMB_BITS);
+        let mut limbs = vec![0u64; limb_shift];
+        let mut carry = 0;
+        for limb in self.limbs() {
+            limbs.push((limb << bit_shift) | carry);
+            carry = match bit_shift {
+                0 => 0,
+                _ => limb >> (LIMB_BITS - bit_shift),
+            };
+        }
+        limbs.push(carry);
+        Bits::from_limb_vec(limbs)
+    }
+
+    fn shr(&self, amount: u32) -> Bits {
+        if let Bits::Narrow(value) = self {
+            return Bits::Narrow(value.checked_shr(amount).unwrap_or(0));
+        }
+        let (limb_shift, bit_shift) = ((amount / LIMB_BITS) as usize, amount % LIMB_BITS);
+        let source = self.limbs().get(limb_shift..).unwrap_or(&[]);
+        let limbs = (0..source.len())
+            .map(|idx| {
+                let high = match bit_shift {
+                    0 => 0,
+                    _ => source.get(idx + 1).copied().unwrap_or(0) << (LIMB_BITS - bit_shift),
+                };
+                (source[idx] >> bit_shift) | high
+            })
+            .collect();
+        Bits::from_limb_vec(limbs)
+    }
+
+    fn rotate_left(&self, amount: u32, width: u32) -> Bits {
+        if width == 0 {
+            return Bits::ZERO;
+        }
+        let value = self.mask(width);
+        let amount = amount % width;
+        value.shl(amount).or(&value.shr(width - amount)).mask(width)
+    }
+
+    fn sign_extend(&self, from_width: u32, to_width: u32) -> Bits {
+        let value = self.mask(from_width);
+        match from_width > 0 && value.bit(from_width - 1) {
+            false => value,
+            true => value.or(&Bits::all_ones(to_width).shr(from_width).shl(from_width)),
+        }
+    }
+
+    /// Divide by a small divisor, returning the quotient and remainder.
+    fn div_rem_u64(&self, divisor: u64) -> (Bits, u64) {
+        let mut quotient = vec![0; self.limbs().len()];
+        let mut remainder: u128 = 0;
+        for (idx, limb) in self.limbs().iter().enumerate().rev() {
+            let dividend = (remainder << LIMB_BITS) | *limb as u128;
+            quotient[idx] = (dividend / divisor as u128) as u64;
+            remainder = dividend % divisor as u128;
+        }
+        (Bits::from_limb_vec(quotient), remainder as u64)
+    }
+}
+
+impl Ord for Bits {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Bits::Narrow(lhs), Bits::Narrow(rhs)) => lhs.cmp(rhs),
+            // Normalised, so more limbs means bigger
+            _ => self.limbs().len().cmp(&other.limbs().len())
+                .then_with(|| self.limbs().iter().rev().cmp(other.limbs().iter().rev())),
+        }
+    }
+}
+
+impl PartialOrd for Bits {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PortValue {
+    /// The value zero.
+    pub const ZERO: PortValue = PortValue { bits: Bits::ZERO, unknown: None };
+
+    /// Build a value from its two planes, normalising the representation.
+    fn from_planes(bits: Bits, unknown: Bits) -> PortValue {
+        match unknown.is_zero() {
+            true => PortValue { bits, unknown: None },
+            false => PortValue { bits, unknown: Some(Box::new(unknown)) },
+        }
+    }
+
+    /// Build a value from little-endian 64-bit limbs: `limbs[0]` holds the lowest 64 bits.
+    pub fn from_limbs(limbs: &[u64]) -> PortValue {
+        PortValue { bits: Bits::from_limb_vec(limbs.to_vec()), unknown: None }
+    }
+
+    /// Build a value from the state of each of its bits, lowest bit first.
+    pub fn from_states(states: &[LogicBit]) -> PortValue {
+        let mut limbs = vec![0u64; states.len().div_ceil(LIMB_BITS as usize)];
+        let mut unknown = limbs.clone();
+        for (idx, state) in states.iter().enumerate() {
+            let (limb, bit) = (idx / LIMB_BITS as usize, idx % LIMB_BITS as usize);
+            if matches!(state, LogicBit::One | LogicBit::X) {
+                limbs[limb] |= 1 << bit;
+            }
+            if matches!(state, LogicBit::X | LogicBit::Z) {
+                unknown[limb] |= 1 << bit;
+            }
+        }
+        PortValue::from_planes(Bits::from_limb_vec(limbs), Bits::from_limb_vec(unknown))
+    }
+
+    /// A value whose lowest `width` bits are all [`LogicBit::X`].
+    pub fn x(width: u32) -> PortValue {
+        PortValue::from_planes(Bits::all_ones(width), Bits::all_ones(width))
+    }
+
+    /// A value whose lowest `width` bits are all [`LogicBit::Z`].
+    pub fn z(width: u32) -> PortValue {
+        PortValue::from_planes(Bits::ZERO, Bits::all_ones(width))
+    }
+
+    /// Get the little-endian 64-bit limbs of this value. Zero has a single zero limb.
+    ///
+    /// Bits that aren't known are 1 for [`LogicBit::X`] and 0 for [`LogicBit::Z`].
+    pub fn limbs(&self) -> &[u64] {
+        self.bits.limbs()
+    }
+
+    /// Get this value as a `u64`, if every bit is known and it fits in one.
+    pub fn to_u64(&self) -> Option<u64> {
+        match (&self.bits, &self.unknown) {
+            (Bits::Narrow(value), None) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get the lowest 64 bits of this value, discarding everything above them. Bits that aren't
+    /// known are treated as in [`PortValue::limbs`].
+    pub fn low_u64(&self) -> u64 {
+        self.bits.limbs()[0]
+    }
+
+    /// Whether this value is known to be zero.
+    pub fn is_zero(&self) -> bool {
+        self.bits.is_zero() && self.unknown.is_none()
+    }
+
+    /// Whether every bit of this value is a known `0` or `1`.
+    pub fn is_known(&self) -> bool {
+        self.unknown.is_none()
+    }
+
+    /// Whether any bit of this value is [`LogicBit::X`].
+    pub fn has_x(&self) -> bool {
+        !self.x_mask().is_zero()
+    }
+
+    /// Whether any bit of this value is [`LogicBit::Z`].
+    pub fn has_z(&self) -> bool {
+        !self.z_mask().is_zero()
+    }
+
+    /// Get a value with a 1 in the position of every [`LogicBit::X`] bit in this value.
+    pub fn x_mask(&self) -> PortValue {
+        PortValue { bits: self.unknown().and(&self.bits), unknown: None }
+    }
+
+    /// Get a value with a 1 in the position of every [`LogicBit::Z`] bit in this value.
+    pub fn z_mask(&self) -> PortValue {
+        PortValue { bits: self.unknown().and_not(&self.bits), unknown: None }
+    }
+
+    /// Get the unknown plane, which is zero if every bit is known.
+    fn unknown(&self) -> &Bits {
+        self.unknown.as_deref().unwrap_or(&Bits::ZERO)
+    }
+
+    /// Whether any of the lowest `width` bits of this value are unknown.
+    fn has_unknown_within(&self, width: u32) -> bool {
+        !self.unknown().mask(width).is_zero()
+    }
+
+    /// Apply the same operation to both planes of this value.
+    fn map_planes(&self, op: impl Fn(&Bits) -> Bits) -> PortValue {
+        match &self.unknown {
+            None => PortValue { bits: op(&self.bits), unknown: None },
+            Some(unknown) => PortValue::from_planes(op(&self.bits), op(unknown)),
+        }
+    }
+
+    /// Apply the same operation to the matching planes of this value and `other`.
+    fn zip_planes(&self, other: &PortValue, op: impl Fn(&Bits, &Bits) -> Bits) -> PortValue {
+        let bits = op(&self.bits, &other.bits);
+        match (&self.unknown, &other.unknown) {
+            (None, None) => PortValue { bits, unknown: None },
+            _ => PortValue::from_planes(bits, op(self.unknown(), other.unknown())),
+        }
+    }
+
+    /// Get the number of bits needed to represent this value (zero for zero), including any
+    /// unknown bits.
+    pub fn bit_length(&self) -> u32 {
+        self.bits.bit_length().max(self.unknown().bit_length())
+    }
+
+    /// Get the state of a single bit of this value.
+    pub fn state(&self, index: u32) -> LogicBit {
+        match (self.unknown().bit(index), self.bits.bit(index)) {
+            (false, false) => LogicBit::Zero,
+            (false, true) => LogicBit::One,
+            (true, true) => LogicBit::X,
+            (true, false) => LogicBit::Z,
+        }
+    }
+
+    /// Whether a single bit of this value is known to be `1`.
+    pub fn bit(&self, index: u32) -> bool {
+        self.state(index) == LogicBit::One
+    }
+
+    /// The largest value that fits in `width` bits, i.e. `width` one bits.
+    pub fn all_ones(width: u32) -> PortValue {
+        PortValue { bits: Bits::all_ones(width), unknown: None }
+    }
+
+    /// Mask this value down to its lowest `width` bits, discarding everything above them.
+    pub fn mask(&self, width: u32) -> PortValue {
+        self.map_planes(|plane| plane.mask(width))
+    }
+
+    /// Get the `width` bits of this value starting at bit `low`, shifted down to bit zero.
+    pub fn slice(&self, low: u32, width: u32) -> PortValue {
+        self.map_planes(|plane| plane.shr(low).mask(width))
+    }
+
+    /// Replace the `width` bits of this value starting at bit `low` with the lowest `width` bits of
+    /// `value`.
+    pub fn with_slice(&self, low: u32, width: u32, value: &PortValue) -> PortValue {
+        self.zip_planes(value, |plane, value| {
+            let above = plane.shr(low + width).shl(low + width);
+            plane.mask(low).or(&above).or(&value.mask(width).shl(low))
+        })
+    }
+
+    /// Concatenate values, each of the given width, into one. The first value ends up in the
+    /// lowest bits.
+    pub fn concat<'a>(parts: impl IntoIterator<Item = (&'a PortValue, u32)>) -> PortValue {
+        let mut result = PortValue::ZERO;
+        let mut low = 0;
+        for (value, width) in parts {
+            result = result.with_slice(low, width, value);
+            low += width;
+        }
+        result
+    }
+
+    /// Resolve two values driven onto the same wire, as a tri-state bus would: a
+    /// [`LogicBit::Z`] bit gives way to whatever the other value drives, and bits driven to
+    /// different values (or to X) become [`LogicBit::X`].
+    pub fn resolve(&self, other: &PortValue) -> PortValue {
+        if self.is_known() && other.is_known() && self.bits == other.bits {
+            return self.clone();
+        }
+        let (z_lhs, z_rhs) = (self.z_mask().bits, other.z_mask().bits);
+        let either_z = z_lhs.or(&z_rhs);
+        // Where neither side is Z, any unknown bit or disagreement gives X
+        let conflict = self.unknown().or(other.unknown()).or(&self.bits.xor(&other.bits))
+            .and_not(&either_z);
+        // Where only one side is Z, the other side wins (and where both are, it stays Z)
+        let bits = conflict.or(&self.bits).and_not(&either_z)
+            .or(&z_lhs.and(&other.bits).and_not(&z_rhs))
+            .or(&z_rhs.and(&self.bits).and_not(&z_lhs));
+        let unknown = conflict
+            .or(&z_lhs.and(other.unknown()))
+            .or(&z_rhs.and(self.unknown()));
+        PortValue::from_planes(bits, unknown)
+    }
+
+    /// Get a value that could be either this one or `other`, for when it isn't known which of two
+    /// things happens (such as whether a write with an unknown enable takes place). A bit is only
+    /// known where both values have the same known `0` or `1` there; every other bit becomes
+    /// [`LogicBit::X`]. Unlike [`PortValue::resolve`], a [`LogicBit::Z`] bit never gives way to
+    /// the other value, as the two aren't both being driven at once.
+    pub fn either(&self, other: &PortValue) -> PortValue {
+        let unknown = self.unknown().or(other.unknown()).or(&self.bits.xor(&other.bits));
+        PortValue::from_planes(self.bits.or(&unknown), unknown)
+    }
+
+    /// Invert the lowest `width` bits of this value. Any bits above `width` are discarded.
+    pub fn not(&self, width: u32) -> PortValue {
+        let unknown = self.unknown().mask(width);
+        // Unknown bits are forced to 1, so that they come out as X
+        let bits = self.bits.xor(&Bits::all_ones(width)).mask(width).or(&unknown);
+        PortValue::from_planes(bits, unknown)
+    }
+
+    /// Work out the result of an arithmetic operation at the given width, which is entirely X if
+    /// any of the operands' bits are unknown.
+    fn arithmetic(&self, rhs: &PortValue, width: u32, op: impl Fn(&Bits, &Bits) -> Bits)
+        -> PortValue
+    {
+        if self.has_unknown_within(width) || rhs.has_unknown_within(width) {
+            return PortValue::x(width);
+        }
+        PortValue { bits: op(&self.bits.mask(width), &rhs.bits.mask(width)), unknown: None }
+    }
+
+    /// Add `rhs` to this value, wrapping around at `width` bits.
+    pub fn wrapping_add(&self, rhs: &PortValue, width: u32) -> PortValue {
+        self.arithmetic(rhs, width, |lhs, rhs| lhs.add(rhs).mask(width))
+    }
+
+    /// Subtract `rhs` from this value, wrapping around at `width` bits.
+    pub fn wrapping_sub(&self, rhs: &PortValue, width: u32) -> PortValue {
+        self.arithmetic(rhs, width, |lhs, rhs| {
+            // Two's complement: a - b = a + !b + 1
+            let not_rhs = rhs.xor(&Bits::all_ones(width));
+            lhs.add(&not_rhs).add(&Bits::Narrow(1)).mask(width)
+        })
+    }
+
+    /// Multiply this value by `rhs`, wrapping around at `width` bits.
+    pub fn wrapping_mul(&self, rhs: &PortValue, width: u32) -> PortValue {
+        self.arithmetic(rhs, width, |lhs, rhs| lhs.mul(rhs).mask(width))
+    }
+
+    /// Rotate the lowest `width` bits of this value left by `amount` bits. Rotating zero bits
+    /// gives 0.
+    pub fn rotate_left(&self, amount: u32, width: u32) -> PortValue {
+        self.map_planes(|plane| plane.rotate_left(amount, width))
+    }
+
+    /// Rotate the lowest `width` bits of this value right by `amount` bits. Rotating zero bits
+    /// gives 0.
+    pub fn rotate_right(&self, amount: u32, width: u32) -> PortValue {
+        match width {
+            0 => self.rotate_left(0, 0),
+            _ => self.rotate_left(width - amount % width, width),
+        }
+    }
+
+    /// Interpret the lowest `from_width` bits of this value as a two's complement number, and
+    /// sign extend it to `to_width` bits. An unknown sign bit is extended as it is.
+    pub fn sign_extend(&self, from_width: u32, to_width: u32) -> PortValue {
+        self.map_planes(|plane| plane.sign_extend(from_width, to_width))
+    }
+
+    /// Whether the lowest `width` bits of this value are known to be negative when interpreted as
+    /// a two's complement number, i.e. whether bit `width - 1` is a known `1`.
+    pub fn is_negative(&self, width: u32) -> bool {
+        width > 0 && self.bit(width - 1)
+    }
+
+    /// Compare the lowest `width` bits of this value and `rhs` as two's complement numbers.
+    ///
+    /// Like the [`Ord`] implementation, this gives a consistent answer for values with unknown bits
+    /// but not a meaningful one, so check [`PortValue::is_known`] first if that matters. Any two
+    /// values are equal when compared over zero bits.
+    pub fn signed_cmp(&self, rhs: &PortValue, width: u32) -> Ordering {
+        if width == 0 {
+            return Ordering::Equal;
+        }
+        match (self.bits.bit(width - 1), rhs.bits.bit(width - 1)) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            // Same sign, so the unsigned comparison gives the right answer
+            _ => self.mask(width).cmp(&rhs.mask(width)),
+        }
+    }
+}
+
+impl Default for PortValue {
+    fn default() -> Self {
+        PortValue::ZERO
+    }
+}
+
+impl From<bool> for PortValue {
+    fn from(value: bool) -> Self {
+        PortValue::from(value as u64)
+    }
+}
+
+impl From<u8> for PortValue {
+    fn from(value: u8) -> Self {
+        PortValue::from(value as u64)
+    }
+}
+
+impl From<u16> for PortValue {
+    fn from(value: u16) -> Self {
+        PortValue::from(value as u64)
+    }
+}
+
+impl From<u32> for PortValue {
+    fn from(value: u32) -> Self {
+        PortValue::from(value as u64)
+    }
+}
+
+impl From<u64> for PortValue {
+    fn from(value: u64) -> Self {
+        PortValue { bits: Bits::Narrow(value), unknown: None }
+    }
+}
+
+impl From<u128> for PortValue {
+    fn from(value: u128) -> Self {
+        PortValue { bits: Bits::from_u128(value), unknown: None }
+    }
+}
+
+impl BitAnd<&PortValue> for &PortValue {
+    type Output = PortValue;
+
+    fn bitand(self, rhs: &PortValue) -> PortValue {
+        if self.is_known() && rhs.is_known() {
+            return PortValue { bits: self.bits.and(&rhs.bits), unknown: None };
+        }
+        // A known 0 on either side wins; otherwise any unknown bit gives X
+        let unknown = self.unknown().or(rhs.unknown())
+            .and(&self.unknown().or(&self.bits))
+            .and(&rhs.unknown().or(&rhs.bits));
+        PortValue::from_planes(self.bits.and(&rhs.bits).or(&unknown), unknown)
+    }
+}
+
+impl BitOr<&PortValue> for &PortValue {
+    type Output = PortValue;
+
+    fn bitor(self, rhs: &PortValue) -> PortValue {
+        if self.is_known() && rhs.is_known() {
+            return PortValue { bits: self.bits.or(&rhs.bits), unknown: None };
+        }
+        // A known 1 on either side wins; otherwise any unknown bit gives X
+        let known_ones = self.bits.and_not(self.unknown()).or(&rhs.bits.and_not(rhs.unknown()));
+        let unknown = self.unknown().or(rhs.unknown()).and_not(&known_ones);
+        PortValue::from_planes(self.bits.or(&rhs.bits).or(&unknown), unknown)
+    }
+}
+
+impl BitXor<&PortValue> for &PortValue {
+    type Output = PortValue;
+
+    fn bitxor(self, rhs: &PortValue) -> PortValue {
+        if self.is_known() && rhs.is_known() {
+            return PortValue { bits: self.bits.xor(&rhs.bits), unknown: None };
+        }
+        let unknown = self.unknown().or(rhs.unknown());
+        PortValue::from_planes(self.bits.xor(&rhs.bits).or(&unknown), unknown)
+    }
+}
+
+impl Add<&PortValue> for &PortValue {
+    type Output = PortValue;
+
+    /// Add without wrapping. If any bit of either operand is unknown, every bit the result could
+    /// occupy is X.
+    fn add(self, rhs: &PortValue) -> PortValue {
+        let width = self.bit_length().max(rhs.bit_length()) + 1;
+        self.arithmetic(rhs, width, |lhs, rhs| lhs.add(rhs))
+    }
+}
+
+impl Mul<&PortValue> for &PortValue {
+    type Output = PortValue;
+
+    /// Multiply without wrapping. If any bit of either operand is unknown, every bit the result
+    /// could occupy is X.
+    fn mul(self, rhs: &PortValue) -> PortValue {
+        let width = self.bit_length().saturating_add(rhs.bit_length());
+        self.arithmetic(rhs, width, |lhs, rhs| lhs.mul(rhs))
+    }
+}
+
+/// Implement a binary operator on owned values in terms of its implementation on references.
+macro_rules! impl_owned_op {
+    ($trait:ident, $method:ident) => {
+        impl $trait for PortValue {
+            type Output = PortValue;
+
+            fn $method(self, rhs: PortValue) -> PortValue {
+                (&self).$method(&rhs)
+            }
+        }
+    };
+}
+
+impl_owned_op!(BitAnd, bitand);
+impl_owned_op!(BitOr, bitor);
+impl_owned_op!(BitXor, bitxor);
+impl_owned_op!(Add, add);
+impl_owned_op!(Mul, mul);
+
+impl Shl<u32> for &PortValue {
+    type Output = PortValue;
+
+    fn shl(self, amount: u32) -> PortValue {
+        self.map_planes(|plane| plane.shl(amount))
+    }
+}
+
+impl Shl<u32> for PortValue {
+    type Output = PortValue;
+
+    fn shl(self, amount: u32) -> PortValue {
+        &self << amount
+    }
+}
+
+impl Shr<u32> for &PortValue {
+    type Output = PortValue;
+
+    fn shr(self, amount: u32) -> PortValue {
+        self.map_planes(|plane| plane.shr(amount))
+    }
+}
+
+impl Shr<u32> for PortValue {
+    type Output = PortValue;
+
+    fn shr(self, amount: u32) -> PortValue {
+        &self >> amount
+    }
+}
+
+/// The error returned when a string can't be parsed as a [`PortValue`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseValueError {
+    /// The character that isn't a valid bit.
+    pub character: char,
+}
+
+impl fmt::Display for ParseValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` is not a valid bit: expected one of 0, 1, x or z", self.character)
+    }
+}
+
+impl Error for ParseValueError {}
+
+impl FromStr for PortValue {
+    type Err = ParseValueError;
+
+    /// Parse a value written in binary, highest bit first, where each bit is one of `0`, `1`, `x`
+    /// or `z`. Underscores may be used as separators, as in `1x0z_0000`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut states = Vec::with_capacity(s.len());
+        for character in s.chars().rev().filter(|character| *character != '_') {
+            states.push(match character {
+                '0' => LogicBit::Zero,
+                '1' => LogicBit::One,
+                'x' | 'X' => LogicBit::X,
+                'z' | 'Z' => LogicBit::Z,
+                _ => return Err(ParseValueError { character }),
+            });
+        }
+        Ok(PortValue::from_states(&states))
+    }
+}
+
+impl fmt::Display for PortValue {
+    /// Known values are written in decimal. Values with unknown bits are written in binary, in the
+    /// form accepted by [`PortValue::from_str`], prefixed with `0b`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_known() {
+            write!(f, "0b")?;
+            for index in (0..self.bit_length()).rev() {
+                let character = match self.state(index) {
+                    LogicBit::Zero => '0',
+                    LogicBit::One => '1',
+                    LogicBit::X => 'x',
+                    LogicBit::Z => 'z',
+                };
+                write!(f, "{character}")?;
+            }
+            return Ok(());
+        }
+        if let Bits::Narrow(value) = self.bits {
+            return write!(f, "{value}");
+        }
+        // Peel off 19 decimal digits at a time, the most that fit in a `u64`
+        const CHUNK: u64 = 10_000_000_000_000_000_000;
+        let mut chunks = Vec::new();
+        let mut rest = self.bits.clone();
+        while !rest.is_zero() {
+            let (quotient, remainder) = rest.div_rem_u64(CHUNK);
+            chunks.push(remainder);
+            rest = quotient;
+        }
+        write!(f, "{}", chunks.pop().unwrap_or(0))?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{chunk:019}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::LowerHex for PortValue {
+    /// Hex digits made up entirely of X or Z bits are written as `x` or `z`; digits only partly
+    /// unknown are written as `X` or `Z`, as an HDL simulator would.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        if self.is_known() {
+            let limbs = self.bits.limbs();
+            write!(f, "{:x}", limbs[limbs.len() - 1])?;
+            for limb in limbs[..limbs.len() - 1].iter().rev() {
+                write!(f, "{limb:016x}")?;
+            }
+            return Ok(());
+        }
+        let (x, z) = (self.x_mask(), self.z_mask());
+        for digit in (0..self.bit_length().div_ceil(4)).rev() {
+            let low = digit * 4;
+            match (x.slice(low, 4).low_u64(), z.slice(low, 4).low_u64()) {
+                (0, 0) => write!(f, "{:x}", self.slice(low, 4).low_u64())?,
+                (0xf, _) => write!(f, "x")?,
+                (_, 0xf) => write!(f, "z")?,
+                (0, _) => write!(f, "Z")?,
+                _ => write!(f, "X")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PortValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:#x}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use crate::device::value::{LogicBit, ParseValueError, PortValue};
+
+    /// Parse a four-state value, for brevity.
+    fn v(s: &str) -> PortValue {
+        s.parse().unwrap()
+    }
+
+    /// A value with a bit set beyond the first 64, for exercising the wide representation.
+    fn wide() -> PortValue {
+        PortValue::from_limbs(&[0x0123_4567_89ab_cdef, 0xfedc_ba98_7654_3210, 0x1])
+    }
+
+    #[test]
+    fn value_representation_is_normalised() {
+        assert_eq!(PortValue::from_limbs(&[5, 0, 0]), PortValue::from(5u32));
+        assert_eq!(PortValue::from_limbs(&[]), PortValue::ZERO);
+        assert_eq!(PortValue::from(5u128).to_u64(), Some(5));
+        assert_eq!(wide().to_u64(), None);
+        assert_eq!(wide().limbs().len(), 3);
+        assert_eq!(wide().bit_length(), 129);
+        assert_eq!(PortValue::ZERO.bit_length(), 0);
+    }
+
+    #[test]
+    fn value_can_be_masked_and_sliced() {
+        assert_eq!(PortValue::from(0xabu32).mask(4), PortValue::from(0xbu32));
+        assert_eq!(wide().mask(64), PortValue::from(0x0123_4567_89ab_cdefu64));
+        assert_eq!(wide().mask(200), wide());
+        assert_eq!(wide().slice(60, 8), PortValue::from(0x00u32));
+        assert_eq!(wide().slice(124, 8), PortValue::from(0x1fu32));
+        assert!(wide().bit(128));
+        assert!(wide().bit(127));
+        assert!(!wide().bit(129));
+        assert!(!wide().bit(1000));
+    }
+
+    #[test]
+    fn value_slices_can_be_replaced() {
+        let value = PortValue::from(0xffffu32);
+        assert_eq!(value.with_slice(4, 8, &PortValue::from(0x1a5u32)), PortValue::from(0xfa5fu32));
+        let value = PortValue::ZERO.with_slice(100, 4, &PortValue::from(0xfu32));
+        assert_eq!(value, PortValue::from(0xfu128 << 100));
+    }
+
+    #[test]
+    fn values_can_be_concatenated() {
+        let low = PortValue::from(0x12u32);
+        let high = PortValue::from(0x1234u32);
+        assert_eq!(PortValue::concat([(&low, 8), (&high, 8)]), PortValue::from(0x3412u32));
+        let expected = PortValue::from(0x1234u128 << 64 | 0x12);
+        assert_eq!(PortValue::concat([(&low, 64), (&high, 16)]), expected);
+    }
+
+    #[test]
+    fn value_arithmetic_wraps_at_width() {
+        let max = PortValue::all_ones(8);
+        let one = PortValue::from(1u32);
+        assert_eq!(max, PortValue::from(0xffu32));
+        assert_eq!(max.wrapping_add(&one, 8), PortValue::ZERO);
+        assert_eq!(max.wrapping_add(&one, 9), PortValue::from(0x100u32));
+        assert_eq!(PortValue::ZERO.wrapping_sub(&one, 8), max);
+        let (sixteen, seventeen) = (PortValue::from(16u32), PortValue::from(17u32));
+        assert_eq!(sixteen.wrapping_mul(&seventeen, 8), sixteen);
+        assert_eq!(PortValue::from(5u32).not(4), PortValue::from(0xau32));
+    }
+
+    #[test]
+    fn value_arithmetic_carries_into_wide_values() {
+        let max = PortValue::from(u64::MAX);
+        let one = PortValue::from(1u32);
+        assert_eq!(&max + &one, PortValue::from(1u128 << 64));
+        assert_eq!(&max * &max, PortValue::from(u64::MAX as u128 * u64::MAX as u128));
+        assert_eq!(PortValue::ZERO.wrapping_sub(&one, 128), PortValue::from(u128::MAX));
+        assert_eq!(&wide() + &PortValue::ZERO, wide());
+        assert_eq!(&wide() * &PortValue::from(2u32), &wide() << 1);
+    }
+
+    #[test]
+    fn value_can_be_shifted() {
+        let value = PortValue::from(0x8000_0000_0000_0001u64);
+        assert_eq!(&value << 4, PortValue::from(0x8000_0000_0000_0001u128 << 4));
+        assert_eq!(&(&value << 68) >> 68, value);
+        assert_eq!(&value >> 64, PortValue::ZERO);
+        assert_eq!(&wide() >> 128, PortValue::from(1u32));
+        assert_eq!(&wide() >> 64, PortValue::from_limbs(&[0xfedc_ba98_7654_3210, 0x1]));
+    }
+
+    #[test]
+    fn value_can_be_rotated() {
+        let value = PortValue::from(0b1001u32);
+        assert_eq!(value.rotate_left(1, 4), PortValue::from(0b0011u32));
+        assert_eq!(value.rotate_right(1, 4), PortValue::from(0b1100u32));
+        assert_eq!(value.rotate_left(4, 4), value);
+        let one = PortValue::from(1u32);
+        assert_eq!(one.rotate_right(1, 100), &one << 99);
+        assert_eq!(value.rotate_left(1, 0), PortValue::ZERO);
+        assert_eq!(value.rotate_right(1, 0), PortValue::ZERO);
+    }
+
+    #[test]
+    fn value_can_be_sign_extended() {
+        assert_eq!(PortValue::from(0x8u32).sign_extend(4, 8), PortValue::from(0xf8u32));
+        assert_eq!(PortValue::from(0x7u32).sign_extend(4, 8), PortValue::from(0x7u32));
+        assert_eq!(PortValue::from(0x80u32).sign_extend(8, 128), PortValue::from(u128::MAX << 7));
+    }
+
+    #[test]
+    fn values_are_ordered_numerically() {
+        assert!(PortValue::from(u64::MAX) < wide());
+        assert!(wide() < &wide() + &PortValue::from(1u32));
+        assert_eq!(PortValue::from(3u32).cmp(&PortValue::from(3u32)), Ordering::Equal);
+        let (minus_one, one) = (PortValue::from(0xffu32), PortValue::from(1u32));
+        assert_eq!(minus_one.signed_cmp(&one, 8), Ordering::Less);
+        assert_eq!(minus_one.signed_cmp(&one, 9), Ordering::Greater);
+        assert_eq!(minus_one.signed_cmp(&one, 0), Ordering::Equal);
+    }
+
+    #[test]
+    fn value_can_be_formatted() {
+        assert_eq!(PortValue::from(1234u32).to_string(), "1234");
+        assert_eq!(PortValue::from(u128::MAX).to_string(), u128::MAX.to_string());
+        assert_eq!(format!("{:x}", PortValue::from(u128::MAX)), format!("{:x}", u128::MAX));
+        assert_eq!(format!("{:#x}", PortValue::from(1u128 << 64)), "0x10000000000000000");
+        assert_eq!(format!("{:?}", PortValue::from(255u32)), "0xff");
+    }
+
+    #[test]
+    fn four_state_values_can_be_built_and_inspected() {
+        let value = v("1x0z");
+        assert!(!value.is_known());
+        assert_eq!(value.state(0), LogicBit::Z);
+        assert_eq!(value.state(1), LogicBit::Zero);
+        assert_eq!(value.state(2), LogicBit::X);
+        assert_eq!(value.state(3), LogicBit::One);
+        assert_eq!(value.x_mask(), PortValue::from(0b0100u32));
+        assert_eq!(value.z_mask(), PortValue::from(0b0001u32));
+        assert!(value.has_x() && value.has_z());
+        assert!(!value.is_zero());
+        assert_eq!(value.to_u64(), None);
+
+        // Known values are exactly the two-state ones
+        assert_eq!(v("0101"), PortValue::from(5u32));
+        assert!(v("0101").is_known());
+        assert_eq!(PortValue::x(3), v("xxx"));
+        assert_eq!(PortValue::z(3), v("zzz"));
+        assert_eq!("10a".parse::<PortValue>(), Err(ParseValueError { character: 'a' }));
+    }
+
+    #[test]
+    fn four_state_bitwise_operations_resolve_bit_by_bit() {
+        let lhs = v("0000_1111_xxxx_zzzz");
+        let rhs = v("01xz_01xz_01xz_01xz");
+        assert_eq!(&lhs & &rhs, v("0000_01xx_0xxx_0xxx"));
+        assert_eq!(&lhs | &rhs, v("01xx_1111_x1xx_x1xx"));
+        assert_eq!(&lhs ^ &rhs, v("01xx_10xx_xxxx_xxxx"));
+        assert_eq!(v("01xz").not(4), v("10xx"));
+    }
+
+    #[test]
+    fn four_state_arithmetic_is_unknown_if_any_input_bit_is() {
+        let one = PortValue::from(1u32);
+        assert_eq!(v("000x").wrapping_add(&one, 4), PortValue::x(4));
+        assert_eq!(one.wrapping_sub(&v("z000"), 4), PortValue::x(4));
+        assert_eq!(v("x000_0001").wrapping_mul(&one, 4), one);
+        assert_eq!(&v("1x") + &one, PortValue::x(3));
+    }
+
+    #[test]
+    fn four_state_values_keep_unknown_bits_when_moved_around() {
+        assert_eq!(v("1x0z").slice(1, 2), v("x0"));
+        assert_eq!(&v("1x0z") << 2, v("1x0z00"));
+        assert_eq!(v("1x0z").rotate_left(1, 4), v("x0z1"));
+        assert_eq!(v("x01").sign_extend(3, 6), v("xxxx01"));
+        assert_eq!(v("1111").with_slice(1, 2, &v("zx")), v("1zx1"));
+        let parts = [(&v("z"), 1), (&v("x0"), 2)];
+        assert_eq!(PortValue::concat(parts), v("x0z"));
+    }
+
+    #[test]
+    fn values_driven_together_resolve_like_a_tri_state_bus() {
+        assert_eq!(v("zzzz").resolve(&v("10xz")), v("10xz"));
+        assert_eq!(v("0101").resolve(&v("zzzz")), v("0101"));
+        assert_eq!(v("0101").resolve(&v("0011")), v("0xx1"));
+        assert_eq!(v("x1z0").resolve(&v("1110")), v("x110"));
+        assert_eq!(PortValue::from(6u32).resolve(&PortValue::from(6u32)), PortValue::from(6u32));
+    }
+
+    #[test]
+    fn either_of_two_values_only_keeps_bits_they_agree_on() {
+        assert_eq!(v("0101").either(&v("0011")), v("0xx1"));
+        assert_eq!(v("zzzz").either(&v("1111")), v("xxxx"));
+        assert_eq!(v("z10x").either(&v("z10x")), v("x10x"));
+        assert_eq!(PortValue::from(6u32).either(&PortValue::from(6u32)), PortValue::from(6u32));
+    }
+
+    #[test]
+    fn four_state_values_can_be_formatted() {
+        assert_eq!(v("1x0z").to_string(), "0b1x0z");
+        assert_eq!(format!("{:#x}", v("1010_xxxx_zzzz_x000")), "0xaxzX");
+        assert_eq!(format!("{:x}", v("z000")), "Z");
+    }
+}